@@ -1,95 +1,342 @@
-use crate::language::detect_language;
+// WASM entry points
+//
+// Thin wrappers that convert JsValue arguments and call the typed API in
+// index.rs and metrics.rs.
+//
+// Entry points that take events also take an optional trailing `validation`
+// argument: a `ValidationPolicy` object, or `undefined` to trust every event.
+// Those with a `timezone` argument accept "UTC", an offset such as "+09:00"
+// or an IANA name, like the CLI's `--timezone`, and default to UTC. Both are
+// optional so callers written before they existed keep working.
+
+use crate::analytics_event::algo_tag;
+use crate::evaluation::{evaluate_detector, parse_labeled_corpus};
+use crate::growth::compute_growth_accounting;
+use crate::heatmap::{compute_activity_heatmap, ActivityHeatmap, HeatmapOptions};
+use crate::index::{build_language_index, extend_language_index, LanguageIndexBuilder};
+use crate::language::{
+    default_detector, default_detector_id, detect_content_language, DEFAULT_MIN_CONTENT_CHARS,
+};
+use crate::metrics::{
+    compute_active_users, compute_active_users_by_language, compute_metrics, compute_stickiness,
+    GroupBy, MetricsBuilder,
+};
+use crate::nostr_utils::now;
 use crate::relay_client::NostrEvent;
+use crate::retention::{compute_retention, CohortPeriod, RetentionOptions};
+use crate::timezone::Zone;
 use crate::types::*;
 use crate::utils::console_log;
-use serde::Serialize;
-use std::collections::{HashMap, HashSet, VecDeque};
+use crate::validation::{validate_events, EventValidator, ValidationPolicy, ValidationReport};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
+fn parse_events(events_json: JsValue) -> Result<Vec<NostrEvent>, JsValue> {
+    serde_wasm_bindgen::from_value(events_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse events: {}", e)))
+}
+
+/// Events of `events_json` that pass `validation` (see `parse_validator`)
+fn parse_valid_events(
+    events_json: JsValue,
+    validation: Option<JsValue>,
+) -> Result<Vec<NostrEvent>, JsValue> {
+    let mut events = parse_events(events_json)?;
+    if let Some(mut validator) = parse_validator(validation)? {
+        validator.retain_valid(&mut events);
+    }
+    Ok(events)
+}
+
+/// Validator for a `ValidationPolicy` object, or none when `policy_json` is
+/// `undefined` or `null`
+fn parse_validator(policy_json: Option<JsValue>) -> Result<Option<EventValidator>, JsValue> {
+    let Some(policy_json) = policy_json.filter(|policy| !policy.is_null()) else {
+        return Ok(None);
+    };
+    let policy: ValidationPolicy = serde_wasm_bindgen::from_value(policy_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse validation policy: {}", e)))?;
+    Ok(Some(EventValidator::new(policy, now())))
+}
+
+/// `timezone` as a zone, UTC when `undefined`
+fn parse_zone(timezone: Option<String>) -> Result<Zone, JsValue> {
+    Ok(timezone.as_deref().unwrap_or("UTC").parse()?)
+}
+
+fn parse_user_languages(
+    user_languages_json: JsValue,
+    since: u64,
+    until: u64,
+) -> Result<LanguageIndex, JsValue> {
+    let user_languages: HashMap<PubkeyHex, HashMap<LanguageCode, f32>> =
+        serde_wasm_bindgen::from_value(user_languages_json)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse user languages: {}", e)))?;
+    Ok(LanguageIndex::from_language_map(
+        user_languages,
+        since,
+        until,
+    ))
+}
+
+/// Language index as exchanged with JS
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexOutput {
+    result: LanguageIndexResult,
+    user_languages: HashMap<PubkeyHex, HashMap<LanguageCode, f32>>,
+    #[serde(default)]
+    user_evidence: HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>>,
+    /// Evidence of users with no eligible language yet
+    #[serde(default)]
+    pending_evidence: HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>>,
+    /// Time range the index covers
+    #[serde(default)]
+    since: Timestamp,
+    #[serde(default)]
+    until: Timestamp,
+}
+
+impl IndexOutput {
+    fn new(index: &LanguageIndex) -> Self {
+        Self {
+            result: index.result(),
+            user_languages: index.to_language_map(),
+            user_evidence: index.to_evidence_map(),
+            pending_evidence: index.to_pending_map(),
+            since: index.since,
+            until: index.until,
+        }
+    }
+
+    fn into_index(self) -> LanguageIndex {
+        LanguageIndex::restore(
+            &self.result,
+            self.user_languages,
+            self.user_evidence,
+            self.pending_evidence,
+            self.since,
+            self.until,
+        )
+    }
+}
+
+/// Index configuration for the positional WASM arguments; `min_posts` and
+/// `min_share` left `undefined` take the default eligibility rule
+fn index_config(
+    conf_thresh: f32,
+    max_langs_per_user: u8,
+    min_posts: Option<u32>,
+    min_share: Option<f32>,
+) -> IndexConfig {
+    let defaults = EligibilityRule::default();
+    IndexConfig {
+        conf_thresh,
+        max_langs_per_user,
+        max_events: None,
+        eligibility: EligibilityRule {
+            min_posts: min_posts.unwrap_or(defaults.min_posts),
+            min_share: min_share.unwrap_or(defaults.min_share),
+        },
+        ..Default::default()
+    }
+}
+
+fn metrics_options(
+    target_languages: Vec<String>,
+    since: u64,
+    until: u64,
+    window_days: u16,
+    zone: Zone,
+) -> MetricsOptions {
+    MetricsOptions {
+        since,
+        until,
+        languages: target_languages,
+        granularity: Granularity::Day,
+        window_days,
+        zone,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    }
+}
+
 /// Process events for language index building
 #[wasm_bindgen]
 pub fn process_events_for_language_index(
     events_json: JsValue,
     conf_thresh: f32,
     max_langs_per_user: u8,
+    min_posts: Option<u32>,
+    min_share: Option<f32>,
+    validation: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
-    let events: Vec<NostrEvent> = serde_wasm_bindgen::from_value(events_json)
-        .map_err(|e| JsValue::from_str(&format!("Failed to parse events: {}", e)))?;
+    let events = parse_valid_events(events_json, validation)?;
 
-    console_log!("Processing {} events for language index", events.len());
+    let config = index_config(conf_thresh, max_langs_per_user, min_posts, min_share);
+    let index = build_language_index(&events, &config);
 
-    let mut user_languages: HashMap<PubkeyHex, UserLanguages> = HashMap::new();
-    let mut events_with_language = 0u32;
+    serde_wasm_bindgen::to_value(&IndexOutput::new(&index))
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
 
-    for event in events.iter() {
-        let content = event.get_content();
-        if content.is_empty() {
-            continue;
-        }
+/// Extend the output of `process_events_for_language_index` (or of a previous
+/// call) with the events of `since..=until`. Events the index already covers
+/// are skipped. A gap between the index and `since` is an error unless
+/// `allow_gaps`, in which case it is listed in `result.gaps`.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn extend_language_index_with_events(
+    index_json: JsValue,
+    events_json: JsValue,
+    since: u64,
+    until: u64,
+    conf_thresh: f32,
+    max_langs_per_user: u8,
+    min_posts: Option<u32>,
+    min_share: Option<f32>,
+    allow_gaps: bool,
+    validation: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let previous: IndexOutput = serde_wasm_bindgen::from_value(index_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse index: {}", e)))?;
+    let events = parse_valid_events(events_json, validation)?;
+
+    let config = index_config(conf_thresh, max_langs_per_user, min_posts, min_share);
+    let gap_policy = if allow_gaps {
+        GapPolicy::Warn
+    } else {
+        GapPolicy::Refuse
+    };
+    let mut index = previous.into_index();
+    if let Some((from, to)) =
+        extend_language_index(&mut index, &events, since, until, &config, gap_policy)?
+    {
+        console_log!("Warning: extended index has a gap {}..={}", from, to);
+    }
 
-        // Detect language
-        match detect_language(content) {
-            Ok(Some((lang, confidence))) => {
-                if confidence >= conf_thresh {
-                    let pubkey = event.get_pubkey().to_string();
-                    let user_langs = user_languages.entry(pubkey).or_default();
-
-                    user_langs.add_language(lang, confidence, max_langs_per_user);
-                    events_with_language += 1;
-                }
-            }
-            Ok(None) => {}
-            Err(e) => {
-                console_log!("Language detection error: {}", e);
-            }
+    serde_wasm_bindgen::to_value(&IndexOutput::new(&index))
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Language index built from events pushed in batches, so a fetcher can hand
+/// over one page of events at a time and drop it instead of passing millions
+/// of events in one call. `finish()` returns the same shape as
+/// `process_events_for_language_index`.
+#[wasm_bindgen]
+pub struct IndexBuilder {
+    builder: LanguageIndexBuilder<'static>,
+}
+
+#[wasm_bindgen]
+impl IndexBuilder {
+    /// `validation` is a `ValidationPolicy` object, or `undefined` to trust
+    /// every event
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        conf_thresh: f32,
+        max_langs_per_user: u8,
+        min_posts: Option<u32>,
+        min_share: Option<f32>,
+        validation: Option<JsValue>,
+    ) -> Result<IndexBuilder, JsValue> {
+        let config = index_config(conf_thresh, max_langs_per_user, min_posts, min_share);
+        let mut builder = LanguageIndexBuilder::new(config);
+        if let Some(validator) = parse_validator(validation)? {
+            builder = builder.with_validation(validator);
         }
+        Ok(IndexBuilder { builder })
     }
 
-    // Calculate stats by language
-    let mut by_lang: HashMap<LanguageCode, u32> = HashMap::new();
-    for user_langs in user_languages.values() {
-        for lang in user_langs.languages.keys() {
-            *by_lang.entry(lang.clone()).or_insert(0) += 1;
-        }
+    /// Detect one batch of events; the batch can be dropped afterwards
+    pub fn push_batch(&mut self, events_json: JsValue) -> Result<(), JsValue> {
+        let events = parse_events(events_json)?;
+        self.builder.push_batch(&events);
+        Ok(())
     }
 
-    let result = LanguageIndexResult {
-        users: user_languages.len() as u32,
-        by_lang,
-        events_processed: events.len() as u32,
-        events_with_language,
-    };
+    /// `{ batches, events_processed, events_rejected, users }` so far
+    pub fn progress(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.builder.progress())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
 
-    console_log!(
-        "Language index: {} users, {} events with language",
-        result.users,
-        result.events_with_language
-    );
-
-    // Convert user_languages to a simpler format for JS
-    let user_languages_map: HashMap<String, HashMap<String, f32>> = user_languages
-        .into_iter()
-        .map(|(k, v)| (k, v.languages))
-        .collect();
-
-    // Create output structure
-    #[derive(Serialize)]
-    struct Output {
-        result: LanguageIndexResult,
-        #[serde(rename = "userLanguages")]
-        user_languages: HashMap<String, HashMap<String, f32>>,
+    /// `ValidationReport` so far (`undefined` without validation)
+    pub fn validation_report(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.builder.validation_report())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
-    let output = Output {
-        result,
-        user_languages: user_languages_map,
-    };
+    /// Build the index; the builder cannot be used afterwards
+    pub fn finish(self) -> Result<JsValue, JsValue> {
+        let index = self.builder.finish();
+
+        serde_wasm_bindgen::to_value(&IndexOutput::new(&index))
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+/// Events that pass `policy_json` (a `ValidationPolicy` object; fields left
+/// out take their defaults) as `{ events, report }`, with the rejections by
+/// reason in `report`
+#[wasm_bindgen]
+pub fn validate_event_batch(
+    events_json: JsValue,
+    policy_json: JsValue,
+) -> Result<JsValue, JsValue> {
+    let mut events = parse_events(events_json)?;
+    let policy: ValidationPolicy = serde_wasm_bindgen::from_value(policy_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse validation policy: {}", e)))?;
+
+    let report = validate_events(&mut events, &policy, now());
+
+    serde_wasm_bindgen::to_value(&ValidatedEvents { events, report })
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[derive(Serialize)]
+struct ValidatedEvents {
+    events: Vec<NostrEvent>,
+    report: ValidationReport,
+}
+
+/// Encode the output of `process_events_for_language_index` as a compact
+/// binary blob (a `Uint8Array`) for storage. Without `include_evidence` the
+/// blob only supports lifetime-eligibility metrics, not extending.
+#[wasm_bindgen]
+pub fn encode_language_index(
+    index_json: JsValue,
+    include_evidence: bool,
+) -> Result<Vec<u8>, JsValue> {
+    let output: IndexOutput = serde_wasm_bindgen::from_value(index_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse index: {}", e)))?;
+
+    Ok(output.into_index().to_bytes(include_evidence)?)
+}
 
-    serde_wasm_bindgen::to_value(&output).map_err(|e| JsValue::from_str(&e.to_string()))
+/// Decode a blob from `encode_language_index` back into the shape returned by
+/// `process_events_for_language_index`
+#[wasm_bindgen]
+pub fn decode_language_index(bytes: &[u8]) -> Result<JsValue, JsValue> {
+    let index = LanguageIndex::from_bytes(bytes)?;
+
+    serde_wasm_bindgen::to_value(&IndexOutput::new(&index))
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Detect the language of one note's content with the default detector,
+/// including preprocessing and CJK script decisions
+#[wasm_bindgen]
+pub fn detect_content(content: String) -> Result<JsValue, JsValue> {
+    let result = detect_content_language(&default_detector(), &content, DEFAULT_MIN_CONTENT_CHARS)?;
+
+    serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
 }
 
 /// Compute metrics from events and language index
 #[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
 pub fn compute_metrics_from_events(
     events_json: JsValue,
     user_languages_json: JsValue,
@@ -97,201 +344,352 @@ pub fn compute_metrics_from_events(
     since: u64,
     until: u64,
     window_days: u16,
+    timezone: Option<String>,
+    validation: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
-    let events: Vec<NostrEvent> = serde_wasm_bindgen::from_value(events_json)
-        .map_err(|e| JsValue::from_str(&format!("Failed to parse events: {}", e)))?;
+    let zone = parse_zone(timezone)?;
+    let events = parse_valid_events(events_json, validation)?;
+    let index = parse_user_languages(user_languages_json, since, until)?;
+    let options = metrics_options(target_languages, since, until, window_days, zone);
 
-    let user_languages: HashMap<PubkeyHex, HashMap<LanguageCode, f32>> =
-        serde_wasm_bindgen::from_value(user_languages_json)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse user languages: {}", e)))?;
+    let results = compute_active_users(&events, &index, &options);
 
-    console_log!(
-        "Computing metrics: {} events, {} users, window {} days",
-        events.len(),
-        user_languages.len(),
-        window_days
-    );
-
-    // Build eligible users set (users who have posted in target languages)
-    let mut eligible_users = HashSet::new();
-    for (pubkey, langs) in user_languages.iter() {
-        for target_lang in &target_languages {
-            if langs.contains_key(target_lang) {
-                eligible_users.insert(pubkey.clone());
-                break;
-            }
-        }
-    }
+    serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+}
 
-    console_log!("Eligible users: {}", eligible_users.len());
+/// Compute metrics from events and language index, separated by language
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn compute_metrics_by_language(
+    events_json: JsValue,
+    user_languages_json: JsValue,
+    target_languages: Vec<String>,
+    since: u64,
+    until: u64,
+    window_days: u16,
+    timezone: Option<String>,
+    validation: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let zone = parse_zone(timezone)?;
+    let events = parse_valid_events(events_json, validation)?;
+    let index = parse_user_languages(user_languages_json, since, until)?;
+    let options = metrics_options(target_languages, since, until, window_days, zone);
 
-    // Collect activity by day
-    let mut activity_by_day: HashMap<EpochDay, HashSet<PubkeyHex>> = HashMap::new();
+    let results_by_lang = compute_active_users_by_language(&events, &index, &options);
 
-    for event in events.iter() {
-        let pubkey = event.get_pubkey().to_string();
+    serde_wasm_bindgen::to_value(&results_by_lang).map_err(|e| JsValue::from_str(&e.to_string()))
+}
 
-        // Only count eligible users
-        if !eligible_users.contains(&pubkey) {
-            continue;
+/// Compute metrics grouped by `group_by` ("all", "language", "kind" or "client")
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn compute_metrics_grouped(
+    events_json: JsValue,
+    user_languages_json: JsValue,
+    target_languages: Vec<String>,
+    since: u64,
+    until: u64,
+    window_days: u16,
+    group_by: String,
+    timezone: Option<String>,
+    validation: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let group_by: GroupBy = group_by.parse()?;
+    let zone = parse_zone(timezone)?;
+    let events = parse_valid_events(events_json, validation)?;
+    let index = parse_user_languages(user_languages_json, since, until)?;
+    let options = metrics_options(target_languages, since, until, window_days, zone);
+
+    let results = compute_metrics(&events, &index, &options, group_by);
+
+    serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Compute metrics with a full `MetricsOptions` object (granularity, time zone
+/// such as `"Asia/Tokyo"` or `"+09:00"`, ...), grouped by `group_by`
+#[wasm_bindgen]
+pub fn compute_metrics_with_options(
+    events_json: JsValue,
+    user_languages_json: JsValue,
+    options_json: JsValue,
+    group_by: String,
+    validation: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let group_by: GroupBy = group_by.parse()?;
+    let events = parse_valid_events(events_json, validation)?;
+    let options: MetricsOptions = serde_wasm_bindgen::from_value(options_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse options: {}", e)))?;
+    let index = parse_user_languages(user_languages_json, options.since, options.until)?;
+
+    let results = compute_metrics(&events, &index, &options, group_by);
+
+    serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Score the default detector on a labeled JSONL corpus
+/// (`{"content": ..., "expected_lang": ...}` per line) at each of `thresholds`
+#[wasm_bindgen]
+pub fn evaluate_language_detection(
+    corpus_jsonl: String,
+    thresholds: Vec<f32>,
+) -> Result<JsValue, JsValue> {
+    let samples = parse_labeled_corpus(&corpus_jsonl)?;
+    let evaluation = evaluate_detector(
+        &default_detector(),
+        &samples,
+        &thresholds,
+        DEFAULT_MIN_CONTENT_CHARS,
+    )?;
+
+    serde_wasm_bindgen::to_value(&evaluation).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Like [`compute_metrics_with_options`], with the `userEvidence` returned by
+/// `process_events_for_language_index` so `eligibility: "period-90d"` can
+/// check when each language was last used
+#[wasm_bindgen]
+pub fn compute_metrics_with_evidence(
+    events_json: JsValue,
+    user_languages_json: JsValue,
+    user_evidence_json: JsValue,
+    options_json: JsValue,
+    group_by: String,
+    validation: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let group_by: GroupBy = group_by.parse()?;
+    let events = parse_valid_events(events_json, validation)?;
+    let options: MetricsOptions = serde_wasm_bindgen::from_value(options_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse options: {}", e)))?;
+    let user_evidence: HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>> =
+        serde_wasm_bindgen::from_value(user_evidence_json)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse user evidence: {}", e)))?;
+    let index = parse_user_languages(user_languages_json, options.since, options.until)?
+        .with_evidence(user_evidence);
+
+    let results = compute_metrics(&events, &index, &options, group_by);
+
+    serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Metrics computed from events pushed in batches, like `IndexBuilder`.
+/// Takes the output of `process_events_for_language_index` (or
+/// `IndexBuilder.finish()`), a full `MetricsOptions` object and `group_by`;
+/// `finish()` returns the same series as `compute_metrics_with_evidence`.
+#[wasm_bindgen]
+pub struct MetricsStream {
+    builder: MetricsBuilder,
+}
+
+#[wasm_bindgen]
+impl MetricsStream {
+    /// `validation` is a `ValidationPolicy` object, or `undefined` to trust
+    /// every event
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        index_json: JsValue,
+        options_json: JsValue,
+        group_by: String,
+        validation: Option<JsValue>,
+    ) -> Result<MetricsStream, JsValue> {
+        let group_by: GroupBy = group_by.parse()?;
+        let index: IndexOutput = serde_wasm_bindgen::from_value(index_json)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse index: {}", e)))?;
+        let options: MetricsOptions = serde_wasm_bindgen::from_value(options_json)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse options: {}", e)))?;
+
+        let mut builder = MetricsBuilder::new(&index.into_index(), options, group_by);
+        if let Some(validator) = parse_validator(validation)? {
+            builder = builder.with_validation(validator);
         }
+        Ok(MetricsStream { builder })
+    }
 
-        let epoch_day = timestamp_to_epoch_day(event.get_created_at());
-        activity_by_day.entry(epoch_day).or_default().insert(pubkey);
+    /// Record one batch of events; the batch can be dropped afterwards
+    pub fn push_batch(&mut self, events_json: JsValue) -> Result<(), JsValue> {
+        let events = parse_events(events_json)?;
+        self.builder.push_batch(&events);
+        Ok(())
     }
 
-    // Compute sliding window metrics
-    let start_day = timestamp_to_epoch_day(since);
-    let end_day = timestamp_to_epoch_day(until);
-    let window_days = window_days as u32;
-
-    let mut results = Vec::new();
-    let mut window: VecDeque<(EpochDay, HashSet<PubkeyHex>)> = VecDeque::new();
-    let mut active_users: HashMap<PubkeyHex, u32> = HashMap::new();
-
-    for day in start_day..=end_day {
-        // Add current day to window
-        if let Some(users) = activity_by_day.get(&day) {
-            window.push_back((day, users.clone()));
-            for user in users {
-                *active_users.entry(user.clone()).or_insert(0) += 1;
-            }
-        }
+    /// `{ batches, events_processed, events_rejected, users }` so far
+    pub fn progress(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.builder.progress())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
 
-        // Remove days outside window
-        while let Some((old_day, _)) = window.front() {
-            if day >= *old_day + window_days {
-                let (_, old_users) = window.pop_front().unwrap();
-                for user in old_users {
-                    if let Some(count) = active_users.get_mut(&user) {
-                        *count -= 1;
-                        if *count == 0 {
-                            active_users.remove(&user);
-                        }
-                    }
-                }
-            } else {
-                break;
-            }
-        }
+    /// `ValidationReport` so far (`undefined` without validation)
+    pub fn validation_report(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.builder.validation_report())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Series of every group; the stream cannot be used afterwards
+    pub fn finish(self) -> Result<JsValue, JsValue> {
+        let results = self.builder.finish();
 
-        // Count unique active users in window
-        let count = active_users.len() as u32;
-        results.push(MetricDataPoint {
-            epoch_day: day,
-            count,
-        });
+        serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
     }
+}
 
-    console_log!("Computed {} data points", results.len());
+/// Distinct users per calendar bucket and language.
+/// `granularity` is "day", "week" (ISO week) or "month" (calendar month).
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn compute_metrics_calendar(
+    events_json: JsValue,
+    user_languages_json: JsValue,
+    target_languages: Vec<String>,
+    since: u64,
+    until: u64,
+    granularity: String,
+    timezone: Option<String>,
+    validation: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let granularity: Granularity = granularity.parse()?;
+    let zone = parse_zone(timezone)?;
+    let events = parse_valid_events(events_json, validation)?;
+    let index = parse_user_languages(user_languages_json, since, until)?;
+    let options = MetricsOptions {
+        granularity,
+        ..metrics_options(target_languages, since, until, 1, zone)
+    };
 
-    serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+    let results_by_lang = compute_active_users_by_language(&events, &index, &options);
+
+    serde_wasm_bindgen::to_value(&results_by_lang).map_err(|e| JsValue::from_str(&e.to_string()))
 }
 
-/// Compute metrics from events and language index, separated by language
+/// Compute several window sizes (e.g. `[1, 7, 30]`) per language in one call,
+/// with ratio series such as DAU/MAU
 #[wasm_bindgen]
-pub fn compute_metrics_by_language(
+#[allow(clippy::too_many_arguments)]
+pub fn compute_stickiness_by_language(
     events_json: JsValue,
     user_languages_json: JsValue,
     target_languages: Vec<String>,
     since: u64,
     until: u64,
-    window_days: u16,
+    windows: Vec<u16>,
+    timezone: Option<String>,
+    validation: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
-    let events: Vec<NostrEvent> = serde_wasm_bindgen::from_value(events_json)
-        .map_err(|e| JsValue::from_str(&format!("Failed to parse events: {}", e)))?;
+    let zone = parse_zone(timezone)?;
+    let events = parse_valid_events(events_json, validation)?;
+    let index = parse_user_languages(user_languages_json, since, until)?;
+    let options = metrics_options(target_languages, since, until, 1, zone);
 
-    let user_languages: HashMap<PubkeyHex, HashMap<LanguageCode, f32>> =
-        serde_wasm_bindgen::from_value(user_languages_json)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse user languages: {}", e)))?;
+    let results = compute_stickiness(&events, &index, &options, GroupBy::Language, &windows);
 
-    console_log!(
-        "Computing metrics by language: {} events, {} users, {} languages, window {} days",
-        events.len(),
-        user_languages.len(),
-        target_languages.len(),
-        window_days
-    );
-
-    let start_day = timestamp_to_epoch_day(since);
-    let end_day = timestamp_to_epoch_day(until);
-    let window_days = window_days as u32;
-
-    // Build results for each language
-    let mut results_by_lang: HashMap<LanguageCode, Vec<MetricDataPoint>> = HashMap::new();
-
-    for target_lang in &target_languages {
-        // Build eligible users for this language
-        let mut eligible_users = HashSet::new();
-        for (pubkey, langs) in user_languages.iter() {
-            if langs.contains_key(target_lang) {
-                eligible_users.insert(pubkey.clone());
-            }
-        }
+    serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+}
 
-        console_log!(
-            "Language {}: {} eligible users",
-            target_lang,
-            eligible_users.len()
-        );
+/// New / retained / resurrected / churned users per day and language.
+/// A user is resurrected after at least `inactive_days` inactive days.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn compute_growth_by_language(
+    events_json: JsValue,
+    user_languages_json: JsValue,
+    target_languages: Vec<String>,
+    since: u64,
+    until: u64,
+    inactive_days: u16,
+    timezone: Option<String>,
+    validation: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let zone = parse_zone(timezone)?;
+    let events = parse_valid_events(events_json, validation)?;
+    let index = parse_user_languages(user_languages_json, since, until)?;
+    let options = metrics_options(target_languages, since, until, 1, zone);
 
-        // Collect activity by day for this language
-        let mut activity_by_day: HashMap<EpochDay, HashSet<PubkeyHex>> = HashMap::new();
+    let results = compute_growth_accounting(&events, &index, &options, inactive_days);
 
-        for event in events.iter() {
-            let pubkey = event.get_pubkey().to_string();
+    serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+}
 
-            if !eligible_users.contains(&pubkey) {
-                continue;
-            }
+/// Compute cohort retention per language.
+/// `cohort_period` is "day" or "week"; empty `offsets` means D1/D7/D30.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn compute_retention_by_language(
+    events_json: JsValue,
+    user_languages_json: JsValue,
+    target_languages: Vec<String>,
+    since: u64,
+    until: u64,
+    cohort_period: String,
+    offsets: Vec<u32>,
+    timezone: Option<String>,
+    validation: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let cohort_period: CohortPeriod = cohort_period.parse()?;
+    let zone = parse_zone(timezone)?;
+    let events = parse_valid_events(events_json, validation)?;
+    let index = parse_user_languages(user_languages_json, since, until)?;
+    let options = RetentionOptions {
+        since,
+        until,
+        languages: target_languages,
+        cohort_period,
+        offsets,
+        zone,
+    };
 
-            let epoch_day = timestamp_to_epoch_day(event.get_created_at());
-            activity_by_day.entry(epoch_day).or_default().insert(pubkey);
-        }
+    let results = compute_retention(&events, &index, &options);
 
-        // Compute sliding window metrics for this language
-        let mut results = Vec::new();
-        let mut window: VecDeque<(EpochDay, HashSet<PubkeyHex>)> = VecDeque::new();
-        let mut active_users: HashMap<PubkeyHex, u32> = HashMap::new();
-
-        for day in start_day..=end_day {
-            // Add current day to window
-            if let Some(users) = activity_by_day.get(&day) {
-                window.push_back((day, users.clone()));
-                for user in users {
-                    *active_users.entry(user.clone()).or_insert(0) += 1;
-                }
-            }
-
-            // Remove days outside window
-            while let Some((old_day, _)) = window.front() {
-                if day >= *old_day + window_days {
-                    let (_, old_users) = window.pop_front().unwrap();
-                    for user in old_users {
-                        if let Some(count) = active_users.get_mut(&user) {
-                            *count -= 1;
-                            if *count == 0 {
-                                active_users.remove(&user);
-                            }
-                        }
-                    }
-                } else {
-                    break;
-                }
-            }
-
-            // Count unique active users in window
-            let count = active_users.len() as u32;
-            results.push(MetricDataPoint {
-                epoch_day: day,
-                count,
-            });
-        }
+    serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+}
 
-        results_by_lang.insert(target_lang.clone(), results);
-    }
+/// Weekday × hour heatmap of distinct users and events per language.
+/// `timezone` is "UTC", an offset such as "+09:00" or an IANA name.
+#[wasm_bindgen]
+pub fn compute_activity_heatmap_by_language(
+    events_json: JsValue,
+    user_languages_json: JsValue,
+    target_languages: Vec<String>,
+    since: u64,
+    until: u64,
+    timezone: Option<String>,
+    validation: Option<JsValue>,
+) -> Result<Vec<ActivityHeatmap>, JsValue> {
+    let zone = parse_zone(timezone)?;
+    let events = parse_valid_events(events_json, validation)?;
+    let index = parse_user_languages(user_languages_json, since, until)?;
+    let options = HeatmapOptions {
+        since,
+        until,
+        languages: target_languages,
+        zone,
+        eligibility: EligibilityScope::Lifetime,
+    };
 
-    console_log!("Computed metrics for {} languages", results_by_lang.len());
+    Ok(compute_activity_heatmap(&events, &index, &options))
+}
 
-    serde_wasm_bindgen::to_value(&results_by_lang).map_err(|e| JsValue::from_str(&e.to_string()))
+/// kind 30080 `algo` tag for the index's `detector` id and the metrics'
+/// `timezone`, `eligibility` (`lifetime` or `period-<N>d`) and `counting`
+/// (`exact` or `hll-p<N>`), the same string the CLI publishes. Arguments left
+/// `undefined` take the defaults.
+#[wasm_bindgen]
+pub fn analytics_algo_tag(
+    detector: Option<String>,
+    timezone: Option<String>,
+    eligibility: Option<String>,
+    counting: Option<String>,
+) -> Result<String, JsValue> {
+    let zone = parse_zone(timezone)?;
+    let eligibility = match eligibility {
+        Some(eligibility) => eligibility.parse()?,
+        None => EligibilityScope::default(),
+    };
+    let counting = match counting {
+        Some(counting) => counting.parse()?,
+        None => CountingMode::default(),
+    };
+    Ok(algo_tag(
+        &detector.unwrap_or_else(default_detector_id),
+        zone,
+        eligibility,
+        counting,
+    ))
 }
@@ -0,0 +1,251 @@
+// kind 30080 analytics result event (docs/custom-kind-spec.md)
+//
+// Mirrors `createAnalyticsEvent` in frontend/src/services/nostr.ts so results
+// published from native jobs replace the same d-tags as the web UI.
+
+use crate::error::AnalyticsError;
+use crate::language::default_detector_id;
+use crate::metrics::RatioMetrics;
+use crate::relay_client::NostrEvent;
+use crate::timezone::Zone;
+use crate::types::*;
+use nostr::{EventBuilder, Keys, Kind, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Event kind for language-based active user analytics
+pub const ANALYTICS_EVENT_KIND: u16 = 30080;
+
+/// `algo` tag of the default configuration (whatlang with CJK script stage, UTC)
+pub const ALGO_TAG: &str = "lang=whatlang@0.16+cjk-script@1;act=all-kinds;elig=lifetime";
+
+/// `algo` tag value for a detector id (`name@version`) and eligibility scope
+/// (`lifetime` or `period-<N>d`); day boundaries other than UTC are recorded
+/// as `tz=<zone>` and approximate counts as `count=hll-p<N>`
+pub fn algo_tag(
+    detector: &str,
+    zone: Zone,
+    eligibility: EligibilityScope,
+    counting: CountingMode,
+) -> String {
+    let mut algo = format!("lang={};act=all-kinds;elig={}", detector, eligibility);
+    if !zone.is_utc() {
+        algo.push_str(&format!(";tz={}", zone));
+    }
+    if counting != CountingMode::Exact {
+        algo.push_str(&format!(";count={}", counting));
+    }
+    algo
+}
+
+/// Generator recorded in the `app` tag
+pub const APP_TAG: &str = concat!("nostr-analytics/", env!("CARGO_PKG_VERSION"));
+
+/// Content schema version
+pub const ANALYTICS_EVENT_VERSION: u32 = 1;
+
+/// Metric name for a window size (`dau`, `wau`, `mau`, `yau`)
+pub fn metric_name(window_days: u16) -> String {
+    match window_days {
+        1 => "dau".to_string(),
+        7 => "wau".to_string(),
+        30 => "mau".to_string(),
+        365 => "yau".to_string(),
+        n => format!("au{}", n),
+    }
+}
+
+/// Metric name for a series: calendar buckets are `wau` (week) and `mau`
+/// (month), sliding windows follow [`metric_name`]
+pub fn granular_metric_name(granularity: Granularity, window_days: u16) -> String {
+    match granularity {
+        Granularity::Day => metric_name(window_days),
+        Granularity::Week => "wau".to_string(),
+        Granularity::Month => "mau".to_string(),
+    }
+}
+
+/// Metric name for a ratio of two window sizes (`dau_mau`, `dau_wau`, ...)
+pub fn ratio_metric_name(numerator_window_days: u16, denominator_window_days: u16) -> String {
+    format!(
+        "{}_{}",
+        metric_name(numerator_window_days),
+        metric_name(denominator_window_days)
+    )
+}
+
+/// One language's metric series to be published as kind 30080
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AnalyticsEvent {
+    pub metric: String,
+    pub language: LanguageCode,
+    pub relays: Vec<String>,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    #[serde(default)]
+    pub granularity: Granularity,
+    /// Sliding window size; 1 for calendar week/month buckets
+    pub window_days: u16,
+    /// Time zone of the `counts` day boundaries
+    #[serde(default)]
+    pub zone: Zone,
+    /// Language detector id of the index (`name@version`)
+    #[serde(default = "default_detector_id")]
+    pub detector: String,
+    /// Eligibility scope of the counts
+    #[serde(default)]
+    pub eligibility: EligibilityScope,
+    /// Exact or HyperLogLog counts
+    #[serde(default)]
+    pub counting: CountingMode,
+    pub counts: Vec<MetricDataPoint>,
+    pub eligible_user_count: u32,
+
+    /// Ratio series for ratio metrics such as `dau_mau` (empty otherwise)
+    #[serde(default)]
+    pub ratios: Vec<RatioDataPoint>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Timeframe {
+    start: Timestamp,
+    end: Timestamp,
+    granularity: &'static str,
+    window_days: u16,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Content<'a> {
+    version: u32,
+    metric: &'a str,
+    language: &'a str,
+    relays: &'a [String],
+    timeframe: Timeframe,
+    counts: Vec<(EpochDay, u32)>,
+    /// Error bounds of estimated counts
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    count_errors: Vec<(EpochDay, u32)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ratios: Vec<(EpochDay, Option<f32>)>,
+    eligible_user_count: u32,
+    notes: String,
+}
+
+impl AnalyticsEvent {
+    /// Ratio event for one group of `ratio` (e.g. DAU/MAU of a language).
+    /// `window_days` is the denominator window; returns `None` if `language`
+    /// has no series.
+    pub fn from_ratio(
+        ratio: &RatioMetrics,
+        language: &str,
+        relays: Vec<String>,
+        eligible_user_count: u32,
+        detector: &str,
+    ) -> Option<Self> {
+        let points = ratio.series.get(language)?;
+        Some(Self {
+            metric: ratio_metric_name(ratio.numerator.window_days, ratio.denominator.window_days),
+            language: language.to_string(),
+            relays,
+            start: ratio.numerator.since,
+            end: ratio.numerator.until,
+            granularity: ratio.numerator.granularity,
+            zone: ratio.numerator.zone,
+            detector: detector.to_string(),
+            eligibility: ratio.numerator.eligibility,
+            counting: ratio.numerator.counting,
+            window_days: ratio.denominator.window_days,
+            counts: Vec::new(),
+            eligible_user_count,
+            ratios: points.clone(),
+        })
+    }
+
+    /// Parameterized replaceable identifier
+    /// `<metric>-<lang>-<gran>-<window_days>-<range_start>-<range_end>-v1`
+    pub fn d_tag(&self) -> String {
+        format!(
+            "{}-{}-{}-{}-{}-{}-v{}",
+            self.metric,
+            self.language,
+            self.granularity.as_str(),
+            self.window_days,
+            self.start,
+            self.end,
+            ANALYTICS_EVENT_VERSION
+        )
+    }
+
+    pub fn tags(&self) -> Vec<Vec<String>> {
+        let mut tags = vec![
+            vec!["d".to_string(), self.d_tag()],
+            vec!["l".to_string(), self.language.clone()],
+            vec![
+                "algo".to_string(),
+                algo_tag(&self.detector, self.zone, self.eligibility, self.counting),
+            ],
+            vec!["gran".to_string(), self.granularity.as_str().to_string()],
+            vec!["wdays".to_string(), self.window_days.to_string()],
+            vec!["app".to_string(), APP_TAG.to_string()],
+        ];
+        for relay in &self.relays {
+            tags.push(vec!["r".to_string(), relay.clone()]);
+        }
+        tags
+    }
+
+    /// JSON content; `generated_at` goes into `notes`
+    pub fn content(&self, generated_at: Timestamp) -> Result<String, AnalyticsError> {
+        let content = Content {
+            version: ANALYTICS_EVENT_VERSION,
+            metric: &self.metric,
+            language: &self.language,
+            relays: &self.relays,
+            timeframe: Timeframe {
+                start: self.start,
+                end: self.end,
+                granularity: self.granularity.as_str(),
+                window_days: self.window_days,
+            },
+            counts: self.counts.iter().map(|p| (p.epoch_day, p.count)).collect(),
+            count_errors: self
+                .counts
+                .iter()
+                .filter_map(|p| p.error.map(|error| (p.epoch_day, error)))
+                .collect(),
+            ratios: self.ratios.iter().map(|p| (p.epoch_day, p.ratio)).collect(),
+            eligible_user_count: self.eligible_user_count,
+            notes: format!(
+                "Generated by Nostr Analytics at {}",
+                nostr::Timestamp::from(generated_at).to_human_datetime()
+            ),
+        };
+        Ok(serde_json::to_string(&content)?)
+    }
+
+    /// Build and sign the event with a hex or nsec secret key
+    pub fn sign(&self, secret_key: &str) -> Result<NostrEvent, AnalyticsError> {
+        let keys = Keys::parse(secret_key)
+            .map_err(|e| AnalyticsError::NostrSdk(format!("Invalid secret key: {}", e)))?;
+
+        let tags = self
+            .tags()
+            .into_iter()
+            .map(Tag::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| AnalyticsError::NostrSdk(e.to_string()))?;
+
+        let created_at = nostr::Timestamp::now();
+        let event = EventBuilder::new(
+            Kind::from(ANALYTICS_EVENT_KIND),
+            self.content(created_at.as_u64())?,
+        )
+        .tags(tags)
+        .custom_created_at(created_at)
+        .sign_with_keys(&keys)
+        .map_err(|e| AnalyticsError::NostrSdk(e.to_string()))?;
+
+        Ok(serde_json::from_value(serde_json::to_value(&event)?)?)
+    }
+}
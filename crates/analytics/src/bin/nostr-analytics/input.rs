@@ -0,0 +1,181 @@
+// Event and file input for the CLI
+
+use crate::report::IndexFile;
+use crate::SourceArgs;
+use nostr_analytics::*;
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+fn open(path: &Path) -> Result<Box<dyn Read>, AnalyticsError> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdin()));
+    }
+    File::open(path)
+        .map(|f| Box::new(f) as Box<dyn Read>)
+        .map_err(|e| AnalyticsError::InvalidParameter(format!("{}: {}", path.display(), e)))
+}
+
+/// Read a JSON file (`-` for stdin)
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, AnalyticsError> {
+    let reader = BufReader::new(open(path)?);
+    serde_json::from_reader(reader)
+        .map_err(|e| AnalyticsError::Serialization(format!("{}: {}", path.display(), e)))
+}
+
+/// Read a whole text file (`-` for stdin)
+pub fn read_text(path: &Path) -> Result<String, AnalyticsError> {
+    let mut text = String::new();
+    open(path)?
+        .read_to_string(&mut text)
+        .map_err(|e| AnalyticsError::InvalidParameter(format!("{}: {}", path.display(), e)))?;
+    Ok(text)
+}
+
+/// Read a language index written by `index`, as JSON or in the binary
+/// encoding
+pub fn read_index(path: &Path) -> Result<IndexFile, AnalyticsError> {
+    let mut bytes = Vec::new();
+    open(path)?
+        .read_to_end(&mut bytes)
+        .map_err(|e| AnalyticsError::InvalidParameter(format!("{}: {}", path.display(), e)))?;
+    if is_encoded_index(&bytes) {
+        return Ok(IndexFile::new(
+            &LanguageIndex::from_bytes(&bytes)?,
+            Vec::new(),
+        ));
+    }
+    serde_json::from_slice(&bytes)
+        .map_err(|e| AnalyticsError::Serialization(format!("{}: {}", path.display(), e)))
+}
+
+/// Read one event per line, skipping blank lines
+pub fn read_jsonl(path: &Path) -> Result<Vec<NostrEvent>, AnalyticsError> {
+    let reader = BufReader::new(open(path)?);
+    let mut events = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line
+            .map_err(|e| AnalyticsError::InvalidParameter(format!("{}: {}", path.display(), e)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|e| {
+            AnalyticsError::EventParsing(format!("{}:{}: {}", path.display(), i + 1, e))
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Load events in `since..=until` from a JSONL file or from relays, dropping
+/// those that fail validation. Relay fetches also return per-relay coverage.
+pub fn load_events(
+    source: &SourceArgs,
+    since: Timestamp,
+    until: Timestamp,
+    kinds: Option<Vec<u16>>,
+) -> Result<(Vec<NostrEvent>, Vec<FetchCoverage>), AnalyticsError> {
+    let (mut events, coverage) = fetch_events(source, since, until, kinds)?;
+
+    let policy = ValidationPolicy {
+        verify_ids: !source.no_verify_ids,
+        verify_signatures: source.verify_sigs,
+        max_clock_skew: Some(source.max_clock_skew),
+        ..Default::default()
+    };
+    let report = validate_events(&mut events, &policy, now());
+    eprintln!("Validation: {}", report);
+
+    Ok((events, coverage))
+}
+
+fn fetch_events(
+    source: &SourceArgs,
+    since: Timestamp,
+    until: Timestamp,
+    kinds: Option<Vec<u16>>,
+) -> Result<(Vec<NostrEvent>, Vec<FetchCoverage>), AnalyticsError> {
+    if let Some(path) = &source.input {
+        let events = read_jsonl(path)?
+            .into_iter()
+            .filter(|e| e.created_at >= since && e.created_at <= until)
+            .filter(|e| kinds.as_ref().is_none_or(|k| k.contains(&e.kind)))
+            .collect();
+        return Ok((events, Vec::new()));
+    }
+
+    if source.relay.is_empty() {
+        return Err(AnalyticsError::InvalidParameter(
+            "either --input or at least one --relay is required".to_string(),
+        ));
+    }
+
+    let planner = FetchPlanner {
+        limit: source.limit,
+        kinds,
+        ..Default::default()
+    };
+    let options = RelayClientOptions {
+        subscription_timeout: Duration::from_secs(source.timeout),
+        ..Default::default()
+    };
+
+    // Fetch from every relay in parallel
+    let outputs: Vec<Result<FetchOutput, AnalyticsError>> = thread::scope(|scope| {
+        let handles: Vec<_> = source
+            .relay
+            .iter()
+            .map(|url| {
+                let (planner, options) = (&planner, options.clone());
+                scope.spawn(move || {
+                    let mut client = RelayClient::connect_with_options(url, options)?;
+                    let output = planner.fetch(&mut client, since, until);
+                    let _ = client.close();
+                    output
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("relay fetch thread panicked"))
+            .collect()
+    });
+
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut events = Vec::new();
+    let mut coverage = Vec::new();
+    for (url, output) in source.relay.iter().zip(outputs) {
+        match output {
+            Ok(output) => {
+                let fetched = output.events.len();
+                let before = events.len();
+                for event in output.events {
+                    if seen.insert(event.id.clone()) {
+                        events.push(event);
+                    }
+                }
+                eprintln!(
+                    "{}: {} events ({} new), {:.1}% complete",
+                    url,
+                    fetched,
+                    events.len() - before,
+                    output.coverage.coverage_ratio() * 100.0
+                );
+                coverage.push(output.coverage);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    if coverage.is_empty() {
+        return Err(AnalyticsError::RelayConnection(
+            "no relay could be fetched".to_string(),
+        ));
+    }
+
+    Ok((events, coverage))
+}
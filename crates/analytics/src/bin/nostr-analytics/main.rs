@@ -0,0 +1,588 @@
+// nostr-analytics command-line interface
+//
+// Headless counterpart of the web UI: builds the language index, computes
+// DAU/WAU/MAU/YAU and publishes kind 30080 results from JSONL files or relays.
+
+mod input;
+mod output;
+mod report;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use nostr_analytics::*;
+use report::{IndexFile, InspectReport, MetricsReport};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    name = "nostr-analytics",
+    version,
+    about = "Language-based Nostr active user analytics"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build the per-user language index from content events (kind 1/42),
+    /// profiles (kind 0) and NIP-32 language labels (kind 1985)
+    Index(IndexArgs),
+    /// Compute sliding-window active users per language, or several windows
+    /// and their stickiness ratios (DAU/MAU, ...)
+    Metrics(MetricsArgs),
+    /// Sign and publish metrics as kind 30080 events
+    Publish(PublishArgs),
+    /// Summarize an event file or a language index
+    Inspect(InspectArgs),
+    /// Score language detection on a labeled JSONL corpus
+    Evaluate(EvaluateArgs),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Csv,
+}
+
+/// Where events come from
+#[derive(Args)]
+struct SourceArgs {
+    /// JSONL file with one event per line (`-` for stdin)
+    #[arg(long, conflicts_with = "relay")]
+    input: Option<PathBuf>,
+
+    /// Relay URL to fetch from (repeatable)
+    #[arg(long)]
+    relay: Vec<String>,
+
+    /// Events per REQ; truncated windows are paginated
+    #[arg(long, default_value_t = 1000)]
+    limit: u32,
+
+    /// Seconds to wait for EOSE per REQ
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Do not check that event ids match their content
+    #[arg(long)]
+    no_verify_ids: bool,
+
+    /// Also verify event signatures (slow)
+    #[arg(long)]
+    verify_sigs: bool,
+
+    /// Seconds an event's created_at may be ahead of the clock
+    #[arg(long, default_value_t = DEFAULT_MAX_CLOCK_SKEW)]
+    max_clock_skew: u64,
+}
+
+#[derive(Args)]
+struct RangeArgs {
+    /// Start timestamp (Unix seconds)
+    #[arg(long)]
+    since: u64,
+
+    /// End timestamp (Unix seconds)
+    #[arg(long)]
+    until: u64,
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    #[arg(long, value_enum, default_value = "json")]
+    format: Format,
+
+    /// Output file (stdout when omitted)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct IndexArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    range: RangeArgs,
+
+    /// Confidence threshold (0.0-1.0)
+    #[arg(long, default_value_t = 0.5)]
+    conf_thresh: f32,
+
+    /// Maximum languages per user
+    #[arg(long, default_value_t = 5)]
+    max_langs_per_user: u8,
+
+    /// Maximum events to process
+    #[arg(long)]
+    max_events: Option<u32>,
+
+    /// Skip events with fewer characters left after stripping URLs, mentions, hashtags, etc.
+    #[arg(long, default_value_t = DEFAULT_MIN_CONTENT_CHARS)]
+    min_content_chars: u32,
+
+    /// A language counts for a user with at least this many posts in it...
+    #[arg(long, default_value_t = EligibilityRule::default().min_posts)]
+    min_posts: u32,
+
+    /// ...or at least this share (0.0-1.0) of their detected posts
+    #[arg(long, default_value_t = EligibilityRule::default().min_share)]
+    min_share: f32,
+
+    /// NIP-32 language labels: prefer (over detection), blend (with detection) or ignore
+    #[arg(long, default_value = "prefer")]
+    label_mode: LabelMode,
+
+    /// Post-equivalent weight of profile (kind 0) about/display_name and NIP-05 hints; 0 disables
+    #[arg(long, default_value_t = DEFAULT_PROFILE_WEIGHT)]
+    profile_weight: f32,
+
+    /// Existing index to extend with the events of --since..--until
+    /// (events it already covers are skipped)
+    #[arg(long)]
+    extend: Option<PathBuf>,
+
+    /// With --extend, accept a gap between the index and --since (recorded in
+    /// `result.gaps`) instead of failing
+    #[arg(long, requires = "extend")]
+    allow_gaps: bool,
+
+    /// Write the compact binary encoding instead of --format (fetch
+    /// coverage is not kept)
+    #[arg(long, conflicts_with = "format")]
+    binary: bool,
+
+    /// With --binary, leave out per-language evidence; the index then only
+    /// supports lifetime eligibility and cannot be extended
+    #[arg(long, requires = "binary")]
+    no_evidence: bool,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args)]
+struct MetricsArgs {
+    /// Language index produced by `index` (JSON or --binary)
+    #[arg(long)]
+    index: PathBuf,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    range: RangeArgs,
+
+    /// Target languages (comma separated, default: every language in the index)
+    #[arg(long, value_delimiter = ',')]
+    languages: Vec<String>,
+
+    /// Window size in days (1=DAU, 7=WAU, 30=MAU, 365=YAU)
+    #[arg(long, default_value_t = 1)]
+    window_days: u16,
+
+    /// Several window sizes (comma separated, e.g. 1,7,30) from one pass,
+    /// with the ratio of each shorter window over each longer one (DAU/MAU,
+    /// ...). Day granularity only.
+    #[arg(long, value_delimiter = ',', conflicts_with = "window_days")]
+    windows: Vec<u16>,
+
+    /// Bucket size: day (sliding window), week (ISO week) or month (calendar month)
+    #[arg(long, default_value = "day")]
+    granularity: Granularity,
+
+    /// Time zone of day boundaries: UTC, an offset (+09:00) or an IANA name (Asia/Tokyo)
+    #[arg(long, default_value = "UTC")]
+    timezone: Zone,
+
+    /// Count a language on every day (lifetime) or only within a lookback
+    /// after posts in it (period, period-30d, ...)
+    #[arg(long, default_value = "lifetime")]
+    eligibility: EligibilityScope,
+
+    /// Count distinct users exactly, or estimate them with HyperLogLog
+    /// sketches (hll, hll-p14, ...) to bound memory on very large runs
+    #[arg(long, default_value = "exact")]
+    counting: CountingMode,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args)]
+struct PublishArgs {
+    /// Metrics report produced by `metrics --format json`
+    #[arg(long)]
+    metrics: PathBuf,
+
+    /// Relay to publish to (repeatable)
+    #[arg(long)]
+    relay: Vec<String>,
+
+    /// Secret key (hex or nsec)
+    #[arg(long, env = "NOSTR_SECRET_KEY", hide_env_values = true)]
+    secret_key: String,
+
+    /// Print signed events as JSONL instead of publishing
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Seconds to wait for OK per event
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// JSONL event file (`-` for stdin)
+    #[arg(long, conflicts_with = "index", required_unless_present = "index")]
+    input: Option<PathBuf>,
+
+    /// Language index produced by `index` (JSON or --binary)
+    #[arg(long)]
+    index: Option<PathBuf>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args)]
+struct EvaluateArgs {
+    /// JSONL with `content` and `expected_lang` per line (default: the
+    /// bundled fixture corpus)
+    #[arg(long)]
+    corpus: Option<PathBuf>,
+
+    /// Confidence thresholds to score (comma separated)
+    #[arg(long, value_delimiter = ',', default_values_t = DEFAULT_EVAL_THRESHOLDS)]
+    conf_thresh: Vec<f32>,
+
+    /// Skip samples with fewer characters left after stripping URLs, mentions, hashtags, etc.
+    #[arg(long, default_value_t = DEFAULT_MIN_CONTENT_CHARS)]
+    min_content_chars: u32,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Index(args) => run_index(args),
+        Command::Metrics(args) => run_metrics(args),
+        Command::Publish(args) => run_publish(args),
+        Command::Inspect(args) => run_inspect(args),
+        Command::Evaluate(args) => run_evaluate(args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_index(args: IndexArgs) -> Result<(), AnalyticsError> {
+    let (events, coverage) = input::load_events(
+        &args.source,
+        args.range.since,
+        args.range.until,
+        Some(vec![METADATA_EVENT_KIND, 1, 42, LABEL_EVENT_KIND]),
+    )?;
+
+    let config = IndexConfig {
+        conf_thresh: args.conf_thresh,
+        max_langs_per_user: args.max_langs_per_user,
+        max_events: args.max_events,
+        min_content_chars: args.min_content_chars,
+        eligibility: EligibilityRule {
+            min_posts: args.min_posts,
+            min_share: args.min_share,
+        },
+        label_mode: args.label_mode,
+        profile_weight: args.profile_weight,
+    };
+    let (language_index, coverage) = match &args.extend {
+        Some(path) => {
+            let previous = input::read_index(path)?;
+            let mut language_index = previous.restore()?;
+            let gap_policy = if args.allow_gaps {
+                GapPolicy::Warn
+            } else {
+                GapPolicy::Refuse
+            };
+            let gap = extend_language_index(
+                &mut language_index,
+                &events,
+                args.range.since,
+                args.range.until,
+                &config,
+                gap_policy,
+            )?;
+            if let Some((from, to)) = gap {
+                eprintln!(
+                    "Warning: no events of {}..={} between the index and --since",
+                    from, to
+                );
+            }
+            let mut all_coverage = previous.result.coverage;
+            all_coverage.extend(coverage);
+            (language_index, all_coverage)
+        }
+        None => {
+            let mut language_index = build_language_index(&events, &config);
+            language_index.since = args.range.since;
+            language_index.until = args.range.until;
+            (language_index, coverage)
+        }
+    };
+    let index = IndexFile::new(&language_index, coverage);
+    let result = &index.result;
+
+    eprintln!(
+        "Language index: {} users, {} of {} events with language ({} labeled, {} detected, {} too short), {} self-labels, {} profiles",
+        result.users,
+        result.events_with_language,
+        result.events_processed,
+        result.events_labeled,
+        result.events_detected,
+        result.events_too_short,
+        result.self_labels,
+        result.profiles_used
+    );
+    if !result.coverage.is_empty() {
+        eprintln!("Fetch coverage: {:.1}%", result.coverage_ratio() * 100.0);
+    }
+    for (since, until) in &result.gaps {
+        eprintln!("Warning: index does not cover {}..={}", since, until);
+    }
+
+    if args.binary {
+        let bytes = language_index.to_bytes(!args.no_evidence)?;
+        return output::write_bytes(args.output.output.as_deref(), &bytes);
+    }
+    match args.output.format {
+        Format::Json => output::write_json(args.output.output.as_deref(), &index),
+        Format::Csv => output::write_csv(
+            args.output.output.as_deref(),
+            &["pubkey", "language", "confidence"],
+            index.csv_rows(),
+        ),
+    }
+}
+
+fn run_metrics(args: MetricsArgs) -> Result<(), AnalyticsError> {
+    let index = input::read_index(&args.index)?;
+    let (events, _) = input::load_events(&args.source, args.range.since, args.range.until, None)?;
+
+    let languages = if args.languages.is_empty() {
+        index.languages()
+    } else {
+        args.languages
+    };
+
+    if !args.windows.is_empty() && args.granularity != Granularity::Day {
+        return Err(AnalyticsError::InvalidParameter(
+            "--windows needs --granularity day".to_string(),
+        ));
+    }
+
+    // Calendar buckets have no sliding window
+    let window_days = match args.granularity {
+        Granularity::Day => args
+            .windows
+            .iter()
+            .copied()
+            .min()
+            .unwrap_or(args.window_days),
+        _ => 1,
+    };
+
+    let options = MetricsOptions {
+        since: args.range.since,
+        until: args.range.until,
+        languages: languages.clone(),
+        granularity: args.granularity,
+        window_days,
+        zone: args.timezone,
+        eligibility: args.eligibility,
+        counting: args.counting,
+    };
+    let language_index = index.language_index(args.range.since, args.range.until);
+    let (by_language, stickiness) = if args.windows.is_empty() {
+        let by_language = compute_active_users_by_language(&events, &language_index, &options);
+        (by_language, None)
+    } else {
+        let stickiness = compute_stickiness(
+            &events,
+            &language_index,
+            &options,
+            GroupBy::Language,
+            &args.windows,
+        );
+        // The shortest window doubles as the report's main series
+        let by_language = stickiness.windows[0].series.clone();
+        (by_language, Some(stickiness))
+    };
+
+    let report = MetricsReport {
+        since: args.range.since,
+        until: args.range.until,
+        granularity: args.granularity,
+        window_days,
+        zone: args.timezone,
+        detector: index.result.detector.clone(),
+        eligibility: args.eligibility,
+        counting: args.counting,
+        relays: args.source.relay.clone(),
+        eligible_user_counts: languages
+            .iter()
+            .map(|lang| (lang.clone(), index.eligible_user_count(lang)))
+            .collect(),
+        by_language: by_language.into_iter().collect::<BTreeMap<_, _>>(),
+        stickiness,
+    };
+
+    match args.output.format {
+        Format::Json => output::write_json(args.output.output.as_deref(), &report),
+        Format::Csv => output::write_csv(
+            args.output.output.as_deref(),
+            report.csv_header(),
+            report.csv_rows(),
+        ),
+    }
+}
+
+fn run_publish(args: PublishArgs) -> Result<(), AnalyticsError> {
+    let report: MetricsReport = input::read_json(&args.metrics)?;
+
+    let mut signed = Vec::new();
+    for event in report.analytics_events() {
+        signed.push(event.sign(&args.secret_key)?);
+    }
+
+    if args.dry_run {
+        let lines = signed
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        println!("{}", lines.join("\n"));
+        return Ok(());
+    }
+
+    if args.relay.is_empty() {
+        return Err(AnalyticsError::InvalidParameter(
+            "at least one --relay is required (or use --dry-run)".to_string(),
+        ));
+    }
+
+    let options = RelayClientOptions {
+        subscription_timeout: std::time::Duration::from_secs(args.timeout),
+        ..Default::default()
+    };
+
+    let mut failures = 0;
+    for url in &args.relay {
+        let mut client = match RelayClient::connect_with_options(url, options.clone()) {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("{}", e);
+                failures += 1;
+                continue;
+            }
+        };
+        for event in &signed {
+            match client.publish(event) {
+                Ok((true, _)) => eprintln!("{}: published {}", url, event.id),
+                Ok((false, message)) => {
+                    eprintln!("{}: rejected {}: {}", url, event.id, message);
+                    failures += 1;
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    failures += 1;
+                }
+            }
+        }
+        let _ = client.close();
+    }
+
+    if failures > 0 {
+        return Err(AnalyticsError::RelayConnection(format!(
+            "{} publish failures",
+            failures
+        )));
+    }
+    Ok(())
+}
+
+fn run_inspect(args: InspectArgs) -> Result<(), AnalyticsError> {
+    let report = match (&args.input, &args.index) {
+        (_, Some(path)) => {
+            let index = input::read_index(path)?;
+            InspectReport::from_index(&index)
+        }
+        (Some(path), None) => InspectReport::from_events(&input::read_jsonl(path)?),
+        (None, None) => unreachable!("clap requires --input or --index"),
+    };
+
+    match args.output.format {
+        Format::Json => output::write_json(args.output.output.as_deref(), &report),
+        Format::Csv => output::write_csv(
+            args.output.output.as_deref(),
+            &["key", "value"],
+            report.csv_rows(),
+        ),
+    }
+}
+
+fn run_evaluate(args: EvaluateArgs) -> Result<(), AnalyticsError> {
+    let samples = match &args.corpus {
+        Some(path) => parse_labeled_corpus(&input::read_text(path)?)?,
+        None => parse_labeled_corpus(FIXTURE_CORPUS)?,
+    };
+    let evaluation = evaluate_detector(
+        &default_detector(),
+        &samples,
+        &args.conf_thresh,
+        args.min_content_chars,
+    )?;
+
+    eprintln!(
+        "Evaluated {} on {} samples",
+        evaluation.detector,
+        samples.len()
+    );
+    if let Some(best) = evaluation.best_threshold() {
+        eprintln!(
+            "Best conf_thresh {:.2}: macro F1 {:.3}, accuracy {:.3}, coverage {:.3}",
+            best.conf_thresh,
+            best.macro_f1.unwrap_or(0.0),
+            best.accuracy,
+            best.coverage
+        );
+    }
+
+    match args.output.format {
+        Format::Json => output::write_json(args.output.output.as_deref(), &evaluation),
+        Format::Csv => output::write_csv(
+            args.output.output.as_deref(),
+            &[
+                "conf_thresh",
+                "language",
+                "support",
+                "predicted",
+                "true_positives",
+                "precision",
+                "recall",
+                "f1",
+            ],
+            report::evaluation_csv_rows(&evaluation),
+        ),
+    }
+}
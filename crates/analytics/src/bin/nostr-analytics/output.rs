@@ -0,0 +1,58 @@
+// JSON/CSV/binary output for the CLI
+
+use nostr_analytics::AnalyticsError;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+fn create(path: Option<&Path>) -> Result<Box<dyn Write>, AnalyticsError> {
+    match path {
+        Some(path) => File::create(path)
+            .map(|f| Box::new(BufWriter::new(f)) as Box<dyn Write>)
+            .map_err(|e| AnalyticsError::InvalidParameter(format!("{}: {}", path.display(), e))),
+        None => Ok(Box::new(io::stdout().lock())),
+    }
+}
+
+fn io_error(e: io::Error) -> AnalyticsError {
+    AnalyticsError::Serialization(e.to_string())
+}
+
+pub fn write_json<T: Serialize>(path: Option<&Path>, value: &T) -> Result<(), AnalyticsError> {
+    let mut writer = create(path)?;
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer)
+        .and_then(|_| writer.flush())
+        .map_err(io_error)
+}
+
+pub fn write_bytes(path: Option<&Path>, bytes: &[u8]) -> Result<(), AnalyticsError> {
+    let mut writer = create(path)?;
+    writer
+        .write_all(bytes)
+        .and_then(|_| writer.flush())
+        .map_err(io_error)
+}
+
+pub fn write_csv(
+    path: Option<&Path>,
+    header: &[&str],
+    rows: Vec<Vec<String>>,
+) -> Result<(), AnalyticsError> {
+    let mut writer = create(path)?;
+    writeln!(writer, "{}", header.join(",")).map_err(io_error)?;
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|f| escape(f)).collect();
+        writeln!(writer, "{}", fields.join(",")).map_err(io_error)?;
+    }
+    writer.flush().map_err(io_error)
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
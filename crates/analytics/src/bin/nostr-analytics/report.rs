@@ -0,0 +1,380 @@
+// File formats written and read by the CLI
+
+use nostr_analytics::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Language index file (same shape as `process_events_for_language_index` output)
+#[derive(Serialize, Deserialize)]
+pub struct IndexFile {
+    pub result: LanguageIndexResult,
+
+    #[serde(rename = "userLanguages")]
+    pub user_languages: HashMap<PubkeyHex, HashMap<LanguageCode, f32>>,
+
+    /// Per-language evidence behind `userLanguages`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub evidence: HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>>,
+
+    /// Evidence of users with no eligible language yet
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pending: HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>>,
+
+    /// Time range the index covers (0 in files written before `--extend`)
+    #[serde(default)]
+    pub since: Timestamp,
+    #[serde(default)]
+    pub until: Timestamp,
+}
+
+impl IndexFile {
+    pub fn new(index: &LanguageIndex, coverage: Vec<FetchCoverage>) -> Self {
+        let mut result = index.result();
+        result.coverage = coverage;
+        Self {
+            result,
+            user_languages: index.to_language_map(),
+            evidence: index.to_evidence_map(),
+            pending: index.to_pending_map(),
+            since: index.since,
+            until: index.until,
+        }
+    }
+
+    /// The full index, for extending it with more events
+    pub fn restore(&self) -> Result<LanguageIndex, AnalyticsError> {
+        if self.until == 0 {
+            return Err(AnalyticsError::InvalidParameter(
+                "Index file has no covered range; rebuild it to extend".to_string(),
+            ));
+        }
+        Ok(LanguageIndex::restore(
+            &self.result,
+            self.user_languages.clone(),
+            self.evidence.clone(),
+            self.pending.clone(),
+            self.since,
+            self.until,
+        ))
+    }
+
+    /// Language index for metrics over `since..=until`
+    pub fn language_index(&self, since: Timestamp, until: Timestamp) -> LanguageIndex {
+        let mut index = LanguageIndex::from_language_map(self.user_languages.clone(), since, until)
+            .with_evidence(self.evidence.clone());
+        index.detector = self.result.detector.clone();
+        index
+    }
+
+    /// Languages present in the index, sorted
+    pub fn languages(&self) -> Vec<LanguageCode> {
+        let mut languages: Vec<LanguageCode> = self
+            .user_languages
+            .values()
+            .flat_map(|langs| langs.keys().cloned())
+            .collect();
+        languages.sort();
+        languages.dedup();
+        languages
+    }
+
+    /// Number of users eligible for a language (U_lang)
+    pub fn eligible_user_count(&self, lang: &str) -> u32 {
+        self.user_languages
+            .values()
+            .filter(|langs| langs.contains_key(lang))
+            .count() as u32
+    }
+
+    pub fn csv_rows(&self) -> Vec<Vec<String>> {
+        let mut rows: Vec<Vec<String>> = self
+            .user_languages
+            .iter()
+            .flat_map(|(pubkey, langs)| {
+                langs.iter().map(move |(lang, confidence)| {
+                    vec![pubkey.clone(), lang.clone(), confidence.to_string()]
+                })
+            })
+            .collect();
+        rows.sort();
+        rows
+    }
+}
+
+/// Metrics report written by `metrics` and read by `publish`
+#[derive(Serialize, Deserialize)]
+pub struct MetricsReport {
+    pub since: Timestamp,
+    pub until: Timestamp,
+    #[serde(default)]
+    pub granularity: Granularity,
+    pub window_days: u16,
+    #[serde(default)]
+    pub zone: Zone,
+
+    /// Language detector id of the index
+    #[serde(default = "default_detector_id")]
+    pub detector: String,
+
+    #[serde(default)]
+    pub eligibility: EligibilityScope,
+
+    #[serde(default)]
+    pub counting: CountingMode,
+
+    /// Relays the events were fetched from
+    pub relays: Vec<String>,
+
+    pub eligible_user_counts: HashMap<LanguageCode, u32>,
+
+    pub by_language: BTreeMap<LanguageCode, Vec<MetricDataPoint>>,
+
+    /// Every window of a `--windows` run and the ratios between them;
+    /// `by_language` then holds the shortest window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stickiness: Option<StickinessMetrics>,
+}
+
+impl MetricsReport {
+    /// Estimated counts get an `error` column; a `--windows` run gets a
+    /// `metric` column (`dau`, `mau`, `dau_mau`, ...) whose ratio rows hold
+    /// the ratio as `value`
+    pub fn csv_header(&self) -> &'static [&'static str] {
+        match (self.stickiness.is_some(), self.counting) {
+            (false, CountingMode::Exact) => &["language", "epoch_day", "count"],
+            (false, CountingMode::Approximate { .. }) => {
+                &["language", "epoch_day", "count", "error"]
+            }
+            (true, CountingMode::Exact) => &["language", "metric", "epoch_day", "value"],
+            (true, CountingMode::Approximate { .. }) => {
+                &["language", "metric", "epoch_day", "value", "error"]
+            }
+        }
+    }
+
+    pub fn csv_rows(&self) -> Vec<Vec<String>> {
+        let approximate = self.counting != CountingMode::Exact;
+        let count_rows = |metric: Option<String>, series: Vec<(&String, &Vec<MetricDataPoint>)>| {
+            series
+                .into_iter()
+                .flat_map(|(lang, points)| {
+                    let metric = metric.clone();
+                    points.iter().map(move |p| {
+                        let mut row = vec![lang.clone()];
+                        row.extend(metric.clone());
+                        row.extend([p.epoch_day.to_string(), p.count.to_string()]);
+                        if approximate {
+                            row.push(p.error.map(|e| e.to_string()).unwrap_or_default());
+                        }
+                        row
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let Some(stickiness) = &self.stickiness else {
+            return count_rows(None, self.by_language.iter().collect());
+        };
+        let mut rows = Vec::new();
+        for window in &stickiness.windows {
+            rows.extend(count_rows(
+                Some(metric_name(window.options.window_days)),
+                sorted(&window.series),
+            ));
+        }
+        for ratio in &stickiness.ratios {
+            let metric =
+                ratio_metric_name(ratio.numerator.window_days, ratio.denominator.window_days);
+            for (lang, points) in sorted(&ratio.series) {
+                for p in points {
+                    let mut row = vec![
+                        lang.clone(),
+                        metric.clone(),
+                        p.epoch_day.to_string(),
+                        p.ratio.map(|r| format!("{:.4}", r)).unwrap_or_default(),
+                    ];
+                    if approximate {
+                        row.push(String::new());
+                    }
+                    rows.push(row);
+                }
+            }
+        }
+        rows
+    }
+
+    /// One kind 30080 event per language, or per window, ratio and language
+    /// for a `--windows` run
+    pub fn analytics_events(&self) -> Vec<AnalyticsEvent> {
+        let Some(stickiness) = &self.stickiness else {
+            return self.series_events(self.window_days, self.by_language.iter().collect());
+        };
+        let mut events = Vec::new();
+        for window in &stickiness.windows {
+            events.extend(self.series_events(window.options.window_days, sorted(&window.series)));
+        }
+        for ratio in &stickiness.ratios {
+            for (lang, _) in sorted(&ratio.series) {
+                events.extend(AnalyticsEvent::from_ratio(
+                    ratio,
+                    lang,
+                    self.relays.clone(),
+                    self.eligible_user_count(lang),
+                    &self.detector,
+                ));
+            }
+        }
+        events
+    }
+
+    fn series_events(
+        &self,
+        window_days: u16,
+        series: Vec<(&LanguageCode, &Vec<MetricDataPoint>)>,
+    ) -> Vec<AnalyticsEvent> {
+        series
+            .into_iter()
+            .map(|(lang, counts)| AnalyticsEvent {
+                metric: granular_metric_name(self.granularity, window_days),
+                language: lang.clone(),
+                relays: self.relays.clone(),
+                start: self.since,
+                end: self.until,
+                granularity: self.granularity,
+                zone: self.zone,
+                detector: self.detector.clone(),
+                eligibility: self.eligibility,
+                counting: self.counting,
+                window_days,
+                counts: counts.clone(),
+                eligible_user_count: self.eligible_user_count(lang),
+                ratios: Vec::new(),
+            })
+            .collect()
+    }
+
+    fn eligible_user_count(&self, lang: &str) -> u32 {
+        self.eligible_user_counts.get(lang).copied().unwrap_or(0)
+    }
+}
+
+/// Entries of `map` in key order
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<(&String, &V)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Per-language scores of `evaluate`, one row per threshold and language
+pub fn evaluation_csv_rows(evaluation: &DetectionEvaluation) -> Vec<Vec<String>> {
+    let optional = |value: Option<f32>| value.map(|v| format!("{:.4}", v)).unwrap_or_default();
+    evaluation
+        .thresholds
+        .iter()
+        .flat_map(|t| {
+            t.languages.iter().map(move |(lang, scores)| {
+                vec![
+                    t.conf_thresh.to_string(),
+                    lang.clone(),
+                    scores.support.to_string(),
+                    scores.predicted.to_string(),
+                    scores.true_positives.to_string(),
+                    optional(scores.precision),
+                    optional(scores.recall),
+                    optional(scores.f1),
+                ]
+            })
+        })
+        .collect()
+}
+
+/// Summary printed by `inspect`
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum InspectReport {
+    Events {
+        events: usize,
+        pubkeys: usize,
+        first_created_at: Option<Timestamp>,
+        last_created_at: Option<Timestamp>,
+        kinds: BTreeMap<u16, usize>,
+    },
+    Index {
+        users: usize,
+        by_lang: BTreeMap<LanguageCode, u32>,
+        coverage_ratio: f32,
+    },
+}
+
+impl InspectReport {
+    pub fn from_events(events: &[NostrEvent]) -> Self {
+        let mut kinds = BTreeMap::new();
+        for event in events {
+            *kinds.entry(event.kind).or_insert(0) += 1;
+        }
+        let pubkeys: std::collections::HashSet<&str> =
+            events.iter().map(|e| e.get_pubkey()).collect();
+
+        InspectReport::Events {
+            events: events.len(),
+            pubkeys: pubkeys.len(),
+            first_created_at: events.iter().map(|e| e.created_at).min(),
+            last_created_at: events.iter().map(|e| e.created_at).max(),
+            kinds,
+        }
+    }
+
+    pub fn from_index(index: &IndexFile) -> Self {
+        InspectReport::Index {
+            users: index.user_languages.len(),
+            by_lang: index
+                .languages()
+                .into_iter()
+                .map(|lang| {
+                    let count = index.eligible_user_count(&lang);
+                    (lang, count)
+                })
+                .collect(),
+            coverage_ratio: index.result.coverage_ratio(),
+        }
+    }
+
+    pub fn csv_rows(&self) -> Vec<Vec<String>> {
+        let row = |k: &str, v: String| vec![k.to_string(), v];
+        let opt = |v: Option<Timestamp>| v.map(|t| t.to_string()).unwrap_or_default();
+        match self {
+            InspectReport::Events {
+                events,
+                pubkeys,
+                first_created_at,
+                last_created_at,
+                kinds,
+            } => {
+                let mut rows = vec![
+                    row("events", events.to_string()),
+                    row("pubkeys", pubkeys.to_string()),
+                    row("first_created_at", opt(*first_created_at)),
+                    row("last_created_at", opt(*last_created_at)),
+                ];
+                for (kind, count) in kinds {
+                    rows.push(row(&format!("kind:{}", kind), count.to_string()));
+                }
+                rows
+            }
+            InspectReport::Index {
+                users,
+                by_lang,
+                coverage_ratio,
+            } => {
+                let mut rows = vec![
+                    row("users", users.to_string()),
+                    row("coverage_ratio", coverage_ratio.to_string()),
+                ];
+                for (lang, count) in by_lang {
+                    rows.push(row(&format!("lang:{}", lang), count.to_string()));
+                }
+                rows
+            }
+        }
+    }
+}
@@ -0,0 +1,368 @@
+// Compressed bitmaps of interned user ids
+//
+// Roaring-style layout: ids are split by their high 16 bits into containers
+// of up to 65536 low halves. A sparse container is a sorted array, a dense one
+// (more than 4096 ids) an 8 KiB bitset, so a day with a handful of users costs
+// a few bytes while a busy day costs at most one bit per possible id.
+
+use std::slice;
+
+/// Containers with more ids than this become bitsets
+const ARRAY_MAX: usize = 4096;
+
+/// u64 words of a bitset container
+const BITSET_WORDS: usize = 65536 / 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Container {
+    /// Sorted low halves, at most [`ARRAY_MAX`]
+    Array(Vec<u16>),
+    /// One bit per low half, with the number of bits set
+    Bitset {
+        words: Box<[u64; BITSET_WORDS]>,
+        len: u32,
+    },
+}
+
+impl Container {
+    fn len(&self) -> u32 {
+        match self {
+            Container::Array(values) => values.len() as u32,
+            Container::Bitset { len, .. } => *len,
+        }
+    }
+
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Container::Array(values) => values.binary_search(&low).is_ok(),
+            Container::Bitset { words, .. } => words[low as usize / 64] & (1 << (low % 64)) != 0,
+        }
+    }
+
+    fn insert(&mut self, low: u16) -> bool {
+        match self {
+            Container::Array(values) => {
+                let Err(position) = values.binary_search(&low) else {
+                    return false;
+                };
+                values.insert(position, low);
+                if values.len() > ARRAY_MAX {
+                    *self = Container::bitset(values);
+                }
+                true
+            }
+            Container::Bitset { words, len } => {
+                let word = &mut words[low as usize / 64];
+                let bit = 1 << (low % 64);
+                if *word & bit != 0 {
+                    return false;
+                }
+                *word |= bit;
+                *len += 1;
+                true
+            }
+        }
+    }
+
+    fn bitset(values: &[u16]) -> Self {
+        let mut words = Box::new([0u64; BITSET_WORDS]);
+        for &low in values {
+            words[low as usize / 64] |= 1 << (low % 64);
+        }
+        Container::Bitset {
+            words,
+            len: values.len() as u32,
+        }
+    }
+
+    /// Bitset with its count recomputed, as an array when sparse enough
+    fn from_words(words: Box<[u64; BITSET_WORDS]>) -> Self {
+        let len: u32 = words.iter().map(|word| word.count_ones()).sum();
+        if len as usize > ARRAY_MAX {
+            return Container::Bitset { words, len };
+        }
+        Container::Array(
+            ContainerIter::Bitset {
+                words: &words,
+                index: 0,
+                word: words[0],
+            }
+            .collect(),
+        )
+    }
+
+    fn iter(&self) -> ContainerIter<'_> {
+        match self {
+            Container::Array(values) => ContainerIter::Array(values.iter()),
+            Container::Bitset { words, .. } => ContainerIter::Bitset {
+                words,
+                index: 0,
+                word: words[0],
+            },
+        }
+    }
+
+    fn union_with(&mut self, other: &Container) {
+        match (&mut *self, other) {
+            (Container::Bitset { words, len }, _) => {
+                match other {
+                    Container::Array(values) => {
+                        for &low in values {
+                            words[low as usize / 64] |= 1 << (low % 64);
+                        }
+                    }
+                    Container::Bitset { words: other, .. } => {
+                        for (word, other) in words.iter_mut().zip(other.iter()) {
+                            *word |= other;
+                        }
+                    }
+                }
+                *len = words.iter().map(|word| word.count_ones()).sum();
+            }
+            (Container::Array(values), Container::Array(other)) => {
+                let merged = merge_union(values, other);
+                *self = if merged.len() > ARRAY_MAX {
+                    Container::bitset(&merged)
+                } else {
+                    Container::Array(merged)
+                };
+            }
+            (Container::Array(_), Container::Bitset { .. }) => {
+                let mut union = other.clone();
+                union.union_with(self);
+                *self = union;
+            }
+        }
+    }
+
+    fn difference(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Array(values), _) => Container::Array(
+                values
+                    .iter()
+                    .copied()
+                    .filter(|&low| !other.contains(low))
+                    .collect(),
+            ),
+            (Container::Bitset { words, .. }, Container::Array(values)) => {
+                let mut words = words.clone();
+                for &low in values {
+                    words[low as usize / 64] &= !(1 << (low % 64));
+                }
+                Container::from_words(words)
+            }
+            (Container::Bitset { words: a, .. }, Container::Bitset { words: b, .. }) => {
+                let mut words = a.clone();
+                for (word, other) in words.iter_mut().zip(b.iter()) {
+                    *word &= !other;
+                }
+                Container::from_words(words)
+            }
+        }
+    }
+
+    fn intersection_len(&self, other: &Container) -> u32 {
+        match (self, other) {
+            (Container::Array(a), Container::Array(b)) => merge_intersection(a, b).len() as u32,
+            (Container::Array(values), bitset) | (bitset, Container::Array(values)) => {
+                values.iter().filter(|&&low| bitset.contains(low)).count() as u32
+            }
+            (Container::Bitset { words: a, .. }, Container::Bitset { words: b, .. }) => a
+                .iter()
+                .zip(b.iter())
+                .map(|(a, b)| (a & b).count_ones())
+                .sum(),
+        }
+    }
+}
+
+fn merge_union(a: &[u16], b: &[u16]) -> Vec<u16> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => {
+                merged.push(a[i]);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                merged.push(b[j]);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                merged.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    merged.extend_from_slice(&a[i..]);
+    merged.extend_from_slice(&b[j..]);
+    merged
+}
+
+fn merge_intersection(a: &[u16], b: &[u16]) -> Vec<u16> {
+    let mut common = Vec::with_capacity(a.len().min(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    common
+}
+
+enum ContainerIter<'a> {
+    Array(slice::Iter<'a, u16>),
+    Bitset {
+        words: &'a [u64; BITSET_WORDS],
+        index: usize,
+        word: u64,
+    },
+}
+
+impl Iterator for ContainerIter<'_> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        match self {
+            ContainerIter::Array(values) => values.next().copied(),
+            ContainerIter::Bitset { words, index, word } => {
+                while *word == 0 {
+                    *index += 1;
+                    *word = *words.get(*index)?;
+                }
+                let bit = word.trailing_zeros() as usize;
+                *word &= *word - 1;
+                Some((*index * 64 + bit) as u16)
+            }
+        }
+    }
+}
+
+/// Set of interned user ids (see [`crate::OwnedPubkeyInterner`]) with set
+/// operations for window unions, churn differences and retained counts
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserBitmap {
+    /// Containers by the ids' high 16 bits, ascending; never empty
+    containers: Vec<(u16, Container)>,
+}
+
+impl UserBitmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `id`; returns whether it was new
+    pub fn insert(&mut self, id: u32) -> bool {
+        let (high, low) = split(id);
+        match self.containers.binary_search_by_key(&high, |(key, _)| *key) {
+            Ok(position) => self.containers[position].1.insert(low),
+            Err(position) => {
+                self.containers
+                    .insert(position, (high, Container::Array(vec![low])));
+                true
+            }
+        }
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        let (high, low) = split(id);
+        self.container(high)
+            .is_some_and(|container| container.contains(low))
+    }
+
+    /// Number of ids
+    pub fn len(&self) -> usize {
+        self.containers
+            .iter()
+            .map(|(_, container)| container.len() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.containers.is_empty()
+    }
+
+    /// Ids in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.containers.iter().flat_map(|(high, container)| {
+            container
+                .iter()
+                .map(move |low| ((*high as u32) << 16) | low as u32)
+        })
+    }
+
+    /// Add every id of `other`
+    pub fn union_with(&mut self, other: &UserBitmap) {
+        for (high, container) in &other.containers {
+            match self.containers.binary_search_by_key(high, |(key, _)| *key) {
+                Ok(position) => self.containers[position].1.union_with(container),
+                Err(position) => self.containers.insert(position, (*high, container.clone())),
+            }
+        }
+    }
+
+    /// Remove the ids in `other`
+    pub fn difference_with(&mut self, other: &UserBitmap) {
+        self.retain_containers(|high, container| match other.container(high) {
+            Some(other) => Some(container.difference(other)),
+            None => Some(container.clone()),
+        });
+    }
+
+    /// Number of ids in both, without building the intersection
+    pub fn intersection_len(&self, other: &UserBitmap) -> usize {
+        self.containers
+            .iter()
+            .filter_map(|(high, container)| {
+                other
+                    .container(*high)
+                    .map(|other| container.intersection_len(other) as usize)
+            })
+            .sum()
+    }
+
+    fn container(&self, high: u16) -> Option<&Container> {
+        self.containers
+            .binary_search_by_key(&high, |(key, _)| *key)
+            .ok()
+            .map(|position| &self.containers[position].1)
+    }
+
+    /// Replace every container by `f`'s result, dropping empty ones
+    fn retain_containers(&mut self, f: impl Fn(u16, &Container) -> Option<Container>) {
+        self.containers = self
+            .containers
+            .iter()
+            .filter_map(|(high, container)| f(*high, container).map(|c| (*high, c)))
+            .filter(|(_, container)| container.len() > 0)
+            .collect();
+    }
+}
+
+fn split(id: u32) -> (u16, u16) {
+    ((id >> 16) as u16, id as u16)
+}
+
+impl FromIterator<u32> for UserBitmap {
+    fn from_iter<I: IntoIterator<Item = u32>>(ids: I) -> Self {
+        let mut bitmap = UserBitmap::new();
+        bitmap.extend(ids);
+        bitmap
+    }
+}
+
+impl Extend<u32> for UserBitmap {
+    fn extend<I: IntoIterator<Item = u32>>(&mut self, ids: I) {
+        for id in ids {
+            self.insert(id);
+        }
+    }
+}
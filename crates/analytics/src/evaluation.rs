@@ -0,0 +1,244 @@
+// Language detection accuracy evaluation
+//
+// Runs a detector over a labeled corpus of Nostr-style notes and scores it at
+// several confidence thresholds, so `conf_thresh` defaults can be chosen from
+// data instead of guessed.
+
+use crate::error::AnalyticsError;
+use crate::language::{detect_content_language, LanguageDetector};
+use crate::types::LanguageCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Small labeled corpus checked in with the crate (fixtures/language_eval.jsonl)
+pub const FIXTURE_CORPUS: &str = include_str!("../fixtures/language_eval.jsonl");
+
+/// Confidence thresholds evaluated when none are given
+pub const DEFAULT_EVAL_THRESHOLDS: [f32; 5] = [0.0, 0.3, 0.5, 0.7, 0.9];
+
+/// Confusion matrix column for samples without a language above the threshold
+pub const UNDETERMINED: &str = "und";
+
+/// One line of a labeled corpus
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LabeledSample {
+    pub content: String,
+
+    /// ISO 639-1 code the content is written in
+    pub expected_lang: LanguageCode,
+}
+
+/// Parse a JSONL corpus of `{"content": ..., "expected_lang": ...}`, skipping
+/// blank lines
+pub fn parse_labeled_corpus(jsonl: &str) -> Result<Vec<LabeledSample>, AnalyticsError> {
+    jsonl
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| AnalyticsError::Serialization(format!("line {}: {}", i + 1, e)))
+        })
+        .collect()
+}
+
+/// Scores of one expected language
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LanguageScores {
+    /// Samples labeled with this language
+    pub support: u32,
+
+    /// Samples detected as this language
+    pub predicted: u32,
+
+    /// Samples labeled and detected as this language
+    pub true_positives: u32,
+
+    /// `None` when nothing was detected as this language
+    pub precision: Option<f32>,
+
+    /// `None` when no sample is labeled with this language
+    pub recall: Option<f32>,
+
+    /// 0 when nothing was detected as this language; `None` when the corpus
+    /// has no samples of it
+    pub f1: Option<f32>,
+}
+
+/// Detection quality at one confidence threshold
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ThresholdEvaluation {
+    pub conf_thresh: f32,
+
+    pub samples: u32,
+
+    /// Samples with a language at or above the threshold
+    pub detected: u32,
+
+    /// Samples detected as their expected language
+    pub correct: u32,
+
+    /// `correct / samples`
+    pub accuracy: f32,
+
+    /// `detected / samples`
+    pub coverage: f32,
+
+    /// Mean F1 over every language with samples in the corpus
+    pub macro_f1: Option<f32>,
+
+    /// Scores by language, for every expected or detected language
+    pub languages: BTreeMap<LanguageCode, LanguageScores>,
+
+    /// Counts by expected language, then detected language
+    /// ([`UNDETERMINED`] below the threshold or when too short)
+    pub confusion: BTreeMap<LanguageCode, BTreeMap<LanguageCode, u32>>,
+}
+
+/// Detection report of a corpus
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DetectionEvaluation {
+    /// Detector id (`name@version`)
+    pub detector: String,
+
+    pub min_content_chars: u32,
+
+    /// One entry per threshold, in the order given
+    pub thresholds: Vec<ThresholdEvaluation>,
+}
+
+impl DetectionEvaluation {
+    /// Threshold with the highest macro F1 (the lowest one on ties)
+    pub fn best_threshold(&self) -> Option<&ThresholdEvaluation> {
+        self.thresholds
+            .iter()
+            .filter(|t| t.macro_f1.is_some())
+            .fold(None, |best: Option<&ThresholdEvaluation>, t| match best {
+                Some(b) if b.macro_f1 >= t.macro_f1 => Some(b),
+                _ => Some(t),
+            })
+    }
+}
+
+/// Detect every sample once and score the detections at each of
+/// `thresholds`. Content goes through the same preprocessing as the index.
+pub fn evaluate_detector(
+    detector: &dyn LanguageDetector,
+    samples: &[LabeledSample],
+    thresholds: &[f32],
+    min_content_chars: u32,
+) -> Result<DetectionEvaluation, AnalyticsError> {
+    let detections = samples
+        .iter()
+        .map(|sample| {
+            let result = detect_content_language(detector, &sample.content, min_content_chars)?;
+            Ok(result.detection.map(|d| (d.language, d.confidence)))
+        })
+        .collect::<Result<Vec<_>, AnalyticsError>>()?;
+
+    let thresholds = thresholds
+        .iter()
+        .map(|&conf_thresh| score(samples, &detections, conf_thresh))
+        .collect();
+
+    Ok(DetectionEvaluation {
+        detector: detector.id(),
+        min_content_chars,
+        thresholds,
+    })
+}
+
+fn score(
+    samples: &[LabeledSample],
+    detections: &[Option<(LanguageCode, f32)>],
+    conf_thresh: f32,
+) -> ThresholdEvaluation {
+    let mut confusion: BTreeMap<LanguageCode, BTreeMap<LanguageCode, u32>> = BTreeMap::new();
+    let mut support: BTreeMap<&str, u32> = BTreeMap::new();
+    let mut predicted: BTreeMap<&str, u32> = BTreeMap::new();
+    let mut true_positives: BTreeMap<&str, u32> = BTreeMap::new();
+    let mut detected = 0;
+
+    for (sample, detection) in samples.iter().zip(detections) {
+        let expected = sample.expected_lang.as_str();
+        *support.entry(expected).or_insert(0) += 1;
+
+        let language = match detection {
+            Some((language, confidence)) if *confidence >= conf_thresh => {
+                detected += 1;
+                *predicted.entry(language.as_str()).or_insert(0) += 1;
+                if language == expected {
+                    *true_positives.entry(expected).or_insert(0) += 1;
+                }
+                language.as_str()
+            }
+            _ => UNDETERMINED,
+        };
+        *confusion
+            .entry(expected.to_string())
+            .or_default()
+            .entry(language.to_string())
+            .or_insert(0) += 1;
+    }
+
+    let mut languages: BTreeMap<LanguageCode, LanguageScores> = BTreeMap::new();
+    for lang in support.keys().chain(predicted.keys()) {
+        if languages.contains_key(*lang) {
+            continue;
+        }
+        let support = support.get(lang).copied().unwrap_or(0);
+        let predicted = predicted.get(lang).copied().unwrap_or(0);
+        let true_positives = true_positives.get(lang).copied().unwrap_or(0);
+        let precision = ratio(true_positives, predicted);
+        let recall = ratio(true_positives, support);
+        // A language that is never predicted scores 0, not "undefined",
+        // so raising the threshold until it drops out is not rewarded
+        let f1 = match (precision, recall) {
+            (Some(p), Some(r)) if p + r > 0.0 => Some(2.0 * p * r / (p + r)),
+            (_, Some(_)) => Some(0.0),
+            _ => None,
+        };
+        languages.insert(
+            lang.to_string(),
+            LanguageScores {
+                support,
+                predicted,
+                true_positives,
+                precision,
+                recall,
+                f1,
+            },
+        );
+    }
+
+    let f1s: Vec<f32> = languages
+        .values()
+        .filter(|s| s.support > 0)
+        .filter_map(|s| s.f1)
+        .collect();
+    let correct: u32 = true_positives.values().sum();
+    let samples = samples.len() as u32;
+
+    ThresholdEvaluation {
+        conf_thresh,
+        samples,
+        detected,
+        correct,
+        accuracy: ratio(correct, samples).unwrap_or(0.0),
+        coverage: ratio(detected, samples).unwrap_or(0.0),
+        macro_f1: if f1s.is_empty() {
+            None
+        } else {
+            Some(f1s.iter().sum::<f32>() / f1s.len() as f32)
+        },
+        languages,
+        confusion,
+    }
+}
+
+fn ratio(part: u32, total: u32) -> Option<f32> {
+    if total == 0 {
+        return None;
+    }
+    Some(part as f32 / total as f32)
+}
@@ -0,0 +1,306 @@
+// Fetch planner
+//
+// Relays cap the number of events returned per REQ. A response with
+// `count == limit` is treated as truncated, and the window is either split in
+// half or walked backward with `until = oldest.created_at` until every window
+// is complete. Per-window stats record how much of the range was really seen.
+
+use crate::error::AnalyticsError;
+use crate::relay_client::{Filter, NostrEvent, SubscriptionEnd, SubscriptionResult};
+use crate::types::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Anything that can answer a single-filter REQ
+pub trait EventSource {
+    /// Name used in coverage stats (usually the relay URL)
+    fn source_name(&self) -> &str;
+
+    fn query(&mut self, filter: &Filter) -> Result<SubscriptionResult, AnalyticsError>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl EventSource for crate::relay_client::RelayClient {
+    fn source_name(&self) -> &str {
+        self.url()
+    }
+
+    fn query(&mut self, filter: &Filter) -> Result<SubscriptionResult, AnalyticsError> {
+        self.fetch(std::slice::from_ref(filter))
+    }
+}
+
+/// How a truncated window is paginated
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PaginationStrategy {
+    /// Split the window in half until each half fits under the limit
+    Split,
+    /// Repeat the query with `until = oldest.created_at`
+    WalkBack,
+}
+
+/// Completeness stats for one planned time window
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WindowStats {
+    /// Window start (inclusive, Unix seconds)
+    pub since: Timestamp,
+
+    /// Window end (inclusive, Unix seconds)
+    pub until: Timestamp,
+
+    /// Unique events fetched for this window
+    pub events: u32,
+
+    /// REQs issued for this window
+    pub requests: u32,
+
+    /// True when every REQ ended with EOSE below the limit
+    pub complete: bool,
+}
+
+/// Coverage report for one source
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FetchCoverage {
+    /// Source name (relay URL)
+    pub source: String,
+
+    pub windows: Vec<WindowStats>,
+}
+
+impl FetchCoverage {
+    pub fn requests(&self) -> u32 {
+        self.windows.iter().map(|w| w.requests).sum()
+    }
+
+    pub fn complete_windows(&self) -> usize {
+        self.windows.iter().filter(|w| w.complete).count()
+    }
+
+    pub fn incomplete_windows(&self) -> usize {
+        self.windows.len() - self.complete_windows()
+    }
+
+    /// Fraction of the covered seconds that were fetched completely (0.0-1.0)
+    pub fn coverage_ratio(&self) -> f32 {
+        let span = |w: &WindowStats| (w.until - w.since + 1) as f64;
+        let total: f64 = self.windows.iter().map(span).sum();
+        if total == 0.0 {
+            return 1.0;
+        }
+        let complete: f64 = self.windows.iter().filter(|w| w.complete).map(span).sum();
+        (complete / total) as f32
+    }
+}
+
+/// Events and coverage produced by the planner
+#[derive(Clone, Debug)]
+pub struct FetchOutput {
+    pub events: Vec<NostrEvent>,
+    pub coverage: FetchCoverage,
+}
+
+/// Plans REQs over a time range and paginates truncated windows
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FetchPlanner {
+    /// Base window size in seconds (default one day, like the TS fetcher)
+    pub chunk_secs: u64,
+
+    /// `limit` sent with every REQ
+    pub limit: u32,
+
+    /// Kinds to request (`None` = all kinds)
+    pub kinds: Option<Vec<u16>>,
+
+    pub strategy: PaginationStrategy,
+
+    /// Windows narrower than this are walked back instead of split
+    pub min_split_secs: u64,
+
+    /// Upper bound on REQs per base window
+    pub max_requests_per_window: u32,
+}
+
+impl Default for FetchPlanner {
+    fn default() -> Self {
+        Self {
+            chunk_secs: 86400,
+            limit: 1000,
+            kinds: None,
+            strategy: PaginationStrategy::Split,
+            min_split_secs: 60,
+            max_requests_per_window: 256,
+        }
+    }
+}
+
+/// Outcome of a single REQ
+struct Page {
+    count: usize,
+    oldest: Option<Timestamp>,
+    eose: bool,
+}
+
+impl FetchPlanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Base windows covering `since..=until`
+    pub fn plan(&self, since: Timestamp, until: Timestamp) -> Vec<(Timestamp, Timestamp)> {
+        let chunk = self.chunk_secs.max(1);
+        let mut windows = Vec::new();
+        let mut start = since;
+        while start <= until {
+            let end = start.saturating_add(chunk - 1).min(until);
+            windows.push((start, end));
+            if end == Timestamp::MAX {
+                break;
+            }
+            start = end + 1;
+        }
+        windows
+    }
+
+    /// Fetch every event in `since..=until` from one source
+    pub fn fetch<S: EventSource + ?Sized>(
+        &self,
+        source: &mut S,
+        since: Timestamp,
+        until: Timestamp,
+    ) -> Result<FetchOutput, AnalyticsError> {
+        if self.limit == 0 {
+            return Err(AnalyticsError::InvalidParameter(
+                "limit must be greater than 0".to_string(),
+            ));
+        }
+
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut events = Vec::new();
+        let mut coverage = FetchCoverage {
+            source: source.source_name().to_string(),
+            windows: Vec::new(),
+        };
+
+        for (start, end) in self.plan(since, until) {
+            let before = events.len();
+            let mut stats = WindowStats {
+                since: start,
+                until: end,
+                events: 0,
+                requests: 0,
+                complete: true,
+            };
+
+            self.fetch_window(source, start, end, &mut seen, &mut events, &mut stats)?;
+
+            stats.events = (events.len() - before) as u32;
+            coverage.windows.push(stats);
+        }
+
+        Ok(FetchOutput { events, coverage })
+    }
+
+    fn fetch_window<S: EventSource + ?Sized>(
+        &self,
+        source: &mut S,
+        since: Timestamp,
+        until: Timestamp,
+        seen: &mut HashSet<String>,
+        events: &mut Vec<NostrEvent>,
+        stats: &mut WindowStats,
+    ) -> Result<(), AnalyticsError> {
+        let splittable = until - since >= self.min_split_secs.max(1);
+        if self.strategy == PaginationStrategy::WalkBack || !splittable {
+            return self.walk_back(source, since, until, seen, events, stats);
+        }
+
+        let Some(page) = self.request(source, since, until, seen, events, stats)? else {
+            return Ok(());
+        };
+        if !page.eose {
+            stats.complete = false;
+            return Ok(());
+        }
+        if page.count < self.limit as usize {
+            return Ok(());
+        }
+
+        let mid = since + (until - since) / 2;
+        self.fetch_window(source, since, mid, seen, events, stats)?;
+        self.fetch_window(source, mid + 1, until, seen, events, stats)
+    }
+
+    fn walk_back<S: EventSource + ?Sized>(
+        &self,
+        source: &mut S,
+        since: Timestamp,
+        until: Timestamp,
+        seen: &mut HashSet<String>,
+        events: &mut Vec<NostrEvent>,
+        stats: &mut WindowStats,
+    ) -> Result<(), AnalyticsError> {
+        let mut current_until = until;
+        loop {
+            let Some(page) = self.request(source, since, current_until, seen, events, stats)?
+            else {
+                return Ok(());
+            };
+            if !page.eose {
+                stats.complete = false;
+                return Ok(());
+            }
+            if page.count < self.limit as usize {
+                return Ok(());
+            }
+
+            let oldest = page.oldest.unwrap_or(since);
+            if oldest >= current_until {
+                // More than `limit` events share one second; skip past it
+                stats.complete = false;
+                if oldest <= since {
+                    return Ok(());
+                }
+                current_until = oldest - 1;
+            } else {
+                // `until` is inclusive so events sharing the boundary second are re-read
+                current_until = oldest;
+            }
+        }
+    }
+
+    /// Issue one REQ; returns `None` once the request budget is exhausted
+    fn request<S: EventSource + ?Sized>(
+        &self,
+        source: &mut S,
+        since: Timestamp,
+        until: Timestamp,
+        seen: &mut HashSet<String>,
+        events: &mut Vec<NostrEvent>,
+        stats: &mut WindowStats,
+    ) -> Result<Option<Page>, AnalyticsError> {
+        if stats.requests >= self.max_requests_per_window {
+            stats.complete = false;
+            return Ok(None);
+        }
+        stats.requests += 1;
+
+        let mut filter = Filter::new().since(since).until(until).limit(self.limit);
+        filter.kinds = self.kinds.clone();
+
+        let result = source.query(&filter)?;
+        let page = Page {
+            count: result.events.len(),
+            oldest: result.events.iter().map(|e| e.created_at).min(),
+            eose: result.end == SubscriptionEnd::Eose,
+        };
+
+        for event in result.events {
+            if seen.insert(event.id.clone()) {
+                events.push(event);
+            }
+        }
+
+        Ok(Some(page))
+    }
+}
@@ -0,0 +1,122 @@
+use crate::bitmap::UserBitmap;
+use crate::metrics::{collect_activity, GroupBy};
+use crate::relay_client::NostrEvent;
+use crate::types::*;
+use crate::utils::console_log;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Growth accounting for one day.
+///
+/// With `inactive_days = 1` this is the classic DAU decomposition:
+/// `active(D) = new + retained + resurrected` and
+/// `active(D-1) = retained + churned`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GrowthDataPoint {
+    pub epoch_day: EpochDay,
+
+    /// Users active on this day
+    pub active: u32,
+
+    /// Active for the first time in the given events
+    pub new: u32,
+
+    /// Active and also active within the previous `inactive_days` days
+    pub retained: u32,
+
+    /// Active again after at least `inactive_days` inactive days
+    pub resurrected: u32,
+
+    /// Last active exactly `inactive_days` days ago and inactive since
+    pub churned: u32,
+
+    /// `(new + resurrected) / churned`, `None` when nobody churned
+    pub quick_ratio: Option<f32>,
+}
+
+/// New / retained / resurrected / churned users per day and language.
+///
+/// Activity before `options.since` in `events` counts as history, so pass
+/// earlier events to avoid everyone being "new" on the first day.
+/// `options.window_days` is ignored.
+pub fn compute_growth_accounting(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &MetricsOptions,
+    inactive_days: u16,
+) -> HashMap<LanguageCode, Vec<GrowthDataPoint>> {
+    let inactive_days = inactive_days.max(1) as u32;
+    let groups = collect_activity(events, index, options, GroupBy::Language);
+
+    let start_day = options.zone.epoch_day(options.since);
+    let end_day = options.zone.epoch_day(options.until);
+
+    let results: HashMap<LanguageCode, Vec<GrowthDataPoint>> = groups
+        .keys
+        .into_iter()
+        .zip(groups.activity.iter())
+        .map(|(lang, activity_by_day)| {
+            let points = growth_series(activity_by_day, start_day, end_day, inactive_days);
+            (lang, points)
+        })
+        .collect();
+
+    console_log!("Computed growth accounting for {} languages", results.len());
+
+    results
+}
+
+/// Growth of one group from set operations on its daily active users
+fn growth_series(
+    activity_by_day: &HashMap<EpochDay, UserBitmap>,
+    start_day: EpochDay,
+    end_day: EpochDay,
+    inactive_days: u32,
+) -> Vec<GrowthDataPoint> {
+    let nobody = UserBitmap::new();
+    let active_on = |day: EpochDay| activity_by_day.get(&day).unwrap_or(&nobody);
+
+    // Users active on any day before the one being computed
+    let mut seen = UserBitmap::new();
+    for (_, users) in activity_by_day.iter().filter(|(&day, _)| day < start_day) {
+        seen.union_with(users);
+    }
+
+    (start_day..=end_day)
+        .map(|epoch_day| {
+            let active = active_on(epoch_day);
+
+            let mut recent = UserBitmap::new();
+            for day in epoch_day.saturating_sub(inactive_days)..epoch_day {
+                recent.union_with(active_on(day));
+            }
+            let retained = active.intersection_len(&recent) as u32;
+            let returning = active.intersection_len(&seen) as u32;
+            let new = active.len() as u32 - returning;
+
+            // Active `inactive_days` ago and not since
+            let churned = match epoch_day.checked_sub(inactive_days) {
+                Some(last_day) => {
+                    let mut churned = active_on(last_day).clone();
+                    for day in last_day + 1..=epoch_day {
+                        churned.difference_with(active_on(day));
+                    }
+                    churned.len() as u32
+                }
+                None => 0,
+            };
+
+            seen.union_with(active);
+            let resurrected = returning - retained;
+            GrowthDataPoint {
+                epoch_day,
+                active: active.len() as u32,
+                new,
+                retained,
+                resurrected,
+                churned,
+                quick_ratio: (churned > 0).then(|| (new + resurrected) as f32 / churned as f32),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,174 @@
+use crate::bitmap::UserBitmap;
+use crate::metrics::{ActiveSet, ActivityCollector, GroupBy};
+use crate::relay_client::NostrEvent;
+use crate::timezone::Zone;
+use crate::types::*;
+use crate::utils::console_log;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+/// Days per week (rows, Monday first)
+pub const HEATMAP_WEEKDAYS: usize = 7;
+
+/// Hours per day (columns)
+pub const HEATMAP_HOURS: usize = 24;
+
+/// Options for the weekday × hour activity heatmap
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeatmapOptions {
+    /// Start timestamp (Unix seconds)
+    pub since: Timestamp,
+
+    /// End timestamp (Unix seconds)
+    pub until: Timestamp,
+
+    /// Target languages
+    pub languages: Vec<LanguageCode>,
+
+    /// Time zone of weekdays and hours
+    #[serde(default)]
+    pub zone: Zone,
+
+    /// Whether a language counts on every day or only after recent posts in it
+    #[serde(default)]
+    pub eligibility: EligibilityScope,
+}
+
+/// 7x24 matrix of one language's activity, rows Monday..Sunday and columns
+/// hours 0..23 in the requested time zone
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[wasm_bindgen(getter_with_clone)]
+pub struct ActivityHeatmap {
+    pub language: LanguageCode,
+
+    /// Time zone the matrix was built in
+    pub zone: String,
+
+    /// Distinct users active in each cell over the whole range
+    #[wasm_bindgen(skip)]
+    pub users: Vec<Vec<u32>>,
+
+    /// Events in each cell
+    #[wasm_bindgen(skip)]
+    pub events: Vec<Vec<u32>>,
+}
+
+#[wasm_bindgen]
+impl ActivityHeatmap {
+    #[wasm_bindgen(getter)]
+    pub fn users(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.users).unwrap_or(JsValue::NULL)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn events(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.events).unwrap_or(JsValue::NULL)
+    }
+
+    /// Distinct users at `weekday` (0 = Monday) and `hour`
+    pub fn users_at(&self, weekday: usize, hour: usize) -> u32 {
+        cell(&self.users, weekday, hour)
+    }
+
+    /// Events at `weekday` (0 = Monday) and `hour`
+    pub fn events_at(&self, weekday: usize, hour: usize) -> u32 {
+        cell(&self.events, weekday, hour)
+    }
+}
+
+fn cell(matrix: &[Vec<u32>], weekday: usize, hour: usize) -> u32 {
+    matrix
+        .get(weekday)
+        .and_then(|row| row.get(hour))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Weekday (0 = Monday) and hour of `timestamp` in `zone`
+pub fn weekday_hour(timestamp: Timestamp, zone: Zone) -> (usize, usize) {
+    let local = (timestamp as i64 + zone.offset_secs(timestamp)).max(0) as u64;
+    let epoch_day = local / 86400;
+    // 1970-01-01 was a Thursday
+    let weekday = ((epoch_day + 3) % 7) as usize;
+    let hour = ((local % 86400) / 3600) as usize;
+    (weekday, hour)
+}
+
+/// Users and events of one heatmap cell
+#[derive(Clone, Default)]
+struct Cell {
+    users: UserBitmap,
+    events: u32,
+}
+
+impl ActiveSet for Cell {
+    fn insert_user(&mut self, id: u32, _hash: u64) {
+        self.users.insert(id);
+        self.events += 1;
+    }
+}
+
+/// Weekday × hour heatmap of distinct users and events per language, for
+/// users eligible for each of `options.languages` at the time of the event
+/// (see [`EligibilityScope`]). Languages come back in the order requested.
+pub fn compute_activity_heatmap(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &HeatmapOptions,
+) -> Vec<ActivityHeatmap> {
+    console_log!(
+        "Computing activity heatmap: {} events, {} languages, zone {}",
+        events.len(),
+        options.languages.len(),
+        options.zone
+    );
+
+    let mut collector = ActivityCollector::with_buckets(
+        index,
+        &options.languages,
+        options.eligibility,
+        options.zone,
+        GroupBy::Language,
+        Cell::default(),
+        weekday_hour,
+    );
+    collector.push(events.iter().filter(|event| {
+        let created_at = event.get_created_at();
+        created_at >= options.since && created_at <= options.until
+    }));
+    let groups = collector.groups;
+    let cells_by_lang: HashMap<&str, &HashMap<(usize, usize), Cell>> = groups
+        .keys
+        .iter()
+        .map(String::as_str)
+        .zip(groups.activity.iter())
+        .collect();
+
+    options
+        .languages
+        .iter()
+        .map(|lang| {
+            let cells = cells_by_lang.get(lang.as_str());
+            let matrix = |value: fn(&Cell) -> u32| -> Vec<Vec<u32>> {
+                (0..HEATMAP_WEEKDAYS)
+                    .map(|weekday| {
+                        (0..HEATMAP_HOURS)
+                            .map(|hour| {
+                                cells
+                                    .and_then(|cells| cells.get(&(weekday, hour)))
+                                    .map_or(0, value)
+                            })
+                            .collect()
+                    })
+                    .collect()
+            };
+            ActivityHeatmap {
+                language: lang.clone(),
+                zone: options.zone.to_string(),
+                users: matrix(|cell| cell.users.len() as u32),
+                events: matrix(|cell| cell.events),
+            }
+        })
+        .collect()
+}
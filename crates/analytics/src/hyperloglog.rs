@@ -0,0 +1,131 @@
+// HyperLogLog distinct counting
+//
+// A sketch of 2^p one-byte registers estimates the number of distinct users it
+// has seen with a standard error of about 1.04 / sqrt(2^p), whatever that
+// number is; small sets are linear-counted from the empty registers, which is
+// more precise. Sketches merge by taking register maxima, so a window's count is
+// the merge of its days' sketches.
+
+/// Smallest and largest supported precision
+pub const MIN_HLL_PRECISION: u8 = 4;
+pub const MAX_HLL_PRECISION: u8 = 18;
+
+/// Precision of `hll` without an explicit `-p<N>`: 4096 registers, about 1.6%
+/// standard error
+pub const DEFAULT_HLL_PRECISION: u8 = 12;
+
+/// HyperLogLog sketch of a set of pubkeys
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Empty sketch with 2^`precision` registers (clamped to
+    /// [`MIN_HLL_PRECISION`]..=[`MAX_HLL_PRECISION`])
+    pub fn new(precision: u8) -> Self {
+        let precision = precision.clamp(MIN_HLL_PRECISION, MAX_HLL_PRECISION);
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    /// 64-bit hash of a pubkey (FNV-1a, then the SplitMix64 finalizer to
+    /// spread the bits)
+    pub fn hash(pubkey: &str) -> u64 {
+        let mut hash = pubkey.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        hash ^= hash >> 30;
+        hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash ^= hash >> 27;
+        hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^ (hash >> 31)
+    }
+
+    pub fn insert(&mut self, pubkey: &str) {
+        self.insert_hash(Self::hash(pubkey));
+    }
+
+    /// Add a value by its [`HyperLogLog::hash`]
+    pub fn insert_hash(&mut self, hash: u64) {
+        let p = self.precision as u32;
+        let register = (hash >> (64 - p)) as usize;
+        let rank = ((hash << p).leading_zeros().min(64 - p) + 1) as u8;
+        if self.registers[register] < rank {
+            self.registers[register] = rank;
+        }
+    }
+
+    /// Add everything `other` has seen.
+    ///
+    /// # Panics
+    ///
+    /// If the sketches have different precisions.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        assert_eq!(
+            self.precision, other.precision,
+            "Cannot merge HyperLogLog sketches of different precision"
+        );
+        for (register, &other) in self.registers.iter_mut().zip(&other.registers) {
+            if *register < other {
+                *register = other;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registers.iter().all(|&register| register == 0)
+    }
+
+    /// Estimated number of distinct values, with linear counting for small
+    /// sets
+    pub fn estimate(&self) -> f64 {
+        self.estimate_with_error().0
+    }
+
+    /// [`HyperLogLog::estimate`] and its standard error. Under linear counting
+    /// the error follows that estimator's own variance, `m(e^t - t - 1)` for
+    /// `t = n/m` (Whang et al.), which is far below the asymptotic bound for
+    /// small sets.
+    pub fn estimate_with_error(&self) -> (f64, f64) {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&register| 2f64.powi(-(register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|&&register| register == 0)
+            .count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            let estimate = m * (m / zeros as f64).ln();
+            let load = estimate / m;
+            (estimate, (m * (load.exp() - load - 1.0)).max(0.0).sqrt())
+        } else {
+            (estimate, self.relative_error() * estimate)
+        }
+    }
+
+    /// Asymptotic relative standard error of [`HyperLogLog::estimate`] for
+    /// sets too large for linear counting
+    pub fn relative_error(&self) -> f64 {
+        1.04 / (self.registers.len() as f64).sqrt()
+    }
+}
@@ -0,0 +1,590 @@
+use crate::error::AnalyticsError;
+use crate::labels::{
+    event_language_label, LabelMode, LanguageLabels, LABEL_CONFIDENCE, LABEL_EVENT_KIND,
+    OVERRULED_DETECTION_WEIGHT, SELF_LABEL_WEIGHT,
+};
+use crate::language::{
+    default_detector, detect_content_language, Detection, LanguageDetector, WhatlangDetector,
+};
+use crate::profile::{profile_hints, ProfileMetadata, METADATA_EVENT_KIND};
+use crate::relay_client::NostrEvent;
+use crate::script::CjkScriptDetector;
+use crate::types::*;
+use crate::utils::console_log;
+use crate::validation::{EventValidator, ValidationReport};
+use std::collections::{HashMap, VecDeque};
+
+/// Build a language index from content events (kind 1/42) with the default
+/// detector (whatlang with CJK script correction). NIP-32 language labels
+/// and kind 1985 self-labels in `events` are used per `config.label_mode`,
+/// and the latest kind 0 profile of each user adds a prior weighted by
+/// `config.profile_weight`.
+/// The index covers the time range of the processed events.
+pub fn build_language_index(events: &[NostrEvent], config: &IndexConfig) -> LanguageIndex {
+    build_language_index_with_detector(events, config, &default_detector())
+}
+
+/// Build a language index using `detector`, whose id is recorded in the index
+pub fn build_language_index_with_detector(
+    events: &[NostrEvent],
+    config: &IndexConfig,
+    detector: &dyn LanguageDetector,
+) -> LanguageIndex {
+    let mut builder = LanguageIndexBuilder::with_detector(config.clone(), detector);
+    builder.push_batch(events);
+    builder.finish()
+}
+
+/// Posts a [`LanguageIndexBuilder`] keeps by default for kind 1985 labels
+/// that arrive after them
+pub const DEFAULT_LABEL_BUFFER: usize = 100_000;
+
+/// [`default_detector`] for builders that outlive the call creating them
+static DEFAULT_DETECTOR: CjkScriptDetector<WhatlangDetector> =
+    CjkScriptDetector::new(WhatlangDetector);
+
+/// Detection result of a content event
+#[derive(Clone, Copy, Debug)]
+enum Detected {
+    /// Not run: the event carries a label that `LabelMode::Prefer` takes
+    Skipped,
+    /// Nothing above the confidence threshold
+    Undetermined,
+    /// Too little text left after preprocessing
+    TooShort,
+    /// Language id (see `LanguageIndexBuilder::languages`) and confidence
+    Language(u16, f32),
+}
+
+/// Id of a content event, as 32 bytes when it is lowercase hex
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum PostId {
+    Hex([u8; 32]),
+    Other(String),
+}
+
+impl PostId {
+    fn new(id: &str) -> Self {
+        if id.len() != 64
+            || !id
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        {
+            return PostId::Other(id.to_string());
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(id.as_bytes().chunks(2)) {
+            let digit = |b: u8| (b as char).to_digit(16).unwrap_or(0) as u8;
+            *byte = digit(pair[0]) << 4 | digit(pair[1]);
+        }
+        PostId::Hex(bytes)
+    }
+}
+
+/// A counted content event that a kind 1985 label of a later batch may still
+/// relabel
+#[derive(Clone, Copy, Debug)]
+struct LabelablePost {
+    author: u32,
+    created_at: Timestamp,
+    detected: Detected,
+}
+
+/// Event counters of the index being built
+#[derive(Clone, Copy, Debug, Default)]
+struct PostCounts {
+    with_language: u32,
+    too_short: u32,
+    labeled: u32,
+    detected: u32,
+    label_conflicts: u32,
+}
+
+/// Builds a language index from events pushed batch by batch, so the caller
+/// can drop each batch (e.g. a relay page) once pushed.
+///
+/// Detection runs as each batch is pushed and is counted toward the author's
+/// languages right away. So that a kind 1985 label arriving later can still
+/// move a post to its label, the id, author and detection of the most recent
+/// posts without a label of their own are kept, at most
+/// [`DEFAULT_LABEL_BUFFER`] (see [`LanguageIndexBuilder::with_label_buffer`]);
+/// labels of older posts are ignored. Labels and profiles are applied by
+/// [`LanguageIndexBuilder::finish`], which gives the same index as
+/// [`build_language_index_with_detector`] over all the events as long as the
+/// buffer holds every labeled post.
+pub struct LanguageIndexBuilder<'d> {
+    config: IndexConfig,
+    detector: &'d dyn LanguageDetector,
+
+    batches: u32,
+    events_processed: u32,
+    since: Option<Timestamp>,
+    until: Timestamp,
+
+    /// Interned authors of content events, with the languages of their
+    /// posts counted so far
+    authors: Vec<PubkeyHex>,
+    author_ids: HashMap<PubkeyHex, u32>,
+    author_languages: Vec<UserLanguages>,
+    counts: PostCounts,
+
+    /// Interned detected and labeled languages
+    languages: Vec<LanguageCode>,
+    language_ids: HashMap<LanguageCode, u16>,
+
+    /// Recent posts without a label of their own, while labels are used;
+    /// ids oldest first, at most `label_buffer`
+    labelable_posts: HashMap<PostId, LabelablePost>,
+    labelable_order: VecDeque<PostId>,
+    label_buffer: usize,
+    /// Kind 1985 events, without content
+    label_events: Vec<NostrEvent>,
+    /// Latest kind 0 event of each author
+    profiles: HashMap<PubkeyHex, NostrEvent>,
+
+    validator: Option<EventValidator>,
+}
+
+impl LanguageIndexBuilder<'static> {
+    /// Builder using the default detector
+    pub fn new(config: IndexConfig) -> Self {
+        Self::with_detector(config, &DEFAULT_DETECTOR)
+    }
+}
+
+impl<'d> LanguageIndexBuilder<'d> {
+    pub fn with_detector(config: IndexConfig, detector: &'d dyn LanguageDetector) -> Self {
+        Self {
+            config,
+            detector,
+            batches: 0,
+            events_processed: 0,
+            since: None,
+            until: 0,
+            authors: Vec::new(),
+            author_ids: HashMap::new(),
+            author_languages: Vec::new(),
+            counts: PostCounts::default(),
+            languages: Vec::new(),
+            language_ids: HashMap::new(),
+            labelable_posts: HashMap::new(),
+            labelable_order: VecDeque::new(),
+            label_buffer: DEFAULT_LABEL_BUFFER,
+            label_events: Vec::new(),
+            profiles: HashMap::new(),
+            validator: None,
+        }
+    }
+
+    /// Check every pushed event with `validator` first; rejected events are
+    /// not processed and do not count toward `config.max_events`
+    pub fn with_validation(mut self, validator: EventValidator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Keep at most `posts` unlabeled posts for kind 1985 labels of later
+    /// batches instead of [`DEFAULT_LABEL_BUFFER`]
+    pub fn with_label_buffer(mut self, posts: usize) -> Self {
+        self.label_buffer = posts;
+        self
+    }
+
+    /// Detect the content events of `events` and keep what
+    /// [`LanguageIndexBuilder::finish`] needs. Events past
+    /// `config.max_events` are ignored.
+    pub fn push_batch(&mut self, events: &[NostrEvent]) {
+        self.batches += 1;
+
+        for event in events {
+            if self
+                .config
+                .max_events
+                .is_some_and(|max_events| self.events_processed >= max_events)
+            {
+                break;
+            }
+            if let Some(validator) = &mut self.validator {
+                if !validator.accept(event) {
+                    continue;
+                }
+            }
+            self.events_processed += 1;
+            self.since = Some(
+                self.since
+                    .map_or(event.created_at, |s| s.min(event.created_at)),
+            );
+            self.until = self.until.max(event.created_at);
+
+            match event.kind {
+                LABEL_EVENT_KIND => {
+                    if self.config.label_mode != LabelMode::Ignore {
+                        self.label_events.push(NostrEvent {
+                            content: String::new(),
+                            ..event.clone()
+                        });
+                    }
+                }
+                METADATA_EVENT_KIND => {
+                    if self.config.profile_weight > 0.0 {
+                        match self.profiles.get(&event.pubkey) {
+                            Some(current) if current.created_at >= event.created_at => {}
+                            _ => {
+                                self.profiles.insert(event.pubkey.clone(), event.clone());
+                            }
+                        }
+                    }
+                }
+                _ => self.push_post(event),
+            }
+        }
+    }
+
+    fn push_post(&mut self, event: &NostrEvent) {
+        let label = match self.config.label_mode {
+            LabelMode::Ignore => None,
+            _ => event_language_label(event),
+        };
+
+        // A kind 1985 label may still arrive in a later batch, so only an
+        // event's own label lets `Prefer` skip detection
+        let detected = if self.config.label_mode == LabelMode::Prefer && label.is_some() {
+            Detected::Skipped
+        } else {
+            self.detect(event.get_content())
+        };
+
+        let author = match self.author_ids.get(&event.pubkey) {
+            Some(&author) => author,
+            None => {
+                let author = self.authors.len() as u32;
+                self.authors.push(event.pubkey.clone());
+                self.author_ids.insert(event.pubkey.clone(), author);
+                self.author_languages.push(UserLanguages::new());
+                author
+            }
+        };
+
+        let label = label.map(|language| self.language_id(language));
+        self.record_post(author, event.get_created_at(), label, detected);
+
+        // Only events without a label of their own can be labeled later
+        if self.config.label_mode != LabelMode::Ignore && label.is_none() {
+            self.buffer_post(
+                PostId::new(&event.id),
+                LabelablePost {
+                    author,
+                    created_at: event.get_created_at(),
+                    detected,
+                },
+            );
+        }
+    }
+
+    /// Keep `post` for labels of later batches, dropping the oldest post
+    /// once the buffer is full
+    fn buffer_post(&mut self, id: PostId, post: LabelablePost) {
+        if self.label_buffer == 0 || self.labelable_posts.contains_key(&id) {
+            return;
+        }
+        if self.labelable_order.len() >= self.label_buffer {
+            if let Some(oldest) = self.labelable_order.pop_front() {
+                self.labelable_posts.remove(&oldest);
+            }
+        }
+        self.labelable_order.push_back(id.clone());
+        self.labelable_posts.insert(id, post);
+    }
+
+    /// Posts kept for kind 1985 labels of later batches
+    pub fn buffered_posts(&self) -> usize {
+        self.labelable_posts.len()
+    }
+
+    /// Count a content event toward its author's languages. Under
+    /// `LabelMode::Prefer` a label replaces detection; under `Blend` the
+    /// event counts once, as a post of the label, and a detection the label
+    /// overrules is only a signal.
+    fn record_post(
+        &mut self,
+        author: u32,
+        created_at: Timestamp,
+        label: Option<u16>,
+        detected: Detected,
+    ) {
+        let max_langs = self.config.max_langs_per_user;
+        let user_langs = &mut self.author_languages[author as usize];
+        let counts = &mut self.counts;
+
+        if let (LabelMode::Prefer, Some(id)) = (self.config.label_mode, label) {
+            user_langs.add_post(
+                self.languages[id as usize].clone(),
+                LABEL_CONFIDENCE,
+                created_at,
+                max_langs,
+            );
+            counts.labeled += 1;
+            counts.with_language += 1;
+            return;
+        }
+
+        let detected = match detected {
+            Detected::Language(id, confidence) => Some((id, confidence)),
+            Detected::TooShort => {
+                counts.too_short += 1;
+                None
+            }
+            Detected::Skipped | Detected::Undetermined => None,
+        };
+
+        // Only `LabelMode::Blend` gets here with a label
+        match (label, detected) {
+            (Some(id), detected) => {
+                user_langs.add_post(
+                    self.languages[id as usize].clone(),
+                    LABEL_CONFIDENCE,
+                    created_at,
+                    max_langs,
+                );
+                counts.labeled += 1;
+                counts.with_language += 1;
+                if let Some((detected_id, confidence)) = detected.filter(|&(d, _)| d != id) {
+                    user_langs.add_signal(
+                        self.languages[detected_id as usize].clone(),
+                        confidence,
+                        OVERRULED_DETECTION_WEIGHT,
+                        max_langs,
+                    );
+                    counts.label_conflicts += 1;
+                }
+            }
+            (None, Some((id, confidence))) => {
+                user_langs.add_post(
+                    self.languages[id as usize].clone(),
+                    confidence,
+                    created_at,
+                    max_langs,
+                );
+                counts.detected += 1;
+                counts.with_language += 1;
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Undo [`LanguageIndexBuilder::record_post`] of an unlabeled post, so a
+    /// label can be recorded in its place
+    fn unrecord_post(&mut self, author: u32, created_at: Timestamp, detected: Detected) {
+        let counts = &mut self.counts;
+        match detected {
+            Detected::Language(id, confidence) => {
+                self.author_languages[author as usize].remove_post(
+                    &self.languages[id as usize],
+                    confidence,
+                    created_at,
+                    self.config.max_langs_per_user,
+                );
+                counts.detected -= 1;
+                counts.with_language -= 1;
+            }
+            Detected::TooShort => counts.too_short -= 1,
+            Detected::Skipped | Detected::Undetermined => {}
+        }
+    }
+
+    /// Detect the language on the text left after stripping URLs, mentions, etc.
+    fn detect(&mut self, content: &str) -> Detected {
+        if content.is_empty() {
+            return Detected::Undetermined;
+        }
+        match detect_content_language(self.detector, content, self.config.min_content_chars) {
+            Ok(result) if result.preprocessed.remaining_chars < self.config.min_content_chars => {
+                Detected::TooShort
+            }
+            Ok(result) => match result.detection {
+                Some(Detection {
+                    language,
+                    confidence,
+                    ..
+                }) if confidence >= self.config.conf_thresh => {
+                    Detected::Language(self.language_id(language), confidence)
+                }
+                _ => Detected::Undetermined,
+            },
+            Err(e) => {
+                console_log!("Language detection error: {}", e);
+                Detected::Undetermined
+            }
+        }
+    }
+
+    fn language_id(&mut self, language: LanguageCode) -> u16 {
+        if let Some(&id) = self.language_ids.get(&language) {
+            return id;
+        }
+        let id = self.languages.len() as u16;
+        self.languages.push(language.clone());
+        self.language_ids.insert(language, id);
+        id
+    }
+
+    /// Batches, events and content authors so far
+    pub fn progress(&self) -> StreamProgress {
+        StreamProgress {
+            batches: self.batches,
+            events_processed: self.events_processed,
+            events_rejected: self
+                .validator
+                .as_ref()
+                .map_or(0, |validator| validator.report().rejected_total()),
+            users: self.authors.len() as u32,
+        }
+    }
+
+    /// Rejections so far, when validating
+    pub fn validation_report(&self) -> Option<&ValidationReport> {
+        self.validator.as_ref().map(EventValidator::report)
+    }
+
+    /// Apply labels and profiles and build the index. It covers the time
+    /// range of the pushed events.
+    pub fn finish(mut self) -> LanguageIndex {
+        console_log!(
+            "Processing {} events for language index",
+            self.events_processed
+        );
+
+        let labels = LanguageLabels::from_label_events(&self.label_events, |id| {
+            self.labelable_posts
+                .get(&PostId::new(id))
+                .map(|post| self.authors[post.author as usize].as_str())
+        });
+        let labeled_events: HashMap<PostId, LanguageCode> = labels
+            .events
+            .into_iter()
+            .map(|(id, language)| (PostId::new(&id), language))
+            .collect();
+
+        // Move labeled posts from their detection to their label, in the
+        // order they were pushed
+        for id in std::mem::take(&mut self.labelable_order) {
+            let (Some(language), Some(post)) =
+                (labeled_events.get(&id), self.labelable_posts.remove(&id))
+            else {
+                continue;
+            };
+            let label = self.language_id(language.clone());
+            self.unrecord_post(post.author, post.created_at, post.detected);
+            self.record_post(post.author, post.created_at, Some(label), post.detected);
+        }
+
+        let config = &self.config;
+        let mut index = LanguageIndex::new(self.since.unwrap_or(0), self.until);
+        index.events_processed = self.events_processed;
+        index.events_with_language = self.counts.with_language;
+        index.events_too_short = self.counts.too_short;
+        index.events_labeled = self.counts.labeled;
+        index.events_detected = self.counts.detected;
+        index.label_conflicts = self.counts.label_conflicts;
+        index.detector = self.detector.id();
+        index.user_languages = self
+            .authors
+            .into_iter()
+            .zip(self.author_languages)
+            .filter(|(_, user_langs)| !user_langs.evidence.is_empty())
+            .collect();
+
+        // Languages users declared for themselves in kind 1985 labels: a
+        // signal, not posts in the language
+        for (pubkey, declared) in labels.users.iter() {
+            let user_langs = index.user_languages.entry(pubkey.clone()).or_default();
+            for (language, _) in declared {
+                user_langs.add_signal(
+                    language.clone(),
+                    LABEL_CONFIDENCE,
+                    SELF_LABEL_WEIGHT,
+                    config.max_langs_per_user,
+                );
+                index.self_labels += 1;
+            }
+        }
+
+        // Profile text and NIP-05 domains as a weighted prior
+        for (pubkey, event) in self.profiles.iter() {
+            let hints = ProfileMetadata::parse(event.get_content()).and_then(|metadata| {
+                profile_hints(
+                    self.detector,
+                    &metadata,
+                    config.conf_thresh,
+                    config.min_content_chars,
+                )
+            });
+            let hints = match hints {
+                Ok(hints) if !hints.is_empty() => hints,
+                Ok(_) => continue,
+                Err(e) => {
+                    console_log!("Skipping profile of {}: {}", pubkey, e);
+                    continue;
+                }
+            };
+            let user_langs = index.user_languages.entry(pubkey.clone()).or_default();
+            for hint in hints {
+                user_langs.add_prior(
+                    hint.language,
+                    hint.confidence,
+                    config.profile_weight,
+                    config.max_langs_per_user,
+                );
+            }
+            index.profiles_used += 1;
+        }
+
+        index.apply_eligibility(&config.eligibility);
+
+        console_log!(
+            "Language index: {} users, {} events with language ({} labeled, {} detected), {} too short",
+            index.user_languages.len(),
+            index.events_with_language,
+            index.events_labeled,
+            index.events_detected,
+            index.events_too_short
+        );
+
+        index
+    }
+}
+
+/// Extend `index` with the events of `since..=until`, e.g. yesterday's index
+/// with today's events. Events already covered by the index are skipped, so
+/// `since` may overlap it; a gap after `index.until` is handled per
+/// `gap_policy` and returned as in [`LanguageIndex::merge`]. The index must
+/// have been built with the default detector.
+pub fn extend_language_index(
+    index: &mut LanguageIndex,
+    events: &[NostrEvent],
+    since: Timestamp,
+    until: Timestamp,
+    config: &IndexConfig,
+    gap_policy: GapPolicy,
+) -> Result<Option<(Timestamp, Timestamp)>, AnalyticsError> {
+    let since = if index.is_empty() {
+        Some(since)
+    } else {
+        index.until.checked_add(1).map(|next| since.max(next))
+    };
+    let Some(since) = since.filter(|&since| since <= until) else {
+        console_log!("Index already covers ..={}, nothing to extend", index.until);
+        return Ok(None);
+    };
+
+    let batch: Vec<NostrEvent> = events
+        .iter()
+        .filter(|e| e.created_at >= since && e.created_at <= until)
+        .cloned()
+        .collect();
+    let mut extension = build_language_index(&batch, config);
+    extension.since = since;
+    extension.until = until;
+
+    index.merge(extension, config, gap_policy)
+}
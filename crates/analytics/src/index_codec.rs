@@ -0,0 +1,495 @@
+// Compact binary encoding of the language index
+//
+// JSON spells every pubkey as 64 hex characters and every language code and
+// confidence as text, which adds up for hundreds of thousands of users. The
+// binary form stores raw 32-byte pubkeys, language codes once in a table,
+// confidences as u16 and counts as varints, and ends with a CRC-32 so a
+// truncated or corrupted blob (e.g. from IndexedDB) is rejected instead of
+// silently misread.
+//
+// Layout, little-endian:
+//
+//   magic "NLIX" | version u8 | flags u8
+//   built_at u64 | since u64 | until u64
+//   counters: 8 varints (events_processed .. profiles_used)
+//   detector: varint length + UTF-8
+//   gaps: varint count, (since, until) varint pairs
+//   languages: varint count, (u8 length + ASCII code) each
+//   users: varint count, each
+//     pubkey [u8; 32] | varint n | n x (varint language, u16 confidence)
+//     evidence block (with FLAG_EVIDENCE)
+//   pending: varint count, each pubkey [u8; 32] + evidence block
+//     (with FLAG_EVIDENCE; otherwise always 0)
+//   crc32 u32 of everything before it
+//
+// Evidence block: varint count, each
+//   varint language | varint posts | f32 confidence_sum
+//   varint first_seen + 1 (0 = none) | varint last_seen + 1 (0 = none)
+//   f32 prior | f32 prior_confidence_sum
+//   f32 signal | f32 signal_confidence_sum
+//   varint days, (varint day delta, varint posts) each
+
+use crate::error::AnalyticsError;
+use crate::types::{
+    LanguageCode, LanguageEvidence, LanguageIndex, PubkeyHex, Timestamp, UserLanguages,
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// First bytes of an encoded index
+pub const INDEX_MAGIC: [u8; 4] = *b"NLIX";
+
+/// Encoding version written by [`LanguageIndex::to_bytes`]
+pub const INDEX_ENCODING_VERSION: u8 = 3;
+
+/// The blob carries per-language evidence and pending users
+const FLAG_EVIDENCE: u8 = 0b0000_0001;
+
+/// Steps of a quantized confidence (0.0-1.0 in u16)
+const CONFIDENCE_STEPS: f32 = u16::MAX as f32;
+
+/// Whether `bytes` starts like an encoded index (as opposed to JSON)
+pub fn is_encoded_index(bytes: &[u8]) -> bool {
+    bytes.starts_with(&INDEX_MAGIC)
+}
+
+impl LanguageIndex {
+    /// Encode in the compact binary form. Without `include_evidence` only the
+    /// ranked languages are kept: enough for lifetime-eligibility metrics,
+    /// but not for period eligibility or extending the index.
+    ///
+    /// Fails on a pubkey that is not 32 bytes of hex.
+    pub fn to_bytes(&self, include_evidence: bool) -> Result<Vec<u8>, AnalyticsError> {
+        let mut users: Vec<(&PubkeyHex, &UserLanguages)> = self.user_languages.iter().collect();
+        users.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let mut pending: Vec<(&PubkeyHex, &UserLanguages)> = if include_evidence {
+            self.pending.iter().collect()
+        } else {
+            Vec::new()
+        };
+        pending.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+        let mut languages: Vec<&str> = users
+            .iter()
+            .chain(&pending)
+            .flat_map(|(_, user_langs)| {
+                let evidence = include_evidence
+                    .then_some(user_langs.evidence.keys())
+                    .into_iter()
+                    .flatten();
+                user_langs.languages.keys().chain(evidence)
+            })
+            .map(String::as_str)
+            .collect();
+        languages.sort_unstable();
+        languages.dedup();
+        let language_ids: HashMap<&str, u64> = languages
+            .iter()
+            .enumerate()
+            .map(|(i, &lang)| (lang, i as u64))
+            .collect();
+
+        let mut writer = Writer::default();
+        writer.bytes(&INDEX_MAGIC);
+        writer.u8(INDEX_ENCODING_VERSION);
+        writer.u8(if include_evidence { FLAG_EVIDENCE } else { 0 });
+        writer.u64(self.built_at);
+        writer.u64(self.since);
+        writer.u64(self.until);
+        for counter in [
+            self.events_processed,
+            self.events_with_language,
+            self.events_too_short,
+            self.events_labeled,
+            self.self_labels,
+            self.events_detected,
+            self.label_conflicts,
+            self.profiles_used,
+        ] {
+            writer.varint(counter as u64);
+        }
+        writer.str(&self.detector);
+
+        writer.varint(self.gaps.len() as u64);
+        for &(since, until) in &self.gaps {
+            writer.varint(since);
+            writer.varint(until);
+        }
+
+        writer.varint(languages.len() as u64);
+        for lang in &languages {
+            let len = u8::try_from(lang.len()).map_err(|_| {
+                AnalyticsError::InvalidParameter(format!("Language code too long: {}", lang))
+            })?;
+            writer.u8(len);
+            writer.bytes(lang.as_bytes());
+        }
+
+        writer.varint(users.len() as u64);
+        for (pubkey, user_langs) in &users {
+            writer.pubkey(pubkey)?;
+            let mut ranked: Vec<(&LanguageCode, &f32)> = user_langs.languages.iter().collect();
+            ranked.sort_unstable_by(|a, b| a.0.cmp(b.0));
+            writer.varint(ranked.len() as u64);
+            for (lang, &confidence) in ranked {
+                writer.varint(language_ids[lang.as_str()]);
+                writer.u16(quantize(confidence));
+            }
+            if include_evidence {
+                writer.evidence(&user_langs.evidence, &language_ids);
+            }
+        }
+
+        writer.varint(pending.len() as u64);
+        for (pubkey, user_langs) in &pending {
+            writer.pubkey(pubkey)?;
+            writer.evidence(&user_langs.evidence, &language_ids);
+        }
+
+        let checksum = crc32(&writer.buf);
+        writer.bytes(&checksum.to_le_bytes());
+        Ok(writer.buf)
+    }
+
+    /// Decode a blob written by [`LanguageIndex::to_bytes`]. Confidences come
+    /// back rounded to 1/65535.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AnalyticsError> {
+        if !is_encoded_index(bytes) {
+            return Err(corrupt("not an encoded language index"));
+        }
+        let Some((body, checksum)) = bytes.split_last_chunk::<4>() else {
+            return Err(corrupt("truncated"));
+        };
+        if crc32(body) != u32::from_le_bytes(*checksum) {
+            return Err(corrupt("checksum mismatch"));
+        }
+
+        let mut reader = Reader {
+            buf: body,
+            pos: INDEX_MAGIC.len(),
+        };
+        let version = reader.u8()?;
+        if version != INDEX_ENCODING_VERSION {
+            return Err(AnalyticsError::Serialization(format!(
+                "Unsupported language index encoding version {} (expected {})",
+                version, INDEX_ENCODING_VERSION
+            )));
+        }
+        let flags = reader.u8()?;
+        let has_evidence = flags & FLAG_EVIDENCE != 0;
+
+        let built_at = reader.u64()?;
+        let since = reader.u64()?;
+        let until = reader.u64()?;
+        let mut index = LanguageIndex::new(since, until);
+        index.built_at = built_at;
+        index.events_processed = reader.counter()?;
+        index.events_with_language = reader.counter()?;
+        index.events_too_short = reader.counter()?;
+        index.events_labeled = reader.counter()?;
+        index.self_labels = reader.counter()?;
+        index.events_detected = reader.counter()?;
+        index.label_conflicts = reader.counter()?;
+        index.profiles_used = reader.counter()?;
+        index.detector = reader.str()?;
+
+        let gaps = reader.len()?;
+        for _ in 0..gaps {
+            let gap = (reader.varint()?, reader.varint()?);
+            index.gaps.push(gap);
+        }
+
+        let language_count = reader.len()?;
+        let mut languages = Vec::with_capacity(language_count);
+        for _ in 0..language_count {
+            let len = reader.u8()? as usize;
+            let code = std::str::from_utf8(reader.take(len)?)
+                .map_err(|_| corrupt("language code is not UTF-8"))?;
+            languages.push(code.to_string());
+        }
+
+        let users = reader.len()?;
+        index.user_languages.reserve(users);
+        for _ in 0..users {
+            let pubkey = reader.pubkey()?;
+            let mut user_langs = UserLanguages::new();
+            let ranked = reader.len()?;
+            for _ in 0..ranked {
+                let lang = reader.language(&languages)?;
+                let confidence = reader.u16()? as f32 / CONFIDENCE_STEPS;
+                user_langs.languages.insert(lang, confidence);
+            }
+            if has_evidence {
+                user_langs.evidence = reader.evidence(&languages)?;
+            }
+            index.user_languages.insert(pubkey, user_langs);
+        }
+
+        let pending = reader.len()?;
+        for _ in 0..pending {
+            let pubkey = reader.pubkey()?;
+            let user_langs = UserLanguages {
+                languages: HashMap::new(),
+                evidence: reader.evidence(&languages)?,
+            };
+            index.pending.insert(pubkey, user_langs);
+        }
+
+        if reader.pos != body.len() {
+            return Err(corrupt("trailing bytes"));
+        }
+        Ok(index)
+    }
+}
+
+fn quantize(confidence: f32) -> u16 {
+    (confidence.clamp(0.0, 1.0) * CONFIDENCE_STEPS).round() as u16
+}
+
+fn corrupt(reason: &str) -> AnalyticsError {
+    AnalyticsError::Serialization(format!("Invalid encoded language index: {}", reason))
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// Unsigned LEB128
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn str(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes(value.as_bytes());
+    }
+
+    fn pubkey(&mut self, pubkey: &str) -> Result<(), AnalyticsError> {
+        let invalid = || {
+            AnalyticsError::InvalidParameter(format!("Pubkey is not 32 bytes of hex: {}", pubkey))
+        };
+        if pubkey.len() != 64 || !pubkey.is_ascii() {
+            return Err(invalid());
+        }
+        for pair in pubkey.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            let byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+            self.buf.push(byte);
+        }
+        Ok(())
+    }
+
+    fn evidence(
+        &mut self,
+        evidence: &HashMap<LanguageCode, LanguageEvidence>,
+        language_ids: &HashMap<&str, u64>,
+    ) {
+        let mut entries: Vec<(&LanguageCode, &LanguageEvidence)> = evidence.iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        self.varint(entries.len() as u64);
+        for (lang, evidence) in entries {
+            self.varint(language_ids[lang.as_str()]);
+            self.varint(evidence.posts as u64);
+            self.f32(evidence.confidence_sum);
+            self.varint(evidence.first_seen.map_or(0, |t| t + 1));
+            self.varint(evidence.last_seen.map_or(0, |t| t + 1));
+            self.f32(evidence.prior);
+            self.f32(evidence.prior_confidence_sum);
+            self.f32(evidence.signal);
+            self.f32(evidence.signal_confidence_sum);
+            self.varint(evidence.daily_posts.len() as u64);
+            let mut previous = 0;
+            for (&day, &posts) in &evidence.daily_posts {
+                self.varint((day - previous) as u64);
+                self.varint(posts as u64);
+                previous = day;
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], AnalyticsError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| corrupt("truncated"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], AnalyticsError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, AnalyticsError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, AnalyticsError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, AnalyticsError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, AnalyticsError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn varint(&mut self) -> Result<u64, AnalyticsError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(corrupt("varint too long"))
+    }
+
+    fn counter(&mut self) -> Result<u32, AnalyticsError> {
+        u32::try_from(self.varint()?).map_err(|_| corrupt("count out of range"))
+    }
+
+    /// A length, bounded by the bytes left so a corrupt count cannot
+    /// trigger a huge allocation
+    fn len(&mut self) -> Result<usize, AnalyticsError> {
+        let len = self.varint()?;
+        if len > (self.buf.len() - self.pos) as u64 {
+            return Err(corrupt("length out of range"));
+        }
+        Ok(len as usize)
+    }
+
+    fn str(&mut self) -> Result<String, AnalyticsError> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| corrupt("string is not UTF-8"))
+    }
+
+    fn pubkey(&mut self) -> Result<PubkeyHex, AnalyticsError> {
+        Ok(self
+            .take(32)?
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    fn language(&mut self, languages: &[LanguageCode]) -> Result<LanguageCode, AnalyticsError> {
+        let id = self.varint()?;
+        languages
+            .get(id as usize)
+            .cloned()
+            .ok_or_else(|| corrupt("unknown language id"))
+    }
+
+    fn timestamp(&mut self) -> Result<Option<Timestamp>, AnalyticsError> {
+        Ok(self.varint()?.checked_sub(1))
+    }
+
+    fn evidence(
+        &mut self,
+        languages: &[LanguageCode],
+    ) -> Result<HashMap<LanguageCode, LanguageEvidence>, AnalyticsError> {
+        let count = self.len()?;
+        let mut evidence = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let lang = self.language(languages)?;
+            let posts = self.counter()?;
+            let confidence_sum = self.f32()?;
+            let first_seen = self.timestamp()?;
+            let last_seen = self.timestamp()?;
+            let prior = self.f32()?;
+            let prior_confidence_sum = self.f32()?;
+            let signal = self.f32()?;
+            let signal_confidence_sum = self.f32()?;
+            let days = self.len()?;
+            let mut daily_posts = BTreeMap::new();
+            let mut day = 0u32;
+            for _ in 0..days {
+                day = day
+                    .checked_add(self.counter()?)
+                    .ok_or_else(|| corrupt("day out of range"))?;
+                daily_posts.insert(day, self.counter()?);
+            }
+            evidence.insert(
+                lang,
+                LanguageEvidence {
+                    posts,
+                    confidence_sum,
+                    first_seen,
+                    last_seen,
+                    daily_posts,
+                    prior,
+                    prior_confidence_sum,
+                    signal,
+                    signal_confidence_sum,
+                },
+            );
+        }
+        Ok(evidence)
+    }
+}
+
+/// CRC-32 (IEEE 802.3, as in zlib and PNG)
+fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
@@ -0,0 +1,52 @@
+// Pubkey interning
+//
+// Maps each hex pubkey to a dense u32 id once per run, so per-day activity can
+// be kept as bitmaps of ids instead of sets of 64-character strings.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Dense ids (`0..len`) for the pubkeys of one run, in order of first sight.
+/// Owns the pubkeys, so collectors can outlive the index they were built
+/// from; each pubkey is stored once.
+#[derive(Clone, Debug, Default)]
+pub struct OwnedPubkeyInterner {
+    ids: HashMap<Arc<str>, u32>,
+    pubkeys: Vec<Arc<str>>,
+}
+
+impl OwnedPubkeyInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Id of `pubkey`, assigning the next one on first sight
+    pub fn intern(&mut self, pubkey: &str) -> u32 {
+        if let Some(&id) = self.ids.get(pubkey) {
+            return id;
+        }
+        let id = self.pubkeys.len() as u32;
+        let pubkey: Arc<str> = Arc::from(pubkey);
+        self.ids.insert(Arc::clone(&pubkey), id);
+        self.pubkeys.push(pubkey);
+        id
+    }
+
+    /// Id of an already interned `pubkey`
+    pub fn get(&self, pubkey: &str) -> Option<u32> {
+        self.ids.get(pubkey).copied()
+    }
+
+    /// Pubkey of `id`
+    pub fn resolve(&self, id: u32) -> Option<&str> {
+        self.pubkeys.get(id as usize).map(|pubkey| &**pubkey)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pubkeys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pubkeys.is_empty()
+    }
+}
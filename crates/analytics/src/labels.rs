@@ -0,0 +1,187 @@
+// NIP-32 language labels
+//
+// Clients can tag a note with its language (`["L","ISO-639-1"]`,
+// `["l","ja","ISO-639-1"]`) and users can publish kind 1985 label events
+// about their own notes or themselves. Explicit labels beat detection on short
+// or mixed-script text.
+
+use crate::error::AnalyticsError;
+use crate::relay_client::NostrEvent;
+use crate::types::{EventId, LanguageCode, PubkeyHex, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// NIP-32 label event kind
+pub const LABEL_EVENT_KIND: u16 = 1985;
+
+/// NIP-32 namespace of ISO 639-1 language labels
+pub const ISO_639_1_NAMESPACE: &str = "ISO-639-1";
+
+/// Confidence recorded for a labeled language
+pub const LABEL_CONFIDENCE: f32 = 1.0;
+
+/// Post-equivalent weight of a language a user declares in a kind 1985
+/// self-label
+pub const SELF_LABEL_WEIGHT: f32 = 1.0;
+
+/// Post-equivalent weight of a detection that a label overrules under
+/// [`LabelMode::Blend`]; the event itself counts once, as a post of the
+/// labeled language
+pub const OVERRULED_DETECTION_WEIGHT: f32 = 0.5;
+
+/// How explicit language labels combine with detection
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelMode {
+    /// Use detection only
+    Ignore,
+    /// A labeled event takes the label's language; detection is skipped
+    #[default]
+    Prefer,
+    /// Record the label, plus the detected language when it differs
+    Blend,
+}
+
+impl LabelMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LabelMode::Ignore => "ignore",
+            LabelMode::Prefer => "prefer",
+            LabelMode::Blend => "blend",
+        }
+    }
+}
+
+impl FromStr for LabelMode {
+    type Err = AnalyticsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(LabelMode::Ignore),
+            "prefer" => Ok(LabelMode::Prefer),
+            "blend" => Ok(LabelMode::Blend),
+            other => Err(AnalyticsError::InvalidParameter(format!(
+                "Unknown label mode: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// ISO 639-1 code of a label value such as `ja` or `en-US`
+fn normalize_language(value: &str) -> Option<LanguageCode> {
+    let code = value.split(['-', '_']).next()?;
+    if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        Some(code.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+/// Languages in `["l", <code>, "ISO-639-1"]` tags
+fn language_labels(tags: &[Vec<String>]) -> Vec<LanguageCode> {
+    let mut languages: Vec<LanguageCode> = tags
+        .iter()
+        .filter(|tag| {
+            tag.len() >= 3 && tag[0] == "l" && tag[2].eq_ignore_ascii_case(ISO_639_1_NAMESPACE)
+        })
+        .filter_map(|tag| normalize_language(&tag[1]))
+        .collect();
+    languages.sort();
+    languages.dedup();
+    languages
+}
+
+/// Language an event is labeled with in its own tags: a NIP-32
+/// `["l", <code>, "ISO-639-1"]` tag, or a plain `["lang", <code>]` tag.
+/// Events with several different labels are ambiguous and return `None`.
+pub fn event_language_label(event: &NostrEvent) -> Option<LanguageCode> {
+    let mut languages = language_labels(&event.tags);
+    languages.extend(
+        event
+            .tags
+            .iter()
+            .filter(|tag| tag.len() >= 2 && tag[0] == "lang")
+            .filter_map(|tag| normalize_language(&tag[1])),
+    );
+    languages.sort();
+    languages.dedup();
+    match languages.as_slice() {
+        [language] => Some(language.clone()),
+        _ => None,
+    }
+}
+
+/// Language labels from kind 1985 events. Only self-labels count: the label's
+/// author must be the author of the labeled event (`e`) or the labeled
+/// pubkey (`p`).
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LanguageLabels {
+    /// Labeled events
+    pub events: HashMap<EventId, LanguageCode>,
+
+    /// Languages users declared for themselves, each once with the timestamp
+    /// of its latest label
+    pub users: HashMap<PubkeyHex, Vec<(LanguageCode, Timestamp)>>,
+
+    /// Label events that contributed at least one label
+    pub label_events: u32,
+}
+
+impl LanguageLabels {
+    /// Collect self-labels from the kind 1985 events in `events`
+    pub fn from_events(events: &[NostrEvent]) -> Self {
+        let authors: HashMap<&str, &str> = events
+            .iter()
+            .map(|e| (e.id.as_str(), e.pubkey.as_str()))
+            .collect();
+        Self::from_label_events(events, |id| authors.get(id).copied())
+    }
+
+    /// Collect self-labels from the kind 1985 events in `events`, looking up
+    /// the author of a labeled event with `author_of` (for when the labeled
+    /// events are no longer at hand)
+    pub fn from_label_events<'a>(
+        events: impl IntoIterator<Item = &'a NostrEvent>,
+        author_of: impl Fn(&str) -> Option<&'a str>,
+    ) -> Self {
+        let mut labels = Self::default();
+
+        for event in events.into_iter().filter(|e| e.kind == LABEL_EVENT_KIND) {
+            let languages = language_labels(&event.tags);
+            let [language] = languages.as_slice() else {
+                continue;
+            };
+
+            let mut used = false;
+            for tag in event.tags.iter().filter(|tag| tag.len() >= 2) {
+                match tag[0].as_str() {
+                    "e" if author_of(&tag[1]) == Some(event.pubkey.as_str()) => {
+                        labels.events.insert(tag[1].clone(), language.clone());
+                        used = true;
+                    }
+                    "p" if tag[1] == event.pubkey => {
+                        let declared = labels.users.entry(tag[1].clone()).or_default();
+                        match declared.iter_mut().find(|(lang, _)| lang == language) {
+                            Some((_, at)) => *at = (*at).max(event.created_at),
+                            None => declared.push((language.clone(), event.created_at)),
+                        }
+                        used = true;
+                    }
+                    _ => {}
+                }
+            }
+            if used {
+                labels.label_events += 1;
+            }
+        }
+
+        labels
+    }
+
+    /// Label of `event`: its own tags first, then kind 1985 labels
+    pub fn language_of(&self, event: &NostrEvent) -> Option<LanguageCode> {
+        event_language_label(event).or_else(|| self.events.get(&event.id).cloned())
+    }
+}
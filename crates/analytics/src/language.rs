@@ -1,6 +1,315 @@
 use crate::error::AnalyticsError;
+use crate::script::{CjkScriptDetector, ScriptDecision};
+use crate::types::LanguageCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use whatlang::{detect, Lang};
 
+/// whatlang release the default detector is built against
+pub const WHATLANG_VERSION: &str = "0.16";
+
+/// Default minimum characters left after preprocessing for detection to run
+pub const DEFAULT_MIN_CONTENT_CHARS: u32 = 5;
+
+/// Bech32 prefixes of NIP-19 entities
+const NIP19_PREFIXES: [&str; 7] = [
+    "npub1",
+    "nsec1",
+    "note1",
+    "nevent1",
+    "nprofile1",
+    "naddr1",
+    "nrelay1",
+];
+
+/// Lightning invoice / LNURL prefixes (BOLT11 networks first)
+const LIGHTNING_PREFIXES: [&str; 5] = ["lnbcrt", "lnbc", "lntbs", "lntb", "lnurl"];
+
+/// Content with Nostr-specific tokens removed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PreprocessedContent {
+    /// Text left for language detection
+    pub text: String,
+
+    /// Characters in the original content
+    pub original_chars: u32,
+
+    /// Non-whitespace characters left in `text`
+    pub remaining_chars: u32,
+}
+
+impl PreprocessedContent {
+    /// Share of the original characters left (0.0-1.0)
+    pub fn remaining_ratio(&self) -> f32 {
+        if self.original_chars == 0 {
+            return 0.0;
+        }
+        self.remaining_chars as f32 / self.original_chars as f32
+    }
+}
+
+/// Strip tokens that skew detection on short notes: URLs (including image
+/// links), `nostr:` URIs and bare NIP-19 entities, hashtags, `:shortcode:`
+/// emoji and Lightning invoices
+pub fn preprocess_content(content: &str) -> PreprocessedContent {
+    let tokens: Vec<String> = content.split_whitespace().filter_map(clean_token).collect();
+    let text = tokens.join(" ");
+    let remaining_chars = text.chars().filter(|c| !c.is_whitespace()).count() as u32;
+
+    PreprocessedContent {
+        text,
+        original_chars: content.chars().filter(|c| !c.is_whitespace()).count() as u32,
+        remaining_chars,
+    }
+}
+
+/// The part of a whitespace-separated token worth detecting, if any
+fn clean_token(token: &str) -> Option<String> {
+    let mut token = token;
+
+    // URLs and nostr: URIs run to the end of the token; keep any text glued in front
+    if let Some(pos) = token.find("://") {
+        let scheme_start = token[..pos]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !c.is_ascii_alphabetic())
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        token = &token[..scheme_start];
+    }
+    if let Some(pos) = token.to_ascii_lowercase().find("nostr:") {
+        token = &token[..pos];
+    }
+    if let Some(pos) = token.to_ascii_lowercase().find("lightning:") {
+        token = &token[..pos];
+    }
+    // Hashtags
+    if let Some(pos) = token
+        .char_indices()
+        .find(|&(i, c)| c == '#' && token[i + 1..].starts_with(char::is_alphanumeric))
+        .map(|(i, _)| i)
+    {
+        token = &token[..pos];
+    }
+
+    let token = strip_shortcodes(token);
+    let core = token
+        .trim_matches(|c: char| !c.is_alphanumeric())
+        .to_ascii_lowercase();
+    if core.is_empty() || core.starts_with("www.") || is_bech32_like(&core) {
+        return None;
+    }
+    Some(token)
+}
+
+/// Remove `:shortcode:` emoji (names need at least one letter, so times like
+/// `10:30:00` survive)
+fn strip_shortcodes(token: &str) -> String {
+    let mut result = String::with_capacity(token.len());
+    let mut rest = token;
+    while let Some(start) = rest.find(':') {
+        let after = &rest[start + 1..];
+        let name_len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+'))
+            .unwrap_or(after.len());
+        let name = &after[..name_len];
+        if after[name_len..].starts_with(':') && name.chars().any(|c| c.is_ascii_alphabetic()) {
+            result.push_str(&rest[..start]);
+            rest = &after[name_len + 1..];
+        } else {
+            result.push_str(&rest[..start + 1]);
+            rest = after;
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Bare NIP-19 entity or Lightning invoice
+fn is_bech32_like(core: &str) -> bool {
+    let long_ascii = core.len() >= 20 && core.chars().all(|c| c.is_ascii_alphanumeric());
+    long_ascii
+        && NIP19_PREFIXES
+            .iter()
+            .chain(LIGHTNING_PREFIXES.iter())
+            .any(|prefix| core.starts_with(prefix))
+}
+
+/// Detected language of one text
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Detection {
+    pub language: LanguageCode,
+
+    /// Confidence (0.0-1.0)
+    pub confidence: f32,
+
+    /// Script analysis, when the detector runs one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<ScriptDecision>,
+}
+
+/// Per-event detection result
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ContentDetection {
+    pub preprocessed: PreprocessedContent,
+
+    /// `None` when too little text was left or nothing was detected
+    pub detection: Option<Detection>,
+}
+
+/// Preprocess `content` and detect its language. Detection is skipped when
+/// fewer than `min_chars` characters are left after preprocessing.
+pub fn detect_content_language(
+    detector: &dyn LanguageDetector,
+    content: &str,
+    min_chars: u32,
+) -> Result<ContentDetection, AnalyticsError> {
+    let preprocessed = preprocess_content(content);
+    let detection = if preprocessed.remaining_chars < min_chars.max(1) {
+        None
+    } else {
+        detector.detect_detailed(&preprocessed.text)?
+    };
+    Ok(ContentDetection {
+        preprocessed,
+        detection,
+    })
+}
+
+/// Language detector used to build the index. Its `name@version` is recorded
+/// in the `lang=` element of the kind 30080 `algo` tag.
+pub trait LanguageDetector {
+    fn name(&self) -> String;
+
+    fn version(&self) -> String;
+
+    /// ISO 639-1 code and confidence (0.0-1.0) of `text`
+    fn detect(&self, text: &str) -> Result<Option<(LanguageCode, f32)>, AnalyticsError>;
+
+    /// Like [`LanguageDetector::detect`], with any intermediate decisions
+    fn detect_detailed(&self, text: &str) -> Result<Option<Detection>, AnalyticsError> {
+        Ok(self.detect(text)?.map(|(language, confidence)| Detection {
+            language,
+            confidence,
+            script: None,
+        }))
+    }
+
+    /// Identifier recorded in the index and the `algo` tag
+    fn id(&self) -> String {
+        format!("{}@{}", self.name(), self.version())
+    }
+}
+
+/// Default detector: whatlang corrected by the CJK script stage
+pub fn default_detector() -> CjkScriptDetector<WhatlangDetector> {
+    CjkScriptDetector::new(WhatlangDetector)
+}
+
+/// Identifier of the default detector (`whatlang@0.16+cjk-script@1`)
+pub fn default_detector_id() -> String {
+    default_detector().id()
+}
+
+/// Default detector backed by whatlang
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WhatlangDetector;
+
+impl LanguageDetector for WhatlangDetector {
+    fn name(&self) -> String {
+        "whatlang".to_string()
+    }
+
+    fn version(&self) -> String {
+        WHATLANG_VERSION.to_string()
+    }
+
+    fn detect(&self, text: &str) -> Result<Option<(LanguageCode, f32)>, AnalyticsError> {
+        detect_language(text)
+    }
+}
+
+/// Detector that gives the same answer for any non-empty text
+/// (tests, or corpora whose language is already known)
+#[derive(Clone, Debug)]
+pub struct FixedLanguageDetector {
+    pub answer: Option<(LanguageCode, f32)>,
+}
+
+impl FixedLanguageDetector {
+    pub fn new(lang: &str, confidence: f32) -> Self {
+        Self {
+            answer: Some((lang.to_string(), confidence)),
+        }
+    }
+}
+
+impl LanguageDetector for FixedLanguageDetector {
+    fn name(&self) -> String {
+        "fixed".to_string()
+    }
+
+    fn version(&self) -> String {
+        match &self.answer {
+            Some((lang, _)) => lang.clone(),
+            None => "none".to_string(),
+        }
+    }
+
+    fn detect(&self, text: &str) -> Result<Option<(LanguageCode, f32)>, AnalyticsError> {
+        if text.trim().is_empty() {
+            return Ok(None);
+        }
+        Ok(self.answer.clone())
+    }
+}
+
+/// Combines several detectors: the language with the highest summed
+/// confidence wins, with the mean confidence across all members
+#[derive(Default)]
+pub struct EnsembleDetector {
+    pub detectors: Vec<Box<dyn LanguageDetector>>,
+}
+
+impl EnsembleDetector {
+    pub fn new(detectors: Vec<Box<dyn LanguageDetector>>) -> Self {
+        Self { detectors }
+    }
+}
+
+impl LanguageDetector for EnsembleDetector {
+    fn name(&self) -> String {
+        "ensemble".to_string()
+    }
+
+    /// Member ids joined with `+`
+    fn version(&self) -> String {
+        self.detectors
+            .iter()
+            .map(|detector| detector.id())
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+
+    fn id(&self) -> String {
+        format!("{}({})", self.name(), self.version())
+    }
+
+    fn detect(&self, text: &str) -> Result<Option<(LanguageCode, f32)>, AnalyticsError> {
+        let mut votes: HashMap<LanguageCode, f32> = HashMap::new();
+        for detector in &self.detectors {
+            if let Some((lang, confidence)) = detector.detect(text)? {
+                *votes.entry(lang).or_insert(0.0) += confidence;
+            }
+        }
+        let members = self.detectors.len().max(1) as f32;
+        Ok(votes
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+            .map(|(lang, total)| (lang, total / members)))
+    }
+}
+
 /// Detect language from text content
 /// Returns ISO 639-1 code and confidence (0.0-1.0)
 pub fn detect_language(text: &str) -> Result<Option<(String, f32)>, AnalyticsError> {
@@ -122,6 +431,67 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[test]
+    fn test_preprocess_strips_nostr_tokens() {
+        let content = "おはようございます！ https://example.com/cat.jpg nostr:npub1sg6plzptd64u62a878hep2kev88swjh3tw00gjsfl8f237lmu63q0uf63m #nostrasia :wave: lnbc2500u1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypq";
+        let preprocessed = preprocess_content(content);
+        assert_eq!(preprocessed.text, "おはようございます！");
+        assert_eq!(preprocessed.remaining_chars, 10);
+        assert!(preprocessed.remaining_ratio() < 0.1);
+    }
+
+    #[test]
+    fn test_preprocess_keeps_text_glued_to_tokens() {
+        let preprocessed =
+            preprocess_content("見てhttps://nostr.band これ#nostr 10:30:00 :smile:です");
+        assert_eq!(preprocessed.text, "見て これ 10:30:00 です");
+    }
+
+    #[test]
+    fn test_detect_content_skips_short_text() {
+        let result = detect_content_language(
+            &WhatlangDetector,
+            "#gm https://example.com/a.png",
+            DEFAULT_MIN_CONTENT_CHARS,
+        )
+        .unwrap();
+        assert_eq!(result.preprocessed.remaining_chars, 0);
+        assert!(result.detection.is_none());
+
+        let result = detect_content_language(
+            &WhatlangDetector,
+            "https://example.com こんにちは、これは日本語のテストメッセージです。",
+            DEFAULT_MIN_CONTENT_CHARS,
+        )
+        .unwrap();
+        assert_eq!(result.detection.unwrap().language, "ja");
+    }
+
+    #[test]
+    fn test_detector_ids() {
+        assert_eq!(WhatlangDetector.id(), "whatlang@0.16");
+        assert_eq!(FixedLanguageDetector::new("ja", 1.0).id(), "fixed@ja");
+
+        let ensemble = EnsembleDetector::new(vec![
+            Box::new(WhatlangDetector),
+            Box::new(FixedLanguageDetector::new("ja", 1.0)),
+        ]);
+        assert_eq!(ensemble.id(), "ensemble(whatlang@0.16+fixed@ja)");
+    }
+
+    #[test]
+    fn test_ensemble_detector_votes() {
+        let ensemble = EnsembleDetector::new(vec![
+            Box::new(FixedLanguageDetector::new("ja", 0.9)),
+            Box::new(FixedLanguageDetector::new("zh", 0.6)),
+            Box::new(FixedLanguageDetector::new("ja", 0.3)),
+        ]);
+        let (lang, confidence) = ensemble.detect("東京").unwrap().unwrap();
+        assert_eq!(lang, "ja");
+        assert!((confidence - 0.4).abs() < 1e-6);
+        assert!(ensemble.detect("  ").unwrap().is_none());
+    }
+
     #[test]
     fn test_detect_short() {
         let result = detect_language("Hi").unwrap();
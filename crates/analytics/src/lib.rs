@@ -1,19 +1,53 @@
 use wasm_bindgen::prelude::*;
 
 mod analytics;
+mod analytics_event;
+mod bitmap;
 mod error;
+mod evaluation;
+mod fetch_planner;
+mod growth;
+mod heatmap;
+mod hyperloglog;
+mod index;
+mod index_codec;
+mod interner;
+mod labels;
 mod language;
+mod metrics;
 mod nostr_utils;
+mod profile;
 mod relay_client;
+mod retention;
+mod script;
+mod timezone;
 mod types;
 mod utils;
+mod validation;
 
 pub use analytics::*;
+pub use analytics_event::*;
+pub use bitmap::*;
 pub use error::AnalyticsError;
+pub use evaluation::*;
+pub use fetch_planner::*;
+pub use growth::*;
+pub use heatmap::*;
+pub use hyperloglog::*;
+pub use index::*;
+pub use index_codec::*;
+pub use interner::*;
+pub use labels::*;
 pub use language::*;
+pub use metrics::*;
 pub use nostr_utils::*;
+pub use profile::*;
 pub use relay_client::*;
+pub use retention::*;
+pub use script::*;
+pub use timezone::*;
 pub use types::*;
+pub use validation::*;
 
 /// Initialize the analytics module with logging
 #[wasm_bindgen(start)]
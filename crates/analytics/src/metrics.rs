@@ -0,0 +1,898 @@
+use crate::bitmap::UserBitmap;
+use crate::error::AnalyticsError;
+use crate::hyperloglog::HyperLogLog;
+use crate::interner::OwnedPubkeyInterner;
+use crate::relay_client::NostrEvent;
+use crate::timezone::Zone;
+use crate::types::*;
+use crate::utils::console_log;
+use crate::validation::{EventValidator, ValidationReport};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::str::FromStr;
+
+/// Series key used for [`GroupBy::All`]
+pub const ALL_GROUP: &str = "all";
+
+/// Series key for events without a `client` tag
+pub const UNKNOWN_CLIENT: &str = "unknown";
+
+/// Dimension that splits activity into separate series.
+/// Only users eligible for any of `MetricsOptions.languages` are counted.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    /// One series keyed `all`
+    All,
+    /// One series per target language (users count toward each of their languages)
+    Language,
+    /// One series per event kind
+    Kind,
+    /// One series per NIP-89 `client` tag value
+    Client,
+}
+
+impl FromStr for GroupBy {
+    type Err = AnalyticsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(GroupBy::All),
+            "language" => Ok(GroupBy::Language),
+            "kind" => Ok(GroupBy::Kind),
+            "client" => Ok(GroupBy::Client),
+            other => Err(AnalyticsError::InvalidParameter(format!(
+                "Unknown group_by: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Sliding-window active users of everyone eligible for any of
+/// `options.languages`, one data point per day in `since..=until`
+pub fn compute_active_users(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &MetricsOptions,
+) -> Vec<MetricDataPoint> {
+    compute_metrics(events, index, options, GroupBy::All)
+        .remove(ALL_GROUP)
+        .unwrap_or_default()
+}
+
+/// Sliding-window active users computed separately for each of `options.languages`
+pub fn compute_active_users_by_language(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &MetricsOptions,
+) -> HashMap<LanguageCode, Vec<MetricDataPoint>> {
+    compute_metrics(events, index, options, GroupBy::Language)
+}
+
+/// Metrics engine: computes the sliding-window series of every group in a
+/// single pass over `events`.
+///
+/// `All` and `Language` always return a series for each requested key (zeros
+/// when nobody is active); `Kind` and `Client` return the groups seen.
+pub fn compute_metrics(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &MetricsOptions,
+    group_by: GroupBy,
+) -> HashMap<String, Vec<MetricDataPoint>> {
+    let activity = Activity::collect(events, index, options, group_by);
+    let results = activity.series(options);
+
+    console_log!(
+        "Computed {} metrics for {} groups",
+        options.counting,
+        results.len()
+    );
+
+    results
+}
+
+/// Series of one window size together with the options that produced it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WindowMetrics {
+    pub options: MetricsOptions,
+    pub series: HashMap<String, Vec<MetricDataPoint>>,
+}
+
+/// Ratio of a shorter window over a longer one (e.g. DAU/MAU) per group
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RatioMetrics {
+    pub numerator: MetricsOptions,
+    pub denominator: MetricsOptions,
+    pub series: HashMap<String, Vec<RatioDataPoint>>,
+}
+
+/// Several window sizes and their stickiness ratios
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StickinessMetrics {
+    /// One entry per window size, ascending
+    pub windows: Vec<WindowMetrics>,
+
+    /// One entry per (shorter, longer) pair of window sizes
+    pub ratios: Vec<RatioMetrics>,
+}
+
+/// Compute several window sizes (e.g. `[1, 7, 30]`) from a single activity
+/// pass, plus the ratio series of every shorter window over every longer one.
+/// `options.window_days` is replaced by each entry of `windows`.
+pub fn compute_stickiness(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &MetricsOptions,
+    group_by: GroupBy,
+    windows: &[u16],
+) -> StickinessMetrics {
+    let mut windows: Vec<u16> = windows.iter().copied().filter(|&w| w > 0).collect();
+    windows.sort_unstable();
+    windows.dedup();
+
+    let activity = Activity::collect(events, index, options, group_by);
+
+    let windows: Vec<WindowMetrics> = windows
+        .into_iter()
+        .map(|window_days| {
+            let options = MetricsOptions {
+                window_days,
+                ..options.clone()
+            };
+            let series = activity.series(&options);
+            WindowMetrics { options, series }
+        })
+        .collect();
+
+    let mut ratios = Vec::new();
+    for (i, numerator) in windows.iter().enumerate() {
+        for denominator in &windows[i + 1..] {
+            let series = numerator
+                .series
+                .iter()
+                .map(|(key, points)| {
+                    let totals = &denominator.series[key];
+                    let ratio_points = points
+                        .iter()
+                        .zip(totals)
+                        .map(|(p, total)| RatioDataPoint {
+                            epoch_day: p.epoch_day,
+                            ratio: (total.count > 0).then(|| p.count as f32 / total.count as f32),
+                        })
+                        .collect();
+                    (key.clone(), ratio_points)
+                })
+                .collect();
+            ratios.push(RatioMetrics {
+                numerator: numerator.options.clone(),
+                denominator: denominator.options.clone(),
+                series,
+            });
+        }
+    }
+
+    console_log!(
+        "Computed {} windows and {} ratios",
+        windows.len(),
+        ratios.len()
+    );
+
+    StickinessMetrics { windows, ratios }
+}
+
+/// Activity of every group, exact or sketched according to `options.counting`
+enum Activity {
+    Exact(Groups<UserBitmap>),
+    Approximate(Groups<HyperLogLog>),
+}
+
+impl Activity {
+    fn collect(
+        events: &[NostrEvent],
+        index: &LanguageIndex,
+        options: &MetricsOptions,
+        group_by: GroupBy,
+    ) -> Self {
+        match options.counting {
+            CountingMode::Exact => {
+                Activity::Exact(collect_activity(events, index, options, group_by))
+            }
+            CountingMode::Approximate { precision } => Activity::Approximate(collect_sets(
+                events,
+                index,
+                options,
+                group_by,
+                HyperLogLog::new(precision),
+            )),
+        }
+    }
+
+    /// Series of every group for `options` (window size and granularity)
+    fn series(&self, options: &MetricsOptions) -> HashMap<String, Vec<MetricDataPoint>> {
+        match self {
+            Activity::Exact(groups) => window_series(groups, options),
+            Activity::Approximate(groups) => sketch_series(groups, options),
+        }
+    }
+}
+
+/// Metrics engine fed in batches, e.g. one relay page at a time, so the
+/// caller can drop each batch once pushed. Gives the same series as
+/// [`compute_metrics`] over all the pushed events.
+pub struct MetricsBuilder {
+    options: MetricsOptions,
+    collector: Collector,
+    batches: u32,
+    events_processed: u32,
+    validator: Option<EventValidator>,
+}
+
+/// [`ActivityCollector`] of either counting mode
+enum Collector {
+    Exact(ActivityCollector<UserBitmap>),
+    Approximate(ActivityCollector<HyperLogLog>),
+}
+
+impl MetricsBuilder {
+    /// Builder for `options` grouped by `group_by`. Only the eligible users
+    /// of `index` are kept, so the index can be dropped afterwards.
+    pub fn new(index: &LanguageIndex, options: MetricsOptions, group_by: GroupBy) -> Self {
+        let collector = match options.counting {
+            CountingMode::Exact => Collector::Exact(ActivityCollector::new(
+                index,
+                &options,
+                group_by,
+                UserBitmap::new(),
+            )),
+            CountingMode::Approximate { precision } => Collector::Approximate(
+                ActivityCollector::new(index, &options, group_by, HyperLogLog::new(precision)),
+            ),
+        };
+        Self {
+            options,
+            collector,
+            batches: 0,
+            events_processed: 0,
+            validator: None,
+        }
+    }
+
+    /// Check every pushed event with `validator` first; rejected events do
+    /// not count as activity
+    pub fn with_validation(mut self, validator: EventValidator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    pub fn push_batch(&mut self, events: &[NostrEvent]) {
+        let mut accepted = 0;
+        let validator = &mut self.validator;
+        let events = events.iter().filter(|event| {
+            let valid = validator
+                .as_mut()
+                .is_none_or(|validator| validator.accept(event));
+            accepted += valid as u32;
+            valid
+        });
+        match &mut self.collector {
+            Collector::Exact(collector) => collector.push(events),
+            Collector::Approximate(collector) => collector.push(events),
+        }
+        self.batches += 1;
+        self.events_processed += accepted;
+    }
+
+    /// Batches, events and eligible users active so far (an estimate when
+    /// counting approximately)
+    pub fn progress(&self) -> StreamProgress {
+        let active = match &self.collector {
+            Collector::Exact(collector) => collector.active.len() as u32,
+            Collector::Approximate(collector) => collector.active.estimate().round() as u32,
+        };
+        StreamProgress {
+            batches: self.batches,
+            events_processed: self.events_processed,
+            events_rejected: self
+                .validator
+                .as_ref()
+                .map_or(0, |validator| validator.report().rejected_total()),
+            users: active,
+        }
+    }
+
+    /// Rejections so far, when validating
+    pub fn validation_report(&self) -> Option<&ValidationReport> {
+        self.validator.as_ref().map(EventValidator::report)
+    }
+
+    /// Series of every group
+    pub fn finish(self) -> HashMap<String, Vec<MetricDataPoint>> {
+        let activity = match self.collector {
+            Collector::Exact(collector) => Activity::Exact(collector.groups),
+            Collector::Approximate(collector) => Activity::Approximate(collector.groups),
+        };
+        let results = activity.series(&self.options);
+
+        console_log!(
+            "Computed {} metrics for {} groups from {} batches",
+            self.options.counting,
+            results.len(),
+            self.batches
+        );
+
+        results
+    }
+}
+
+/// Series of every group: sliding windows of `options.window_days` for
+/// `Granularity::Day`, calendar buckets otherwise
+fn window_series(
+    groups: &Groups<UserBitmap>,
+    options: &MetricsOptions,
+) -> HashMap<String, Vec<MetricDataPoint>> {
+    let start_day = options.zone.epoch_day(options.since);
+    let end_day = options.zone.epoch_day(options.until);
+
+    groups
+        .keys
+        .iter()
+        .zip(groups.activity.iter())
+        .map(|(key, activity_by_day)| {
+            let points = match options.granularity {
+                Granularity::Day => sliding_window(
+                    activity_by_day,
+                    groups.users,
+                    start_day,
+                    end_day,
+                    options.window_days,
+                ),
+                granularity => calendar_buckets(activity_by_day, start_day, end_day, granularity),
+            };
+            (key.clone(), points)
+        })
+        .collect()
+}
+
+/// Distinct users per calendar bucket. Points are keyed by the bucket's first
+/// day; buckets at the edges only count days within `start_day..=end_day`.
+fn calendar_buckets(
+    activity_by_day: &HashMap<EpochDay, UserBitmap>,
+    start_day: EpochDay,
+    end_day: EpochDay,
+    granularity: Granularity,
+) -> Vec<MetricDataPoint> {
+    let mut results = Vec::new();
+    let mut bucket = granularity.bucket_start(start_day);
+
+    while bucket <= end_day {
+        let next = granularity.next_bucket(bucket);
+        let mut active_users = UserBitmap::new();
+        for day in bucket.max(start_day)..next.min(end_day + 1) {
+            if let Some(users) = activity_by_day.get(&day) {
+                active_users.union_with(users);
+            }
+        }
+        results.push(MetricDataPoint {
+            epoch_day: bucket,
+            count: active_users.len() as u32,
+            error: None,
+        });
+        bucket = next;
+    }
+
+    results
+}
+
+/// Eligible users with their target-language groups, looked up by pubkey
+enum EligibleUsers {
+    /// By interned id, for sets of ids; ids index `users`
+    Interned {
+        ids: OwnedPubkeyInterner,
+        users: Vec<Vec<LangGroup>>,
+    },
+    /// By pubkey hash, for sketches, which need neither ids nor pubkeys. Two
+    /// pubkeys with the same 64-bit hash count as one user.
+    Hashed(HashMap<u64, Vec<LangGroup>>),
+}
+
+impl EligibleUsers {
+    fn len(&self) -> usize {
+        match self {
+            EligibleUsers::Interned { users, .. } => users.len(),
+            EligibleUsers::Hashed(users) => users.len(),
+        }
+    }
+}
+
+/// Group of a target language an eligible user has
+struct LangGroup {
+    group: usize,
+    /// UTC days with posts in the language, ascending; only kept under a
+    /// period scope
+    days: Vec<EpochDay>,
+}
+
+impl LangGroup {
+    /// Whether the user counts toward the group for an event on UTC day
+    /// `epoch_day`, as [`UserLanguages::has_language_on`]
+    fn counts_on(&self, epoch_day: EpochDay, scope: EligibilityScope) -> bool {
+        match scope {
+            EligibilityScope::Lifetime => true,
+            EligibilityScope::Period { lookback_days } => {
+                let from = epoch_day.saturating_sub(lookback_days.max(1) as u32 - 1);
+                let position = self.days.partition_point(|&day| day < from);
+                self.days.get(position).is_some_and(|&day| day <= epoch_day)
+            }
+        }
+    }
+}
+
+/// Active user ids by group and day for users eligible for any of
+/// `options.languages` on that day (see [`EligibilityScope`]). Days outside `since..=until` are kept so callers can
+/// look back before the range.
+pub(crate) fn collect_activity(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &MetricsOptions,
+    group_by: GroupBy,
+) -> Groups<UserBitmap> {
+    collect_sets(events, index, options, group_by, UserBitmap::new())
+}
+
+/// [`collect_activity`] into any kind of per-day set, starting each day from
+/// `empty`
+fn collect_sets<S: ActiveSet>(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &MetricsOptions,
+    group_by: GroupBy,
+    empty: S,
+) -> Groups<S> {
+    console_log!(
+        "Computing metrics by {:?}: {} events, {} users, {} languages, window {} days",
+        group_by,
+        events.len(),
+        index.user_languages.len(),
+        options.languages.len(),
+        options.window_days
+    );
+
+    let mut collector = ActivityCollector::new(index, options, group_by, empty);
+    collector.push(events);
+    collector.groups
+}
+
+/// Collects [`Groups`] from events pushed in any number of batches. Keeps
+/// what it needs of the index, so the index can be dropped once it is built.
+///
+/// Activity is keyed by the zone day of each event unless another `bucket`
+/// is given.
+pub(crate) struct ActivityCollector<S, K = EpochDay> {
+    pub(crate) groups: Groups<S, K>,
+    group_by: GroupBy,
+    zone: Zone,
+    bucket: fn(Timestamp, Zone) -> K,
+    eligibility: EligibilityScope,
+
+    eligible: EligibleUsers,
+    kind_positions: HashMap<u16, usize>,
+
+    /// Eligible users counted in any group so far
+    active: S,
+}
+
+impl<S: ActiveSet> ActivityCollector<S> {
+    fn new(index: &LanguageIndex, options: &MetricsOptions, group_by: GroupBy, empty: S) -> Self {
+        Self::with_buckets(
+            index,
+            &options.languages,
+            options.eligibility,
+            options.zone,
+            group_by,
+            empty,
+            |timestamp, zone| zone.epoch_day(timestamp),
+        )
+    }
+}
+
+impl<S: ActiveSet, K: Copy + Eq + Hash> ActivityCollector<S, K> {
+    /// Collector of the users eligible for any of `languages`, keying
+    /// activity by `bucket` of each event's timestamp in `zone`
+    pub(crate) fn with_buckets(
+        index: &LanguageIndex,
+        languages: &[LanguageCode],
+        eligibility: EligibilityScope,
+        zone: Zone,
+        group_by: GroupBy,
+        empty: S,
+        bucket: fn(Timestamp, Zone) -> K,
+    ) -> Self {
+        let active = empty.clone();
+        let mut groups = Groups::new(empty);
+        if group_by == GroupBy::All {
+            groups.get_or_insert(ALL_GROUP);
+        }
+        // Group position of each target language
+        let lang_positions: Vec<usize> = languages
+            .iter()
+            .map(|lang| match group_by {
+                GroupBy::Language => groups.get_or_insert(lang),
+                _ => 0,
+            })
+            .collect();
+        let period = matches!(eligibility, EligibilityScope::Period { .. });
+
+        // Target-language groups of every eligible user, by dense id unless
+        // the sets only need hashes
+        let mut eligible = if S::BY_HASH {
+            EligibleUsers::Hashed(HashMap::new())
+        } else {
+            EligibleUsers::Interned {
+                ids: OwnedPubkeyInterner::new(),
+                users: Vec::new(),
+            }
+        };
+        for (pubkey, user_langs) in index.user_languages.iter() {
+            let mut lang_groups: Vec<(usize, &str)> = languages
+                .iter()
+                .zip(&lang_positions)
+                .filter(|(lang, _)| user_langs.has_language(lang))
+                .map(|(lang, &position)| (position, lang.as_str()))
+                .collect();
+            if lang_groups.is_empty() {
+                continue;
+            }
+            lang_groups.sort_unstable();
+            lang_groups.dedup();
+            let lang_groups: Vec<LangGroup> = lang_groups
+                .into_iter()
+                .map(|(group, lang)| LangGroup {
+                    group,
+                    days: match (period, user_langs.evidence.get(lang)) {
+                        (true, Some(evidence)) => evidence.daily_posts.keys().copied().collect(),
+                        _ => Vec::new(),
+                    },
+                })
+                .collect();
+            match &mut eligible {
+                EligibleUsers::Interned { ids, users } => {
+                    ids.intern(pubkey);
+                    users.push(lang_groups);
+                    groups.users = users.len();
+                }
+                EligibleUsers::Hashed(users) => {
+                    users.insert(HyperLogLog::hash(pubkey), lang_groups);
+                }
+            }
+        }
+
+        console_log!("Eligible users: {} ({})", eligible.len(), eligibility);
+
+        Self {
+            groups,
+            group_by,
+            zone,
+            bucket,
+            eligibility,
+            eligible,
+            kind_positions: HashMap::new(),
+            active,
+        }
+    }
+
+    /// Record the activity of `events` by group and bucket
+    pub(crate) fn push<'e>(&mut self, events: impl IntoIterator<Item = &'e NostrEvent>) {
+        for event in events {
+            let pubkey = event.get_pubkey();
+            let (id, hash, user) = match &self.eligible {
+                EligibleUsers::Interned { ids, users } => match ids.get(pubkey) {
+                    Some(id) => (id, 0, &users[id as usize]),
+                    None => continue,
+                },
+                EligibleUsers::Hashed(users) => {
+                    let hash = HyperLogLog::hash(pubkey);
+                    match users.get(&hash) {
+                        Some(user) => (0, hash, user),
+                        None => continue,
+                    }
+                }
+            };
+            let bucket = (self.bucket)(event.get_created_at(), self.zone);
+            // Evidence is keyed by UTC day whatever the metrics zone
+            let utc_day = timestamp_to_epoch_day(event.get_created_at());
+
+            // Target languages the user counts toward on this day
+            let mut lang_groups = user
+                .iter()
+                .filter(|lang_group| lang_group.counts_on(utc_day, self.eligibility))
+                .map(|lang_group| lang_group.group)
+                .peekable();
+            if lang_groups.peek().is_none() {
+                continue;
+            }
+            self.active.insert_user(id, hash);
+
+            let groups = &mut self.groups;
+            match self.group_by {
+                GroupBy::All => groups.record(0, bucket, id, hash),
+                GroupBy::Language => {
+                    for group in lang_groups {
+                        groups.record(group, bucket, id, hash);
+                    }
+                }
+                GroupBy::Kind => {
+                    let group = *self
+                        .kind_positions
+                        .entry(event.kind)
+                        .or_insert_with(|| groups.get_or_insert(&event.kind.to_string()));
+                    groups.record(group, bucket, id, hash);
+                }
+                GroupBy::Client => {
+                    let group = groups.get_or_insert(client_name(event));
+                    groups.record(group, bucket, id, hash);
+                }
+            }
+        }
+    }
+}
+
+/// Value of the NIP-89 `client` tag
+fn client_name(event: &NostrEvent) -> &str {
+    event
+        .tags
+        .iter()
+        .find(|tag| tag.first().map(String::as_str) == Some("client"))
+        .and_then(|tag| tag.get(1))
+        .map(String::as_str)
+        .unwrap_or(UNKNOWN_CLIENT)
+}
+
+/// Set of the users active in a group on one day
+pub(crate) trait ActiveSet: Clone {
+    /// Whether the set only uses pubkey hashes, so users need no ids
+    const BY_HASH: bool = false;
+
+    /// Add the user with interned `id` and pubkey hash `hash` (only one of
+    /// them is set, see [`ActiveSet::BY_HASH`])
+    fn insert_user(&mut self, id: u32, hash: u64);
+}
+
+impl ActiveSet for UserBitmap {
+    fn insert_user(&mut self, id: u32, _hash: u64) {
+        self.insert(id);
+    }
+}
+
+impl ActiveSet for HyperLogLog {
+    const BY_HASH: bool = true;
+
+    fn insert_user(&mut self, _id: u32, hash: u64) {
+        self.insert_hash(hash);
+    }
+}
+
+/// Per-group activity: active users by day (or other bucket)
+pub(crate) struct Groups<S, K = EpochDay> {
+    pub(crate) keys: Vec<String>,
+    positions: HashMap<String, usize>,
+    pub(crate) activity: Vec<HashMap<K, S>>,
+
+    /// Number of interned users; ids are `0..users`
+    pub(crate) users: usize,
+
+    /// Set a day starts from
+    empty: S,
+}
+
+impl<S: ActiveSet, K: Eq + Hash> Groups<S, K> {
+    fn new(empty: S) -> Self {
+        Self {
+            keys: Vec::new(),
+            positions: HashMap::new(),
+            activity: Vec::new(),
+            users: 0,
+            empty,
+        }
+    }
+
+    fn get_or_insert(&mut self, key: &str) -> usize {
+        if let Some(&position) = self.positions.get(key) {
+            return position;
+        }
+        let position = self.keys.len();
+        self.keys.push(key.to_string());
+        self.positions.insert(key.to_string(), position);
+        self.activity.push(HashMap::new());
+        position
+    }
+
+    fn record(&mut self, group: usize, bucket: K, user: u32, hash: u64) {
+        self.activity[group]
+            .entry(bucket)
+            .or_insert_with(|| self.empty.clone())
+            .insert_user(user, hash);
+    }
+}
+
+/// Distinct users over the trailing `window_days` days (clipped at `start_day`).
+///
+/// Rather than re-unioning the whole window every day, each id counts its
+/// active days in the window, so a day costs only the users entering and
+/// leaving it.
+fn sliding_window(
+    activity_by_day: &HashMap<EpochDay, UserBitmap>,
+    users: usize,
+    start_day: EpochDay,
+    end_day: EpochDay,
+    window_days: u16,
+) -> Vec<MetricDataPoint> {
+    let mut results = Vec::new();
+    let mut days_in_window: Vec<u16> = vec![0; users];
+    let mut active_users = 0u32;
+
+    for day in start_day..=end_day {
+        // Add current day to window
+        for user in activity_by_day
+            .get(&day)
+            .into_iter()
+            .flat_map(UserBitmap::iter)
+        {
+            let days = &mut days_in_window[user as usize];
+            if *days == 0 {
+                active_users += 1;
+            }
+            *days += 1;
+        }
+
+        // Remove the day that just left the window
+        if let Some(old_day) = day.checked_sub(window_days as u32) {
+            if old_day >= start_day {
+                for user in activity_by_day
+                    .get(&old_day)
+                    .into_iter()
+                    .flat_map(UserBitmap::iter)
+                {
+                    let days = &mut days_in_window[user as usize];
+                    *days -= 1;
+                    if *days == 0 {
+                        active_users -= 1;
+                    }
+                }
+            }
+        }
+
+        // Count unique active users in window
+        results.push(MetricDataPoint {
+            epoch_day: day,
+            count: active_users,
+            error: None,
+        });
+    }
+
+    results
+}
+
+/// Estimated series of every group, as [`window_series`] does for exact sets
+fn sketch_series(
+    groups: &Groups<HyperLogLog>,
+    options: &MetricsOptions,
+) -> HashMap<String, Vec<MetricDataPoint>> {
+    let start_day = options.zone.epoch_day(options.since);
+    let end_day = options.zone.epoch_day(options.until);
+
+    groups
+        .keys
+        .iter()
+        .zip(groups.activity.iter())
+        .map(|(key, sketches_by_day)| {
+            let points = match options.granularity {
+                Granularity::Day => sketch_window(
+                    sketches_by_day,
+                    &groups.empty,
+                    start_day,
+                    end_day,
+                    options.window_days,
+                ),
+                granularity => sketch_buckets(
+                    sketches_by_day,
+                    &groups.empty,
+                    start_day,
+                    end_day,
+                    granularity,
+                ),
+            };
+            (key.clone(), points)
+        })
+        .collect()
+}
+
+/// Estimate of a merged sketch, with twice the standard error of the
+/// estimator used as the bound
+fn estimated_point(epoch_day: EpochDay, sketch: &HyperLogLog) -> MetricDataPoint {
+    let (estimate, error) = sketch.estimate_with_error();
+    MetricDataPoint {
+        epoch_day,
+        count: estimate.round() as u32,
+        error: Some((2.0 * error).ceil() as u32),
+    }
+}
+
+/// Estimated distinct users over the trailing `window_days` days (clipped at
+/// `start_day`).
+///
+/// The window is a two-stack queue: `front` holds merges of the older days
+/// from each day up to the newest day moved there, `back` the merge of the
+/// days added since. A day's sketch is merged a constant number of times on
+/// average instead of once per window it falls in.
+fn sketch_window(
+    sketches_by_day: &HashMap<EpochDay, HyperLogLog>,
+    empty: &HyperLogLog,
+    start_day: EpochDay,
+    end_day: EpochDay,
+    window_days: u16,
+) -> Vec<MetricDataPoint> {
+    let mut results = Vec::new();
+    let mut front: Vec<HyperLogLog> = Vec::new();
+    let mut back_days: Vec<EpochDay> = Vec::new();
+    let mut back = empty.clone();
+
+    for day in start_day..=end_day {
+        // Add current day to window
+        back_days.push(day);
+        if let Some(sketch) = sketches_by_day.get(&day) {
+            back.merge(sketch);
+        }
+
+        // Remove the day that just left the window
+        if front.len() + back_days.len() > window_days as usize {
+            if front.is_empty() {
+                let mut merged = empty.clone();
+                for old_day in back_days.drain(..).rev() {
+                    if let Some(sketch) = sketches_by_day.get(&old_day) {
+                        merged.merge(sketch);
+                    }
+                    front.push(merged.clone());
+                }
+                back = empty.clone();
+            }
+            front.pop();
+        }
+
+        let mut window = back.clone();
+        if let Some(older) = front.last() {
+            window.merge(older);
+        }
+        results.push(estimated_point(day, &window));
+    }
+
+    results
+}
+
+/// Estimated distinct users per calendar bucket, as [`calendar_buckets`]
+fn sketch_buckets(
+    sketches_by_day: &HashMap<EpochDay, HyperLogLog>,
+    empty: &HyperLogLog,
+    start_day: EpochDay,
+    end_day: EpochDay,
+    granularity: Granularity,
+) -> Vec<MetricDataPoint> {
+    let mut results = Vec::new();
+    let mut bucket = granularity.bucket_start(start_day);
+
+    while bucket <= end_day {
+        let next = granularity.next_bucket(bucket);
+        let mut merged = empty.clone();
+        for day in bucket.max(start_day)..next.min(end_day + 1) {
+            if let Some(sketch) = sketches_by_day.get(&day) {
+                merged.merge(sketch);
+            }
+        }
+        results.push(estimated_point(bucket, &merged));
+        bucket = next;
+    }
+
+    results
+}
@@ -1,3 +1,5 @@
+use crate::relay_client::NostrEvent;
+use crate::validation;
 use nostr::nips::nip19::ToBech32;
 use nostr::types::time::Instant;
 use nostr::{Event, EventBuilder, Keys, Kind, PublicKey, Tag, Timestamp};
@@ -116,17 +118,17 @@ impl NostrEventBuilder {
 /// イベントIDの検証
 #[wasm_bindgen]
 pub fn verify_event_id(event_json: JsValue) -> Result<bool, JsValue> {
-    let event: Event = serde_wasm_bindgen::from_value(event_json)
+    let event: NostrEvent = serde_wasm_bindgen::from_value(event_json)
         .map_err(|e| JsValue::from_str(&format!("Deserialization error: {}", e)))?;
-    Ok(event.verify_id())
+    Ok(validation::verify_id(&event).is_ok())
 }
 
 /// イベントの署名検証
 #[wasm_bindgen]
 pub fn verify_event_signature(event_json: JsValue) -> Result<bool, JsValue> {
-    let event: Event = serde_wasm_bindgen::from_value(event_json)
+    let event: NostrEvent = serde_wasm_bindgen::from_value(event_json)
         .map_err(|e| JsValue::from_str(&format!("Deserialization error: {}", e)))?;
-    Ok(event.verify_signature())
+    Ok(validation::verify_signature(&event).is_ok())
 }
 
 /// 現在のタイムスタンプを取得
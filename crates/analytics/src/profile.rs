@@ -0,0 +1,116 @@
+// Profile metadata (kind 0) as a language signal
+//
+// A user's `about` and `display_name` are usually written in their main
+// language, and a NIP-05 identifier on a country-code domain hints at it too.
+// Users who rarely post get most of their language evidence from here.
+
+use crate::error::AnalyticsError;
+use crate::language::{detect_content_language, LanguageDetector};
+use crate::types::LanguageCode;
+use serde::{Deserialize, Serialize};
+
+/// Profile metadata event kind
+pub const METADATA_EVENT_KIND: u16 = 0;
+
+/// Default post-equivalent weight of a profile hint
+pub const DEFAULT_PROFILE_WEIGHT: f32 = 2.0;
+
+/// Confidence of a NIP-05 country-code domain hint
+pub const NIP05_HINT_CONFIDENCE: f32 = 0.5;
+
+/// Country-code TLDs whose NIP-05 domains suggest a language
+const NIP05_TLD_LANGUAGES: [(&str, &str); 16] = [
+    ("jp", "ja"),
+    ("kr", "ko"),
+    ("cn", "zh"),
+    ("tw", "zh"),
+    ("de", "de"),
+    ("at", "de"),
+    ("fr", "fr"),
+    ("es", "es"),
+    ("mx", "es"),
+    ("br", "pt"),
+    ("pt", "pt"),
+    ("it", "it"),
+    ("nl", "nl"),
+    ("ru", "ru"),
+    ("pl", "pl"),
+    ("ua", "uk"),
+];
+
+/// Fields of kind 0 content used as language hints
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ProfileMetadata {
+    #[serde(default)]
+    pub about: Option<String>,
+
+    #[serde(default, alias = "displayName")]
+    pub display_name: Option<String>,
+
+    #[serde(default)]
+    pub nip05: Option<String>,
+}
+
+impl ProfileMetadata {
+    /// Parse kind 0 content; unknown fields are ignored
+    pub fn parse(content: &str) -> Result<Self, AnalyticsError> {
+        Ok(serde_json::from_str(content)?)
+    }
+
+    /// Text to detect: `display_name` and `about`
+    pub fn text(&self) -> String {
+        [&self.display_name, &self.about]
+            .into_iter()
+            .flatten()
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Language suggested by the NIP-05 domain's country-code TLD
+    pub fn nip05_language(&self) -> Option<LanguageCode> {
+        let (_, domain) = self.nip05.as_deref()?.rsplit_once('@')?;
+        let tld = domain.trim_end_matches('.').rsplit('.').next()?;
+        NIP05_TLD_LANGUAGES
+            .iter()
+            .find(|(cctld, _)| tld.eq_ignore_ascii_case(cctld))
+            .map(|(_, lang)| lang.to_string())
+    }
+}
+
+/// One language hint from a profile
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProfileHint {
+    pub language: LanguageCode,
+    pub confidence: f32,
+}
+
+/// Language hints of one profile: the detected language of its text (when at
+/// or above `conf_thresh`) and the NIP-05 domain's language
+pub fn profile_hints(
+    detector: &dyn LanguageDetector,
+    metadata: &ProfileMetadata,
+    conf_thresh: f32,
+    min_content_chars: u32,
+) -> Result<Vec<ProfileHint>, AnalyticsError> {
+    let mut hints = Vec::new();
+
+    let result = detect_content_language(detector, &metadata.text(), min_content_chars)?;
+    if let Some(detection) = result.detection {
+        if detection.confidence >= conf_thresh {
+            hints.push(ProfileHint {
+                language: detection.language,
+                confidence: detection.confidence,
+            });
+        }
+    }
+    if let Some(language) = metadata.nip05_language() {
+        hints.push(ProfileHint {
+            language,
+            confidence: NIP05_HINT_CONFIDENCE,
+        });
+    }
+
+    Ok(hints)
+}
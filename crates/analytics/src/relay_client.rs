@@ -1,8 +1,25 @@
-// Relay client is now handled in JavaScript/TypeScript
-// This module provides types and helpers for the WASM interface
+// Relay client
+//
+// In the browser, relay connections are handled in JavaScript/TypeScript
+// (`fetchEventsChunked` in frontend/src/wasm/analytics.ts) and events are
+// handed to the WASM interface. On native targets this module also provides a
+// blocking WebSocket client speaking NIP-01 (REQ/EVENT/EOSE/CLOSE/CLOSED/NOTICE)
+// so analytics jobs can run outside a browser.
 
+use crate::error::AnalyticsError;
 use crate::types::Timestamp;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+#[cfg(not(target_arch = "wasm32"))]
+use tungstenite::{client::IntoClientRequest, stream::MaybeTlsStream, Message, WebSocket};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NostrEvent {
@@ -28,3 +45,495 @@ impl NostrEvent {
         self.created_at
     }
 }
+
+/// Subscription filter (NIP-01)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authors: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<u16>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<Timestamp>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<Timestamp>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+
+    /// Tag filters keyed by `#<letter>` (e.g. `#l`)
+    #[serde(flatten)]
+    pub tags: BTreeMap<String, Vec<String>>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn kinds(mut self, kinds: Vec<u16>) -> Self {
+        self.kinds = Some(kinds);
+        self
+    }
+
+    pub fn authors(mut self, authors: Vec<String>) -> Self {
+        self.authors = Some(authors);
+        self
+    }
+
+    pub fn since(mut self, since: Timestamp) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    pub fn until(mut self, until: Timestamp) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn tag(mut self, name: char, values: Vec<String>) -> Self {
+        self.tags.insert(format!("#{}", name), values);
+        self
+    }
+
+    /// Check whether an event matches this filter (`limit` is ignored)
+    pub fn matches(&self, event: &NostrEvent) -> bool {
+        if let Some(ids) = &self.ids {
+            if !ids.iter().any(|id| id == &event.id) {
+                return false;
+            }
+        }
+        if let Some(authors) = &self.authors {
+            if !authors.iter().any(|a| a == &event.pubkey) {
+                return false;
+            }
+        }
+        if let Some(kinds) = &self.kinds {
+            if !kinds.contains(&event.kind) {
+                return false;
+            }
+        }
+        if self.since.is_some_and(|since| event.created_at < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| event.created_at > until) {
+            return false;
+        }
+        self.tags.iter().all(|(key, values)| {
+            let name = key.trim_start_matches('#');
+            event.tags.iter().any(|tag| {
+                tag.first().map(String::as_str) == Some(name)
+                    && tag.get(1).is_some_and(|v| values.contains(v))
+            })
+        })
+    }
+}
+
+/// Message received from a relay
+#[derive(Clone, Debug)]
+pub enum RelayMessage {
+    Event {
+        subscription_id: String,
+        event: NostrEvent,
+    },
+    Eose {
+        subscription_id: String,
+    },
+    Closed {
+        subscription_id: String,
+        message: String,
+    },
+    Notice {
+        message: String,
+    },
+    Ok {
+        event_id: String,
+        accepted: bool,
+        message: String,
+    },
+}
+
+impl RelayMessage {
+    /// Parse a relay-to-client message
+    pub fn from_json(text: &str) -> Result<Self, AnalyticsError> {
+        let value: Value = serde_json::from_str(text)?;
+        let parts = value
+            .as_array()
+            .ok_or_else(|| AnalyticsError::EventParsing(format!("Not an array: {}", text)))?;
+
+        let str_at = |i: usize| -> Result<String, AnalyticsError> {
+            parts
+                .get(i)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    AnalyticsError::EventParsing(format!("Missing field {} in {}", i, text))
+                })
+        };
+
+        match str_at(0)?.as_str() {
+            "EVENT" => {
+                let event = parts.get(2).cloned().ok_or_else(|| {
+                    AnalyticsError::EventParsing(format!("Missing event in {}", text))
+                })?;
+                Ok(RelayMessage::Event {
+                    subscription_id: str_at(1)?,
+                    event: serde_json::from_value(event)?,
+                })
+            }
+            "EOSE" => Ok(RelayMessage::Eose {
+                subscription_id: str_at(1)?,
+            }),
+            "CLOSED" => Ok(RelayMessage::Closed {
+                subscription_id: str_at(1)?,
+                message: str_at(2).unwrap_or_default(),
+            }),
+            "NOTICE" => Ok(RelayMessage::Notice {
+                message: str_at(1)?,
+            }),
+            "OK" => Ok(RelayMessage::Ok {
+                event_id: str_at(1)?,
+                accepted: parts.get(2).and_then(Value::as_bool).unwrap_or(false),
+                message: str_at(3).unwrap_or_default(),
+            }),
+            other => Err(AnalyticsError::EventParsing(format!(
+                "Unknown relay message type: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Build a client-to-relay REQ message
+pub fn req_message(subscription_id: &str, filters: &[Filter]) -> Result<String, AnalyticsError> {
+    let mut parts = vec![Value::from("REQ"), Value::from(subscription_id)];
+    for filter in filters {
+        parts.push(serde_json::to_value(filter)?);
+    }
+    Ok(Value::Array(parts).to_string())
+}
+
+/// Build a client-to-relay CLOSE message
+pub fn close_message(subscription_id: &str) -> String {
+    Value::Array(vec![Value::from("CLOSE"), Value::from(subscription_id)]).to_string()
+}
+
+/// How a subscription finished
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriptionEnd {
+    /// Relay sent EOSE
+    Eose,
+    /// Relay sent CLOSED
+    Closed,
+    /// No EOSE/CLOSED before the subscription timeout
+    Timeout,
+}
+
+/// Events collected by one subscription
+#[derive(Clone, Debug)]
+pub struct SubscriptionResult {
+    pub events: Vec<NostrEvent>,
+    pub end: SubscriptionEnd,
+
+    /// Reason given by the relay in CLOSED, if any
+    pub message: Option<String>,
+}
+
+/// Options for the native relay client
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct RelayClientOptions {
+    /// Timeout for TCP connect and WebSocket handshake
+    pub connect_timeout: Duration,
+
+    /// Maximum time to wait for EOSE/CLOSED on a subscription
+    pub subscription_timeout: Duration,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for RelayClientOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            subscription_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Blocking NIP-01 relay client (native targets only)
+#[cfg(not(target_arch = "wasm32"))]
+pub struct RelayClient {
+    url: String,
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    options: RelayClientOptions,
+    notices: Vec<String>,
+    next_subscription: u64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RelayClient {
+    /// Connect to a relay with default options
+    pub fn connect(url: &str) -> Result<Self, AnalyticsError> {
+        Self::connect_with_options(url, RelayClientOptions::default())
+    }
+
+    /// Connect to a relay (`ws://` or `wss://`)
+    pub fn connect_with_options(
+        url: &str,
+        options: RelayClientOptions,
+    ) -> Result<Self, AnalyticsError> {
+        let request = url
+            .into_client_request()
+            .map_err(|e| AnalyticsError::RelayConnection(format!("{}: {}", url, e)))?;
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or_else(|| AnalyticsError::RelayConnection(format!("{}: missing host", url)))?;
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("wss") {
+                443
+            } else {
+                80
+            });
+
+        let addr = (host, port)
+            .to_socket_addrs()
+            .map_err(|e| AnalyticsError::RelayConnection(format!("{}: {}", url, e)))?
+            .next()
+            .ok_or_else(|| AnalyticsError::RelayConnection(format!("{}: no address", url)))?;
+
+        let stream = TcpStream::connect_timeout(&addr, options.connect_timeout)
+            .map_err(|e| AnalyticsError::RelayConnection(format!("{}: {}", url, e)))?;
+        stream
+            .set_read_timeout(Some(options.connect_timeout))
+            .and_then(|_| stream.set_write_timeout(Some(options.connect_timeout)))
+            .and_then(|_| stream.set_nodelay(true))
+            .map_err(|e| AnalyticsError::RelayConnection(format!("{}: {}", url, e)))?;
+
+        let (socket, _) = tungstenite::client_tls(request, stream)
+            .map_err(|e| AnalyticsError::RelayConnection(format!("{}: {}", url, e)))?;
+
+        Ok(Self {
+            url: url.to_string(),
+            socket,
+            options,
+            notices: Vec::new(),
+            next_subscription: 0,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn options(&self) -> &RelayClientOptions {
+        &self.options
+    }
+
+    /// NOTICE messages received so far
+    pub fn notices(&self) -> &[String] {
+        &self.notices
+    }
+
+    /// Send a REQ and return the generated subscription id
+    pub fn subscribe(&mut self, filters: &[Filter]) -> Result<String, AnalyticsError> {
+        self.next_subscription += 1;
+        let subscription_id = format!("na-{}", self.next_subscription);
+        self.send(req_message(&subscription_id, filters)?)?;
+        Ok(subscription_id)
+    }
+
+    /// Send a CLOSE for a subscription
+    pub fn close_subscription(&mut self, subscription_id: &str) -> Result<(), AnalyticsError> {
+        self.send(close_message(subscription_id))
+    }
+
+    /// Wait for the next relay message until `deadline`.
+    /// Returns `Ok(None)` when the deadline passes without a message.
+    pub fn next_message(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<Option<RelayMessage>, AnalyticsError> {
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            self.set_read_timeout(remaining)?;
+
+            let message = match self.socket.read() {
+                Ok(message) => message,
+                Err(tungstenite::Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(None);
+                }
+                Err(e) => {
+                    return Err(AnalyticsError::RelayConnection(format!(
+                        "{}: {}",
+                        self.url, e
+                    )))
+                }
+            };
+
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => {
+                    return Err(AnalyticsError::RelayConnection(format!(
+                        "{}: connection closed by relay",
+                        self.url
+                    )))
+                }
+                _ => continue,
+            };
+
+            match RelayMessage::from_json(text.as_str()) {
+                Ok(RelayMessage::Notice { message }) => {
+                    self.notices.push(message.clone());
+                    return Ok(Some(RelayMessage::Notice { message }));
+                }
+                Ok(message) => return Ok(Some(message)),
+                // Ignore messages we don't understand (AUTH, COUNT, malformed events...)
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// Run a single subscription until EOSE, CLOSED or timeout
+    pub fn fetch(&mut self, filters: &[Filter]) -> Result<SubscriptionResult, AnalyticsError> {
+        let mut results = self.fetch_many(&[filters.to_vec()])?;
+        Ok(results.remove(0))
+    }
+
+    /// Run several subscriptions concurrently on this connection.
+    /// Results are returned in the order of `requests`.
+    pub fn fetch_many(
+        &mut self,
+        requests: &[Vec<Filter>],
+    ) -> Result<Vec<SubscriptionResult>, AnalyticsError> {
+        let mut pending: HashMap<String, usize> = HashMap::new();
+        let mut results: Vec<SubscriptionResult> = Vec::with_capacity(requests.len());
+
+        for (i, filters) in requests.iter().enumerate() {
+            let subscription_id = self.subscribe(filters)?;
+            pending.insert(subscription_id, i);
+            results.push(SubscriptionResult {
+                events: Vec::new(),
+                end: SubscriptionEnd::Timeout,
+                message: None,
+            });
+        }
+
+        let deadline = Instant::now() + self.options.subscription_timeout;
+
+        while !pending.is_empty() {
+            let Some(message) = self.next_message(deadline)? else {
+                break;
+            };
+            match message {
+                RelayMessage::Event {
+                    subscription_id,
+                    event,
+                } => {
+                    if let Some(&i) = pending.get(&subscription_id) {
+                        results[i].events.push(event);
+                    }
+                }
+                RelayMessage::Eose { subscription_id } => {
+                    if let Some(i) = pending.remove(&subscription_id) {
+                        results[i].end = SubscriptionEnd::Eose;
+                        self.close_subscription(&subscription_id)?;
+                    }
+                }
+                RelayMessage::Closed {
+                    subscription_id,
+                    message,
+                } => {
+                    if let Some(i) = pending.remove(&subscription_id) {
+                        results[i].end = SubscriptionEnd::Closed;
+                        results[i].message = Some(message);
+                    }
+                }
+                RelayMessage::Notice { .. } | RelayMessage::Ok { .. } => {}
+            }
+        }
+
+        // Subscriptions that timed out are closed so the relay stops sending
+        for subscription_id in pending.keys() {
+            self.close_subscription(subscription_id)?;
+        }
+
+        Ok(results)
+    }
+
+    /// Send a signed event and wait for the relay's OK.
+    /// Returns whether the relay accepted it and its message.
+    pub fn publish(&mut self, event: &NostrEvent) -> Result<(bool, String), AnalyticsError> {
+        let message = Value::Array(vec![Value::from("EVENT"), serde_json::to_value(event)?]);
+        self.send(message.to_string())?;
+
+        let deadline = Instant::now() + self.options.subscription_timeout;
+        while let Some(message) = self.next_message(deadline)? {
+            if let RelayMessage::Ok {
+                event_id,
+                accepted,
+                message,
+            } = message
+            {
+                if event_id == event.id {
+                    return Ok((accepted, message));
+                }
+            }
+        }
+
+        Err(AnalyticsError::RelayConnection(format!(
+            "{}: no OK for event {}",
+            self.url, event.id
+        )))
+    }
+
+    /// Close the WebSocket connection
+    pub fn close(mut self) -> Result<(), AnalyticsError> {
+        self.socket
+            .close(None)
+            .and_then(|_| self.socket.flush())
+            .or_else(|e| match e {
+                tungstenite::Error::ConnectionClosed => Ok(()),
+                e => Err(e),
+            })
+            .map_err(|e| AnalyticsError::RelayConnection(format!("{}: {}", self.url, e)))
+    }
+
+    fn send(&mut self, text: String) -> Result<(), AnalyticsError> {
+        self.socket
+            .send(Message::text(text))
+            .map_err(|e| AnalyticsError::RelayConnection(format!("{}: {}", self.url, e)))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> Result<(), AnalyticsError> {
+        let stream = match self.socket.get_mut() {
+            MaybeTlsStream::Plain(stream) => stream,
+            MaybeTlsStream::NativeTls(stream) => stream.get_mut(),
+            _ => return Ok(()),
+        };
+        stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| AnalyticsError::RelayConnection(format!("{}: {}", self.url, e)))
+    }
+}
@@ -0,0 +1,205 @@
+use crate::error::AnalyticsError;
+use crate::interner::OwnedPubkeyInterner;
+use crate::relay_client::NostrEvent;
+use crate::timezone::Zone;
+use crate::types::*;
+use crate::utils::console_log;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Default retention offsets (D1/D7/D30)
+pub const DEFAULT_RETENTION_OFFSETS: [u32; 3] = [1, 7, 30];
+
+/// How users are grouped into cohorts by their first-seen day
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CohortPeriod {
+    /// One cohort per epoch day
+    Day,
+    /// One cohort per ISO week (Monday start)
+    Week,
+}
+
+impl FromStr for CohortPeriod {
+    type Err = AnalyticsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(CohortPeriod::Day),
+            "week" => Ok(CohortPeriod::Week),
+            other => Err(AnalyticsError::InvalidParameter(format!(
+                "Unknown cohort period: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Options for retention analysis
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetentionOptions {
+    /// Start timestamp (Unix seconds)
+    pub since: Timestamp,
+
+    /// End timestamp (Unix seconds)
+    pub until: Timestamp,
+
+    /// Target languages
+    pub languages: Vec<LanguageCode>,
+
+    pub cohort_period: CohortPeriod,
+
+    /// Days after first activity to measure (default D1/D7/D30)
+    pub offsets: Vec<u32>,
+
+    /// Time zone of day boundaries
+    #[serde(default)]
+    pub zone: Zone,
+}
+
+/// One cohort of a retention matrix
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CohortRow {
+    /// First epoch day of the cohort period
+    pub cohort_start: EpochDay,
+
+    /// Users first seen in this cohort period
+    pub size: u32,
+
+    /// Users active exactly N days after their first-seen day, per offset.
+    /// `None` when the offset reaches past `until` for part of the cohort.
+    pub retained: Vec<Option<u32>>,
+}
+
+impl CohortRow {
+    /// Retained share per offset (0.0-1.0)
+    pub fn rates(&self) -> Vec<Option<f32>> {
+        self.retained
+            .iter()
+            .map(|r| r.map(|n| n as f32 / self.size.max(1) as f32))
+            .collect()
+    }
+}
+
+/// N-day retention matrix for one language
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RetentionMatrix {
+    pub language: LanguageCode,
+    pub cohort_period: CohortPeriod,
+    pub offsets: Vec<u32>,
+    pub cohorts: Vec<CohortRow>,
+}
+
+/// Compute cohort retention per target language.
+///
+/// A user's first-seen day is their earliest activity within `since..=until`,
+/// so users active before `since` are treated as new on their first day in range.
+pub fn compute_retention(
+    events: &[NostrEvent],
+    index: &LanguageIndex,
+    options: &RetentionOptions,
+) -> HashMap<LanguageCode, RetentionMatrix> {
+    let offsets: Vec<u32> = if options.offsets.is_empty() {
+        DEFAULT_RETENTION_OFFSETS.to_vec()
+    } else {
+        options.offsets.clone()
+    };
+
+    let start_day = options.zone.epoch_day(options.since);
+    let end_day = options.zone.epoch_day(options.until);
+
+    // Eligible users by interned id
+    let mut interner = OwnedPubkeyInterner::new();
+    let mut eligible: Vec<&UserLanguages> = Vec::new();
+    for (pubkey, user_langs) in index.user_languages.iter() {
+        if options
+            .languages
+            .iter()
+            .any(|lang| user_langs.has_language(lang))
+        {
+            interner.intern(pubkey.as_str());
+            eligible.push(user_langs);
+        }
+    }
+
+    console_log!(
+        "Computing retention: {} events, {} eligible users, {} languages",
+        events.len(),
+        eligible.len(),
+        options.languages.len()
+    );
+
+    // Active days per eligible user
+    let mut active_days: Vec<Vec<EpochDay>> = vec![Vec::new(); eligible.len()];
+    for event in events.iter() {
+        let day = options.zone.epoch_day(event.get_created_at());
+        if day < start_day || day > end_day {
+            continue;
+        }
+        if let Some(id) = interner.get(event.get_pubkey()) {
+            active_days[id as usize].push(day);
+        }
+    }
+    for days in active_days.iter_mut() {
+        days.sort_unstable();
+        days.dedup();
+    }
+
+    let cohort_of = |day: EpochDay| match options.cohort_period {
+        CohortPeriod::Day => day,
+        CohortPeriod::Week => epoch_day_to_week_start(day).max(start_day),
+    };
+    let cohort_end = |cohort_start: EpochDay| match options.cohort_period {
+        CohortPeriod::Day => cohort_start,
+        CohortPeriod::Week => epoch_day_to_week_start(cohort_start) + 6,
+    };
+
+    let mut results = HashMap::new();
+    for lang in &options.languages {
+        // cohort start -> (size, retained per offset)
+        let mut cohorts: HashMap<EpochDay, (u32, Vec<u32>)> = HashMap::new();
+
+        for (user_langs, days) in eligible.iter().zip(&active_days) {
+            if days.is_empty() || !user_langs.has_language(lang) {
+                continue;
+            }
+            let first = days[0];
+            let entry = cohorts
+                .entry(cohort_of(first))
+                .or_insert_with(|| (0, vec![0; offsets.len()]));
+            entry.0 += 1;
+            for (i, offset) in offsets.iter().enumerate() {
+                if days.binary_search(&(first + offset)).is_ok() {
+                    entry.1[i] += 1;
+                }
+            }
+        }
+
+        let mut rows: Vec<CohortRow> = cohorts
+            .into_iter()
+            .map(|(cohort_start, (size, retained))| CohortRow {
+                cohort_start,
+                size,
+                retained: offsets
+                    .iter()
+                    .zip(retained)
+                    .map(|(offset, n)| (cohort_end(cohort_start) + offset <= end_day).then_some(n))
+                    .collect(),
+            })
+            .collect();
+        rows.sort_by_key(|row| row.cohort_start);
+
+        results.insert(
+            lang.clone(),
+            RetentionMatrix {
+                language: lang.clone(),
+                cohort_period: options.cohort_period,
+                offsets: offsets.clone(),
+                cohorts: rows,
+            },
+        );
+    }
+
+    results
+}
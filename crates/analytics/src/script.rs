@@ -0,0 +1,287 @@
+// Unicode-script stage for Japanese / Chinese / Korean
+//
+// whatlang often labels short Japanese notes that are mostly kanji as Chinese.
+// Kana only occur in Japanese and Hangul only in Korean, so their share of the
+// CJK characters decides between ja, zh and ko more reliably on short text.
+
+use crate::error::AnalyticsError;
+use crate::language::{Detection, LanguageDetector};
+use crate::types::LanguageCode;
+use serde::{Deserialize, Serialize};
+
+/// Minimum kana share of CJK characters for Han-heavy text to count as
+/// Japanese. Even kanji-heavy Japanese needs particles and inflections in
+/// kana, while Chinese text may borrow a single `の`.
+pub const MIN_KANA_RATIO: f32 = 0.2;
+
+/// Minimum CJK share of letters for the script stage to decide at all
+pub const MIN_CJK_RATIO: f32 = 0.5;
+
+/// Character counts by script
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScriptStats {
+    /// Alphabetic characters of any script
+    pub letters: u32,
+    pub hiragana: u32,
+    /// Katakana, including half-width forms
+    pub katakana: u32,
+    pub hangul: u32,
+    /// Han ideographs
+    pub han: u32,
+    /// Japanese-only marks such as `々` and `〆`
+    pub ja_marks: u32,
+}
+
+impl ScriptStats {
+    pub fn from_text(text: &str) -> Self {
+        let mut stats = Self::default();
+        for c in text.chars() {
+            match c {
+                '\u{3040}'..='\u{309F}' => stats.hiragana += 1,
+                '\u{30A0}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}' | '\u{FF66}'..='\u{FF9F}' => {
+                    stats.katakana += 1
+                }
+                '\u{AC00}'..='\u{D7AF}' | '\u{1100}'..='\u{11FF}' | '\u{3130}'..='\u{318F}' => {
+                    stats.hangul += 1
+                }
+                '\u{4E00}'..='\u{9FFF}'
+                | '\u{3400}'..='\u{4DBF}'
+                | '\u{F900}'..='\u{FAFF}'
+                | '\u{20000}'..='\u{2FFFF}' => stats.han += 1,
+                '\u{3005}' | '\u{3006}' => stats.ja_marks += 1,
+                _ => {}
+            }
+            if c.is_alphabetic() {
+                stats.letters += 1;
+            }
+        }
+        stats
+    }
+
+    /// Hiragana, katakana and Japanese marks
+    pub fn kana(&self) -> u32 {
+        self.hiragana + self.katakana + self.ja_marks
+    }
+
+    pub fn cjk(&self) -> u32 {
+        self.kana() + self.hangul + self.han
+    }
+
+    /// CJK share of letters (0.0-1.0)
+    pub fn cjk_ratio(&self) -> f32 {
+        ratio(self.cjk(), self.letters)
+    }
+
+    /// Kana share of CJK characters (0.0-1.0)
+    pub fn kana_ratio(&self) -> f32 {
+        ratio(self.kana(), self.cjk())
+    }
+
+    /// Hangul share of CJK characters (0.0-1.0)
+    pub fn hangul_ratio(&self) -> f32 {
+        ratio(self.hangul, self.cjk())
+    }
+
+    /// Language implied by the scripts alone, with the CJK share of letters
+    /// as confidence. `None` for non-CJK text and Han-only text, which could
+    /// be either Japanese or Chinese.
+    pub fn language(&self) -> Option<(LanguageCode, f32)> {
+        if self.cjk() == 0 || self.cjk_ratio() < MIN_CJK_RATIO {
+            return None;
+        }
+        let lang = if self.hangul_ratio() >= 0.5 {
+            "ko"
+        } else if self.kana() > 0 && self.kana_ratio() >= MIN_KANA_RATIO {
+            "ja"
+        } else {
+            return None;
+        };
+        Some((lang.to_string(), self.cjk_ratio()))
+    }
+}
+
+fn ratio(part: u32, total: u32) -> f32 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f32 / total as f32
+}
+
+/// Languages the script stage may correct
+fn is_cjk_language(lang: &str) -> bool {
+    matches!(lang, "ja" | "zh" | "ko")
+}
+
+/// What the script stage did with the wrapped detector's answer
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptAction {
+    /// Scripts were inconclusive, agreed with the detector, or the detector
+    /// returned a non-CJK language
+    Kept,
+    /// Scripts replaced the detector's language
+    Overridden,
+    /// The detector gave no answer; scripts supplied one
+    Added,
+}
+
+/// Script analysis recorded on a [`Detection`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScriptDecision {
+    pub stats: ScriptStats,
+
+    /// Language the wrapped detector returned
+    pub detector_language: Option<LanguageCode>,
+
+    pub action: ScriptAction,
+}
+
+/// Wraps a detector and corrects its ja/zh/ko answers from Unicode scripts
+#[derive(Clone, Debug, Default)]
+pub struct CjkScriptDetector<D> {
+    pub inner: D,
+}
+
+impl<D: LanguageDetector> CjkScriptDetector<D> {
+    pub const fn new(inner: D) -> Self {
+        Self { inner }
+    }
+}
+
+impl<D: LanguageDetector> LanguageDetector for CjkScriptDetector<D> {
+    fn name(&self) -> String {
+        "cjk-script".to_string()
+    }
+
+    fn version(&self) -> String {
+        "1".to_string()
+    }
+
+    /// `<inner id>+cjk-script@1`
+    fn id(&self) -> String {
+        format!("{}+{}@{}", self.inner.id(), self.name(), self.version())
+    }
+
+    fn detect(&self, text: &str) -> Result<Option<(LanguageCode, f32)>, AnalyticsError> {
+        Ok(self
+            .detect_detailed(text)?
+            .map(|detection| (detection.language, detection.confidence)))
+    }
+
+    fn detect_detailed(&self, text: &str) -> Result<Option<Detection>, AnalyticsError> {
+        let stats = ScriptStats::from_text(text);
+        let detected = self.inner.detect(text)?;
+        let detector_language = detected.as_ref().map(|(lang, _)| lang.clone());
+
+        let (language, confidence, action) = match (detected, stats.language()) {
+            // Only ja/zh/ko answers are corrected; the scripts say nothing
+            // about whether a non-CJK answer is wrong
+            (Some((lang, _)), Some((script_lang, script_confidence)))
+                if lang != script_lang && is_cjk_language(&lang) =>
+            {
+                (script_lang, script_confidence, ScriptAction::Overridden)
+            }
+            (None, Some((script_lang, script_confidence))) => {
+                (script_lang, script_confidence, ScriptAction::Added)
+            }
+            (Some((lang, confidence)), _) => (lang, confidence, ScriptAction::Kept),
+            (None, None) => return Ok(None),
+        };
+
+        Ok(Some(Detection {
+            language,
+            confidence,
+            script: Some(ScriptDecision {
+                stats,
+                detector_language,
+                action,
+            }),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::FixedLanguageDetector;
+
+    #[test]
+    fn test_script_stats() {
+        let stats = ScriptStats::from_text("東京は晴れ。안녕 abc");
+        assert_eq!(stats.han, 3);
+        assert_eq!(stats.hiragana, 2);
+        assert_eq!(stats.hangul, 2);
+        assert_eq!(stats.letters, 10);
+    }
+
+    #[test]
+    fn test_script_language() {
+        assert_eq!(
+            ScriptStats::from_text("今日は天気").language().unwrap().0,
+            "ja"
+        );
+        assert_eq!(
+            ScriptStats::from_text("안녕하세요").language().unwrap().0,
+            "ko"
+        );
+        // Han only: ambiguous
+        assert!(ScriptStats::from_text("今日天気晴朗").language().is_none());
+        // Mostly Latin
+        assert!(ScriptStats::from_text("hello world の")
+            .language()
+            .is_none());
+    }
+
+    #[test]
+    fn test_kanji_heavy_japanese_overrides_chinese() {
+        let detector = CjkScriptDetector::new(FixedLanguageDetector::new("zh", 0.9));
+        let detection = detector
+            .detect_detailed("東京都の新規感染者数を発表した")
+            .unwrap()
+            .unwrap();
+        assert_eq!(detection.language, "ja");
+        let script = detection.script.unwrap();
+        assert_eq!(script.action, ScriptAction::Overridden);
+        assert_eq!(script.detector_language.as_deref(), Some("zh"));
+        assert_eq!(script.stats.hiragana, 4);
+    }
+
+    #[test]
+    fn test_single_kana_keeps_chinese() {
+        let detector = CjkScriptDetector::new(FixedLanguageDetector::new("zh", 0.9));
+        let detection = detector
+            .detect_detailed("我的の天气很好今天")
+            .unwrap()
+            .unwrap();
+        assert_eq!(detection.language, "zh");
+        assert_eq!(detection.script.unwrap().action, ScriptAction::Kept);
+    }
+
+    #[test]
+    fn test_non_cjk_answer_is_not_corrected() {
+        let detector = CjkScriptDetector::new(FixedLanguageDetector::new("en", 0.6));
+        let detection = detector.detect_detailed("今日は天気").unwrap().unwrap();
+        assert_eq!(detection.language, "en");
+        assert_eq!(detection.confidence, 0.6);
+        assert_eq!(detection.script.unwrap().action, ScriptAction::Kept);
+    }
+
+    #[test]
+    fn test_han_only_keeps_detector_answer() {
+        let detector = CjkScriptDetector::new(FixedLanguageDetector::new("zh", 0.7));
+        let detection = detector.detect_detailed("今日天気晴朗").unwrap().unwrap();
+        assert_eq!(detection.language, "zh");
+        assert_eq!(detection.confidence, 0.7);
+        assert_eq!(detection.script.unwrap().action, ScriptAction::Kept);
+    }
+
+    #[test]
+    fn test_script_answer_added_when_detector_gives_none() {
+        let detector = CjkScriptDetector::new(FixedLanguageDetector { answer: None });
+        let detection = detector.detect_detailed("안녕하세요").unwrap().unwrap();
+        assert_eq!(detection.language, "ko");
+        assert_eq!(detection.confidence, 1.0);
+        assert_eq!(detection.script.unwrap().action, ScriptAction::Added);
+        assert_eq!(detector.id(), "fixed@none+cjk-script@1");
+    }
+}
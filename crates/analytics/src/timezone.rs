@@ -0,0 +1,105 @@
+use crate::error::AnalyticsError;
+use crate::types::{EpochDay, Timestamp};
+use chrono::{Offset, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Time zone used to bucket timestamps into days.
+///
+/// Parsed from `UTC`, a fixed offset such as `+09:00`, or an IANA name such as
+/// `Asia/Tokyo`. Serialized as the same string.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Zone {
+    #[default]
+    Utc,
+    /// Fixed offset east of UTC in seconds
+    Fixed(i32),
+    Iana(chrono_tz::Tz),
+}
+
+impl Zone {
+    /// Offset east of UTC in seconds at `timestamp`
+    pub fn offset_secs(&self, timestamp: Timestamp) -> i64 {
+        match self {
+            Zone::Utc => 0,
+            Zone::Fixed(offset) => *offset as i64,
+            Zone::Iana(tz) => match chrono::Utc.timestamp_opt(timestamp as i64, 0).single() {
+                Some(utc) => tz
+                    .offset_from_utc_datetime(&utc.naive_utc())
+                    .fix()
+                    .local_minus_utc() as i64,
+                None => 0,
+            },
+        }
+    }
+
+    /// Local calendar day of `timestamp` as days since 1970-01-01
+    pub fn epoch_day(&self, timestamp: Timestamp) -> EpochDay {
+        let local = timestamp as i64 + self.offset_secs(timestamp);
+        local.div_euclid(86400).max(0) as EpochDay
+    }
+
+    pub fn is_utc(&self) -> bool {
+        *self == Zone::Utc
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zone::Utc => write!(f, "UTC"),
+            Zone::Fixed(offset) => {
+                let sign = if *offset < 0 { '-' } else { '+' };
+                let minutes = offset.unsigned_abs() / 60;
+                write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+            }
+            Zone::Iana(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = AnalyticsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AnalyticsError::InvalidParameter(format!("Unknown time zone: {}", s));
+
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(Zone::Utc);
+        }
+        if let Some(rest) = s.strip_prefix('+').or_else(|| s.strip_prefix('-')) {
+            let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+            let hours: i32 = hours.parse().map_err(|_| invalid())?;
+            let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+            if hours > 14 || minutes > 59 {
+                return Err(invalid());
+            }
+            let offset = (hours * 60 + minutes) * 60;
+            let offset = if s.starts_with('-') { -offset } else { offset };
+            return Ok(if offset == 0 {
+                Zone::Utc
+            } else {
+                Zone::Fixed(offset)
+            });
+        }
+        s.parse::<chrono_tz::Tz>()
+            .map(Zone::Iana)
+            .map_err(|_| invalid())
+    }
+}
+
+impl TryFrom<String> for Zone {
+    type Error = AnalyticsError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Zone> for String {
+    fn from(zone: Zone) -> Self {
+        zone.to_string()
+    }
+}
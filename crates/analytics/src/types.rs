@@ -1,5 +1,15 @@
+use crate::error::AnalyticsError;
+use crate::fetch_planner::FetchCoverage;
+use crate::hyperloglog::{DEFAULT_HLL_PRECISION, MAX_HLL_PRECISION, MIN_HLL_PRECISION};
+use crate::labels::LabelMode;
+use crate::language::{default_detector_id, DEFAULT_MIN_CONTENT_CHARS};
+use crate::profile::DEFAULT_PROFILE_WEIGHT;
+use crate::timezone::Zone;
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 use wasm_bindgen::prelude::*;
 
 /// Language code (ISO 639-1)
@@ -33,6 +43,44 @@ pub struct LanguageIndexResult {
 
     /// Events with detected language
     pub events_with_language: u32,
+
+    /// Events skipped because too little text was left after preprocessing
+    #[serde(default)]
+    pub events_too_short: u32,
+
+    /// Events whose language came from a NIP-32 label
+    #[serde(default)]
+    pub events_labeled: u32,
+
+    /// Languages users declared for themselves in kind 1985 labels
+    #[serde(default)]
+    pub self_labels: u32,
+
+    /// Events whose language came from detection
+    #[serde(default)]
+    pub events_detected: u32,
+
+    /// Labeled events whose detected language differed from the label
+    #[serde(default)]
+    pub label_conflicts: u32,
+
+    /// Profiles (kind 0) that added a language prior
+    #[serde(default)]
+    pub profiles_used: u32,
+
+    /// Language detector id (`name@version`)
+    #[serde(default = "default_detector_id")]
+    pub detector: String,
+
+    /// Fetch completeness per source (empty when events were fetched elsewhere)
+    #[wasm_bindgen(skip)]
+    #[serde(default)]
+    pub coverage: Vec<FetchCoverage>,
+
+    /// Uncovered ranges `(since, until)` accepted while extending the index
+    #[wasm_bindgen(skip)]
+    #[serde(default)]
+    pub gaps: Vec<(Timestamp, Timestamp)>,
 }
 
 #[wasm_bindgen]
@@ -41,6 +89,46 @@ impl LanguageIndexResult {
     pub fn by_lang(&self) -> JsValue {
         serde_wasm_bindgen::to_value(&self.by_lang).unwrap_or(JsValue::NULL)
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn coverage(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.coverage).unwrap_or(JsValue::NULL)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn gaps(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.gaps).unwrap_or(JsValue::NULL)
+    }
+
+    /// Lowest per-source completeness ratio (1.0 when no coverage was recorded)
+    pub fn coverage_ratio(&self) -> f32 {
+        if self.coverage.is_empty() {
+            return 1.0;
+        }
+        self.coverage
+            .iter()
+            .map(FetchCoverage::coverage_ratio)
+            .fold(f32::INFINITY, f32::min)
+    }
+}
+
+/// Progress of a builder fed in batches
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamProgress {
+    /// Batches pushed so far
+    pub batches: u32,
+
+    /// Events accepted so far
+    pub events_processed: u32,
+
+    /// Events dropped by validation so far
+    #[serde(default)]
+    pub events_rejected: u32,
+
+    /// Distinct authors of content events (index) or distinct eligible
+    /// users active in any group (metrics; estimated under HyperLogLog
+    /// counting)
+    pub users: u32,
 }
 
 /// Options for building language index
@@ -60,6 +148,106 @@ pub struct LanguageIndexOptions {
 
     /// Maximum languages per user (default 5)
     pub max_langs_per_user: Option<u8>,
+
+    /// Minimum characters left after preprocessing (default 5)
+    #[serde(default)]
+    pub min_content_chars: Option<u32>,
+
+    /// How NIP-32 language labels combine with detection (default "prefer")
+    #[serde(default)]
+    pub label_mode: Option<LabelMode>,
+
+    /// Post-equivalent weight of profile (kind 0) hints (default 2.0, 0 disables)
+    #[serde(default)]
+    pub profile_weight: Option<f32>,
+
+    /// Posts in a language that make it eligible (default 3)
+    #[serde(default)]
+    pub min_posts: Option<u32>,
+
+    /// Share of a user's detected posts that makes a language eligible
+    /// (0.0-1.0, default 0.2)
+    #[serde(default)]
+    pub min_share: Option<f32>,
+}
+
+/// Configuration for building a language index
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IndexConfig {
+    /// Confidence threshold (0.0-1.0)
+    pub conf_thresh: f32,
+
+    /// Maximum languages per user
+    pub max_langs_per_user: u8,
+
+    /// Maximum events to process (optional)
+    pub max_events: Option<u32>,
+
+    /// Minimum characters left after preprocessing for an event to be detected
+    pub min_content_chars: u32,
+
+    /// Which detected languages make a user eligible
+    #[serde(default)]
+    pub eligibility: EligibilityRule,
+
+    /// How NIP-32 language labels combine with detection
+    #[serde(default)]
+    pub label_mode: LabelMode,
+
+    /// Post-equivalent weight of each profile (kind 0) hint; 0 disables
+    #[serde(default = "default_profile_weight")]
+    pub profile_weight: f32,
+}
+
+fn default_profile_weight() -> f32 {
+    DEFAULT_PROFILE_WEIGHT
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            conf_thresh: 0.5,
+            max_langs_per_user: 5,
+            max_events: None,
+            min_content_chars: DEFAULT_MIN_CONTENT_CHARS,
+            eligibility: EligibilityRule::default(),
+            label_mode: LabelMode::default(),
+            profile_weight: DEFAULT_PROFILE_WEIGHT,
+        }
+    }
+}
+
+impl From<&LanguageIndexOptions> for IndexConfig {
+    fn from(options: &LanguageIndexOptions) -> Self {
+        let defaults = Self::default();
+        Self {
+            conf_thresh: options.conf_thresh.unwrap_or(defaults.conf_thresh),
+            max_langs_per_user: options
+                .max_langs_per_user
+                .unwrap_or(defaults.max_langs_per_user),
+            max_events: options.max_events,
+            min_content_chars: options
+                .min_content_chars
+                .unwrap_or(defaults.min_content_chars),
+            eligibility: EligibilityRule {
+                min_posts: options.min_posts.unwrap_or(defaults.eligibility.min_posts),
+                min_share: options.min_share.unwrap_or(defaults.eligibility.min_share),
+            },
+            label_mode: options.label_mode.unwrap_or(defaults.label_mode),
+            profile_weight: options.profile_weight.unwrap_or(defaults.profile_weight),
+        }
+    }
+}
+
+/// What [`LanguageIndex::merge`] does when the two ranges leave a gap
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GapPolicy {
+    /// Return an error
+    #[default]
+    Refuse,
+    /// Merge anyway, log a warning and record the gap
+    Warn,
 }
 
 /// Options for computing metrics
@@ -74,11 +262,222 @@ pub struct MetricsOptions {
     /// Target languages
     pub languages: Vec<LanguageCode>,
 
-    /// Granularity (currently only "day" supported)
-    pub granularity: String,
+    /// Bucket size of the series
+    #[serde(default)]
+    pub granularity: Granularity,
 
-    /// Window size in days (1=DAU, 7=WAU, 30=MAU, 365=YAU)
+    /// Window size in days (1=DAU, 7=WAU, 30=MAU, 365=YAU).
+    /// Only used with `Granularity::Day`.
     pub window_days: u16,
+
+    /// Time zone of day boundaries
+    #[serde(default)]
+    pub zone: Zone,
+
+    /// Whether a language counts on every day or only after recent posts in it
+    #[serde(default)]
+    pub eligibility: EligibilityScope,
+
+    /// Exact distinct users, or HyperLogLog estimates for runs too large to
+    /// keep every user set in memory
+    #[serde(default)]
+    pub counting: CountingMode,
+}
+
+/// Default lookback of [`EligibilityScope::Period`] when none is given
+pub const DEFAULT_LOOKBACK_DAYS: u16 = 90;
+
+/// When a user's language counts toward that language's active users.
+///
+/// Serialized as `lifetime` or `period-<N>d`, the same string as the `elig`
+/// element of the kind 30080 `algo` tag.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum EligibilityScope {
+    /// Every day, once the index assigns the language
+    #[default]
+    Lifetime,
+    /// On day D only if the user posted in the language during the
+    /// `lookback_days` days ending on D
+    Period { lookback_days: u16 },
+}
+
+impl fmt::Display for EligibilityScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EligibilityScope::Lifetime => write!(f, "lifetime"),
+            EligibilityScope::Period { lookback_days } => write!(f, "period-{}d", lookback_days),
+        }
+    }
+}
+
+impl FromStr for EligibilityScope {
+    type Err = AnalyticsError;
+
+    /// `lifetime`, `period` (default lookback) or `period-<N>d`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AnalyticsError::InvalidParameter(format!("Unknown eligibility: {}", s));
+
+        if s == "lifetime" {
+            return Ok(EligibilityScope::Lifetime);
+        }
+        if s == "period" {
+            return Ok(EligibilityScope::Period {
+                lookback_days: DEFAULT_LOOKBACK_DAYS,
+            });
+        }
+        let days = s
+            .strip_prefix("period-")
+            .and_then(|rest| rest.strip_suffix('d'))
+            .ok_or_else(invalid)?;
+        let lookback_days: u16 = days.parse().map_err(|_| invalid())?;
+        if lookback_days == 0 {
+            return Err(invalid());
+        }
+        Ok(EligibilityScope::Period { lookback_days })
+    }
+}
+
+impl TryFrom<String> for EligibilityScope {
+    type Error = AnalyticsError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<EligibilityScope> for String {
+    fn from(scope: EligibilityScope) -> Self {
+        scope.to_string()
+    }
+}
+
+/// How distinct active users are counted.
+///
+/// Serialized as `exact` or `hll-p<N>`, the same string as the `count`
+/// element of the kind 30080 `algo` tag.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum CountingMode {
+    /// Exact sets of users
+    #[default]
+    Exact,
+    /// One HyperLogLog sketch of 2^`precision` registers per group and day;
+    /// counts come with an error bound
+    Approximate { precision: u8 },
+}
+
+impl fmt::Display for CountingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CountingMode::Exact => write!(f, "exact"),
+            CountingMode::Approximate { precision } => write!(f, "hll-p{}", precision),
+        }
+    }
+}
+
+impl FromStr for CountingMode {
+    type Err = AnalyticsError;
+
+    /// `exact`, `hll` (default precision) or `hll-p<N>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AnalyticsError::InvalidParameter(format!("Unknown counting mode: {}", s));
+
+        if s == "exact" {
+            return Ok(CountingMode::Exact);
+        }
+        if s == "hll" {
+            return Ok(CountingMode::Approximate {
+                precision: DEFAULT_HLL_PRECISION,
+            });
+        }
+        let precision: u8 = s
+            .strip_prefix("hll-p")
+            .ok_or_else(invalid)?
+            .parse()
+            .map_err(|_| invalid())?;
+        if !(MIN_HLL_PRECISION..=MAX_HLL_PRECISION).contains(&precision) {
+            return Err(AnalyticsError::InvalidParameter(format!(
+                "HyperLogLog precision must be {}-{}: {}",
+                MIN_HLL_PRECISION, MAX_HLL_PRECISION, s
+            )));
+        }
+        Ok(CountingMode::Approximate { precision })
+    }
+}
+
+impl TryFrom<String> for CountingMode {
+    type Error = AnalyticsError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<CountingMode> for String {
+    fn from(mode: CountingMode) -> Self {
+        mode.to_string()
+    }
+}
+
+/// Bucket size of a metric series
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    /// One point per day, counting distinct users over a sliding window
+    #[default]
+    Day,
+    /// One point per ISO week (Monday start), distinct users in the week
+    Week,
+    /// One point per calendar month, distinct users in the month
+    Month,
+}
+
+impl Granularity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+        }
+    }
+
+    /// First epoch day of the bucket containing `epoch_day`
+    pub fn bucket_start(&self, epoch_day: EpochDay) -> EpochDay {
+        match self {
+            Granularity::Day => epoch_day,
+            Granularity::Week => epoch_day_to_week_start(epoch_day),
+            Granularity::Month => epoch_day_to_month_start(epoch_day),
+        }
+    }
+
+    /// First epoch day of the bucket after the one starting at `bucket_start`
+    pub fn next_bucket(&self, bucket_start: EpochDay) -> EpochDay {
+        match self {
+            Granularity::Day => bucket_start + 1,
+            Granularity::Week => bucket_start + 7,
+            Granularity::Month => {
+                let next = epoch_day_to_date(bucket_start) + Months::new(1);
+                date_to_epoch_day(next.with_day(1).unwrap_or(next))
+            }
+        }
+    }
+}
+
+impl FromStr for Granularity {
+    type Err = AnalyticsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Granularity::Day),
+            "week" => Ok(Granularity::Week),
+            "month" => Ok(Granularity::Month),
+            other => Err(AnalyticsError::InvalidParameter(format!(
+                "Unknown granularity: {}",
+                other
+            ))),
+        }
+    }
 }
 
 /// Single data point in metrics result
@@ -87,15 +486,121 @@ pub struct MetricDataPoint {
     /// Epoch day
     pub epoch_day: EpochDay,
 
-    /// Count of active users
+    /// Count of active users (an estimate with [`CountingMode::Approximate`])
     pub count: u32,
+
+    /// Error bound of an estimated count: about 95% of estimates are within
+    /// `count ± error`. `None` for exact counts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<u32>,
+}
+
+/// Single data point of a ratio series such as DAU/MAU
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RatioDataPoint {
+    /// Epoch day
+    pub epoch_day: EpochDay,
+
+    /// `None` when the denominator is zero
+    pub ratio: Option<f32>,
+}
+
+/// Evidence collected for one of a user's languages
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LanguageEvidence {
+    /// Posts detected in this language
+    pub posts: u32,
+
+    /// Sum of detection confidences
+    pub confidence_sum: f32,
+
+    /// Earliest and latest post (`None` when added without a timestamp)
+    pub first_seen: Option<Timestamp>,
+    pub last_seen: Option<Timestamp>,
+
+    /// Posts per UTC epoch day, for period eligibility
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub daily_posts: BTreeMap<EpochDay, u32>,
+
+    /// Post-equivalent weight of profile metadata hints (kind 0)
+    #[serde(default)]
+    pub prior: f32,
+
+    /// Sum of hint confidences times their weight
+    #[serde(default)]
+    pub prior_confidence_sum: f32,
+
+    /// Post-equivalent weight of evidence that is not a post of its own:
+    /// kind 1985 self-labels and detections overruled by a label
+    #[serde(default)]
+    pub signal: f32,
+
+    /// Sum of signal confidences times their weight
+    #[serde(default)]
+    pub signal_confidence_sum: f32,
+}
+
+impl LanguageEvidence {
+    /// Posts plus the profile prior and other signals
+    pub fn weight(&self) -> f32 {
+        self.posts as f32 + self.prior + self.signal
+    }
+
+    /// Confidence averaged over posts, the prior and signals
+    pub fn mean_confidence(&self) -> f32 {
+        let weight = self.weight();
+        if weight <= 0.0 {
+            return 0.0;
+        }
+        (self.confidence_sum + self.prior_confidence_sum + self.signal_confidence_sum) / weight
+    }
+
+    /// Add the posts and signals of `other`, e.g. from a later batch of
+    /// events
+    pub fn merge_posts(&mut self, other: &LanguageEvidence) {
+        self.posts += other.posts;
+        self.confidence_sum += other.confidence_sum;
+        self.signal += other.signal;
+        self.signal_confidence_sum += other.signal_confidence_sum;
+        self.first_seen = match (self.first_seen, other.first_seen) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.last_seen = self.last_seen.max(other.last_seen);
+        for (&day, &posts) in &other.daily_posts {
+            *self.daily_posts.entry(day).or_insert(0) += posts;
+        }
+    }
+}
+
+/// Which of a user's languages count toward that language's users
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct EligibilityRule {
+    /// Eligible with at least this many posts in the language...
+    pub min_posts: u32,
+
+    /// ...or at least this share (0.0-1.0) of the user's detected posts
+    pub min_share: f32,
+}
+
+impl Default for EligibilityRule {
+    fn default() -> Self {
+        Self {
+            min_posts: 3,
+            min_share: 0.2,
+        }
+    }
 }
 
 /// Structure to track user languages
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UserLanguages {
-    /// Languages detected for this user with confidence scores
+    /// Top languages by evidence with their mean confidence
     pub languages: HashMap<LanguageCode, f32>,
+
+    /// Evidence for every language seen
+    #[serde(default)]
+    pub evidence: HashMap<LanguageCode, LanguageEvidence>,
 }
 
 impl Default for UserLanguages {
@@ -108,35 +613,228 @@ impl UserLanguages {
     pub fn new() -> Self {
         Self {
             languages: HashMap::new(),
+            evidence: HashMap::new(),
         }
     }
 
+    /// Record one detected post without a timestamp
     pub fn add_language(&mut self, lang: LanguageCode, confidence: f32, max_langs: u8) {
-        // Update or insert
-        self.languages
-            .entry(lang)
-            .and_modify(|c| *c = c.max(confidence))
-            .or_insert(confidence);
+        self.record(lang, confidence, None, max_langs);
+    }
+
+    /// Record one detected post created at `created_at`
+    pub fn add_post(
+        &mut self,
+        lang: LanguageCode,
+        confidence: f32,
+        created_at: Timestamp,
+        max_langs: u8,
+    ) {
+        self.record(lang, confidence, Some(created_at), max_langs);
+    }
 
-        // Keep only top N languages
-        if self.languages.len() > max_langs as usize {
-            let mut langs: Vec<_> = self.languages.iter().collect();
-            langs.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
-            self.languages = langs
-                .into_iter()
-                .take(max_langs as usize)
-                .map(|(k, v)| (k.clone(), *v))
-                .collect();
+    fn record(
+        &mut self,
+        lang: LanguageCode,
+        confidence: f32,
+        created_at: Option<Timestamp>,
+        max_langs: u8,
+    ) {
+        let evidence = self.evidence.entry(lang).or_default();
+        evidence.posts += 1;
+        evidence.confidence_sum += confidence;
+        if let Some(created_at) = created_at {
+            evidence.first_seen = Some(
+                evidence
+                    .first_seen
+                    .map_or(created_at, |t| t.min(created_at)),
+            );
+            evidence.last_seen = Some(evidence.last_seen.map_or(created_at, |t| t.max(created_at)));
+            *evidence
+                .daily_posts
+                .entry(timestamp_to_epoch_day(created_at))
+                .or_insert(0) += 1;
         }
+        self.rank(max_langs);
+    }
+
+    /// Take back one post added by [`UserLanguages::add_post`], e.g. when a
+    /// label overrides its detection. `first_seen` and `last_seen` keep the
+    /// post unless no post in `lang` is left.
+    pub(crate) fn remove_post(
+        &mut self,
+        lang: &str,
+        confidence: f32,
+        created_at: Timestamp,
+        max_langs: u8,
+    ) {
+        let Some(evidence) = self.evidence.get_mut(lang) else {
+            return;
+        };
+        evidence.posts = evidence.posts.saturating_sub(1);
+        evidence.confidence_sum = (evidence.confidence_sum - confidence).max(0.0);
+        let day = timestamp_to_epoch_day(created_at);
+        if let Some(posts) = evidence.daily_posts.get_mut(&day) {
+            *posts -= 1;
+            if *posts == 0 {
+                evidence.daily_posts.remove(&day);
+            }
+        }
+        if evidence.posts == 0 {
+            evidence.first_seen = None;
+            evidence.last_seen = None;
+            evidence.confidence_sum = 0.0;
+        }
+        if evidence.weight() <= 0.0 {
+            self.evidence.remove(lang);
+        }
+        self.rank(max_langs);
+    }
+
+    /// Add a profile hint worth `weight` posts detected with `confidence`
+    pub fn add_prior(&mut self, lang: LanguageCode, confidence: f32, weight: f32, max_langs: u8) {
+        if weight.is_nan() || weight <= 0.0 {
+            return;
+        }
+        let evidence = self.evidence.entry(lang).or_default();
+        evidence.prior += weight;
+        evidence.prior_confidence_sum += confidence * weight;
+        self.rank(max_langs);
+    }
+
+    /// Add a signal worth `weight` posts with `confidence` that is not a
+    /// post itself, such as a kind 1985 self-label. Unlike a profile prior it
+    /// accumulates, and it has no day for period eligibility.
+    pub fn add_signal(&mut self, lang: LanguageCode, confidence: f32, weight: f32, max_langs: u8) {
+        if weight.is_nan() || weight <= 0.0 {
+            return;
+        }
+        let evidence = self.evidence.entry(lang).or_default();
+        evidence.signal += weight;
+        evidence.signal_confidence_sum += confidence * weight;
+        self.rank(max_langs);
+    }
+
+    /// Add the evidence of `other` (a later batch of events) and re-rank.
+    /// A profile prior in `other` replaces this one, since only the latest
+    /// profile counts.
+    pub fn merge(&mut self, other: UserLanguages, max_langs: u8) {
+        if self.evidence.is_empty() && other.evidence.is_empty() {
+            self.languages.extend(other.languages);
+            return;
+        }
+        if other.evidence.values().any(|e| e.prior > 0.0) {
+            for evidence in self.evidence.values_mut() {
+                evidence.prior = 0.0;
+                evidence.prior_confidence_sum = 0.0;
+            }
+        }
+        for (lang, other_evidence) in other.evidence {
+            let evidence = self.evidence.entry(lang).or_default();
+            evidence.merge_posts(&other_evidence);
+            evidence.prior += other_evidence.prior;
+            evidence.prior_confidence_sum += other_evidence.prior_confidence_sum;
+        }
+        self.evidence.retain(|_, e| e.weight() > 0.0);
+        self.rank(max_langs);
+    }
+
+    /// Keep only top N languages by weight, then summed confidence
+    pub(crate) fn rank(&mut self, max_langs: u8) {
+        let mut ranked: Vec<(&LanguageCode, &LanguageEvidence)> = self.evidence.iter().collect();
+        ranked.sort_by(|a, b| {
+            b.1.weight()
+                .total_cmp(&a.1.weight())
+                .then_with(|| b.1.confidence_sum.total_cmp(&a.1.confidence_sum))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        self.languages = ranked
+            .into_iter()
+            .take(max_langs as usize)
+            .map(|(lang, evidence)| (lang.clone(), evidence.mean_confidence()))
+            .collect();
+    }
+
+    /// Detected posts across all languages
+    pub fn total_posts(&self) -> u32 {
+        self.evidence.values().map(|e| e.posts).sum()
+    }
+
+    /// Posts plus profile priors and signals across all languages
+    pub fn total_weight(&self) -> f32 {
+        self.evidence.values().map(|e| e.weight()).sum()
+    }
+
+    /// Share (0.0-1.0) of the evidence weight in `lang`
+    pub fn share(&self, lang: &str) -> f32 {
+        let total = self.total_weight();
+        match self.evidence.get(lang) {
+            Some(evidence) if total > 0.0 => evidence.weight() / total,
+            _ => 0.0,
+        }
+    }
+
+    /// Whether `lang` is among the top languages and satisfies `rule`, with
+    /// profile priors and signals counting as posts.
+    /// Languages without evidence (e.g. loaded from a plain map) are eligible.
+    pub fn is_eligible(&self, lang: &str, rule: &EligibilityRule) -> bool {
+        if !self.languages.contains_key(lang) {
+            return false;
+        }
+        match self.evidence.get(lang) {
+            Some(evidence) => {
+                evidence.weight() >= rule.min_posts as f32 || self.share(lang) >= rule.min_share
+            }
+            None => true,
+        }
+    }
+
+    /// Drop languages from `languages` that do not satisfy `rule`
+    pub fn apply_eligibility(&mut self, rule: &EligibilityRule) {
+        let eligible: HashSet<LanguageCode> = self
+            .languages
+            .keys()
+            .filter(|lang| self.is_eligible(lang, rule))
+            .cloned()
+            .collect();
+        self.languages.retain(|lang, _| eligible.contains(lang));
     }
 
     pub fn has_language(&self, lang: &str) -> bool {
         self.languages.contains_key(lang)
     }
+
+    /// Whether `lang` counts for this user on UTC day `epoch_day` under
+    /// `scope` (evidence is keyed by UTC day, so callers bucketing in another
+    /// zone pass the event's UTC day). Languages without dated evidence never
+    /// count under a period scope.
+    pub fn has_language_on(
+        &self,
+        lang: &str,
+        epoch_day: EpochDay,
+        scope: EligibilityScope,
+    ) -> bool {
+        if !self.has_language(lang) {
+            return false;
+        }
+        match scope {
+            EligibilityScope::Lifetime => true,
+            EligibilityScope::Period { lookback_days } => {
+                let from = epoch_day.saturating_sub(lookback_days.max(1) as u32 - 1);
+                self.evidence.get(lang).is_some_and(|evidence| {
+                    evidence
+                        .daily_posts
+                        .range(from..=epoch_day)
+                        .next()
+                        .is_some()
+                })
+            }
+        }
+    }
 }
 
 /// Structure for language index
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct LanguageIndex {
     /// Map from pubkey to their languages
     pub user_languages: HashMap<PubkeyHex, UserLanguages>,
@@ -147,6 +845,47 @@ pub struct LanguageIndex {
     /// Time range covered
     pub since: Timestamp,
     pub until: Timestamp,
+
+    /// Events fed to the builder
+    pub events_processed: u32,
+
+    /// Events whose detected language passed the threshold
+    pub events_with_language: u32,
+
+    /// Events skipped because too little text was left after preprocessing
+    pub events_too_short: u32,
+
+    /// Events whose language came from a label
+    #[serde(default)]
+    pub events_labeled: u32,
+
+    /// (user, language) self-labels applied as signals
+    #[serde(default)]
+    pub self_labels: u32,
+
+    /// Events whose language came from detection
+    #[serde(default)]
+    pub events_detected: u32,
+
+    /// Labeled events whose detected language differed from the label
+    #[serde(default)]
+    pub label_conflicts: u32,
+
+    /// Profiles (kind 0) that added a language prior
+    #[serde(default)]
+    pub profiles_used: u32,
+
+    /// Language detector id (`name@version`)
+    pub detector: String,
+
+    /// Users with evidence but no eligible language yet, kept so later
+    /// batches can add to their evidence
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pending: HashMap<PubkeyHex, UserLanguages>,
+
+    /// Uncovered ranges `(since, until)` accepted while merging
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gaps: Vec<(Timestamp, Timestamp)>,
 }
 
 impl LanguageIndex {
@@ -156,6 +895,248 @@ impl LanguageIndex {
             built_at: Self::current_timestamp(),
             since,
             until,
+            events_processed: 0,
+            events_with_language: 0,
+            events_too_short: 0,
+            events_labeled: 0,
+            self_labels: 0,
+            events_detected: 0,
+            label_conflicts: 0,
+            profiles_used: 0,
+            detector: default_detector_id(),
+            pending: HashMap::new(),
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Build from the `userLanguages` map exchanged with JS
+    pub fn from_language_map(
+        map: HashMap<PubkeyHex, HashMap<LanguageCode, f32>>,
+        since: Timestamp,
+        until: Timestamp,
+    ) -> Self {
+        let mut index = Self::new(since, until);
+        index.user_languages = map
+            .into_iter()
+            .map(|(pubkey, languages)| {
+                let user_langs = UserLanguages {
+                    languages,
+                    evidence: HashMap::new(),
+                };
+                (pubkey, user_langs)
+            })
+            .collect();
+        index
+    }
+
+    /// Attach per-language evidence (as from [`Self::to_evidence_map`]) to
+    /// the users already in the index
+    pub fn with_evidence(
+        mut self,
+        evidence: HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>>,
+    ) -> Self {
+        for (pubkey, evidence) in evidence {
+            if let Some(user_langs) = self.user_languages.get_mut(&pubkey) {
+                user_langs.evidence = evidence;
+            }
+        }
+        self
+    }
+
+    /// Covers no time range and has no users, like `LanguageIndex::new(0, 0)`
+    /// or an index built from no events. An index over a real range with no
+    /// events is not empty: its range still counts when merging.
+    pub fn is_empty(&self) -> bool {
+        self.since == 0
+            && self.until == 0
+            && self.user_languages.is_empty()
+            && self.pending.is_empty()
+    }
+
+    /// Apply `rule` to every user. Users left without languages move to
+    /// `pending`.
+    pub fn apply_eligibility(&mut self, rule: &EligibilityRule) {
+        for user_langs in self.user_languages.values_mut() {
+            user_langs.apply_eligibility(rule);
+        }
+        let ineligible: Vec<PubkeyHex> = self
+            .user_languages
+            .iter()
+            .filter(|(_, user_langs)| user_langs.languages.is_empty())
+            .map(|(pubkey, _)| pubkey.clone())
+            .collect();
+        for pubkey in ineligible {
+            if let Some(user_langs) = self.user_languages.remove(&pubkey) {
+                if !user_langs.evidence.is_empty() {
+                    self.pending.insert(pubkey, user_langs);
+                }
+            }
+        }
+    }
+
+    /// Merge an index of an adjacent time range into this one, re-ranking
+    /// every user's languages and re-applying `config.eligibility`.
+    ///
+    /// Overlapping ranges are refused because their events would be counted
+    /// twice; a gap between the ranges is refused or recorded in `gaps`
+    /// according to `gap_policy`. Returns the gap this merge left, for the
+    /// caller to report.
+    pub fn merge(
+        &mut self,
+        other: LanguageIndex,
+        config: &IndexConfig,
+        gap_policy: GapPolicy,
+    ) -> Result<Option<(Timestamp, Timestamp)>, AnalyticsError> {
+        if self.is_empty() {
+            *self = other;
+            return Ok(None);
+        }
+        if other.detector != self.detector {
+            return Err(AnalyticsError::InvalidParameter(format!(
+                "Cannot merge indexes built with different detectors: {} and {}",
+                self.detector, other.detector
+            )));
+        }
+        if other.since <= self.until && other.until >= self.since {
+            return Err(AnalyticsError::InvalidParameter(format!(
+                "Index ranges {}..={} and {}..={} overlap",
+                self.since, self.until, other.since, other.until
+            )));
+        }
+        let gap = if other.since > self.until.saturating_add(1) {
+            Some((self.until + 1, other.since - 1))
+        } else if other.until.saturating_add(1) < self.since {
+            Some((other.until + 1, self.since - 1))
+        } else {
+            None
+        };
+        if let Some((from, to)) = gap {
+            match gap_policy {
+                GapPolicy::Refuse => {
+                    return Err(AnalyticsError::InvalidParameter(format!(
+                        "Index ranges leave a gap {}..={}",
+                        from, to
+                    )));
+                }
+                GapPolicy::Warn => self.gaps.push((from, to)),
+            }
+        }
+
+        let max_langs = config.max_langs_per_user;
+        let pending = std::mem::take(&mut self.pending);
+        self.user_languages.extend(pending);
+        for (pubkey, user_langs) in other.user_languages.into_iter().chain(other.pending) {
+            self.user_languages
+                .entry(pubkey)
+                .or_default()
+                .merge(user_langs, max_langs);
+        }
+        // Restore languages an earlier eligibility pass removed
+        for user_langs in self.user_languages.values_mut() {
+            if !user_langs.evidence.is_empty() {
+                user_langs.rank(max_langs);
+            }
+        }
+        self.apply_eligibility(&config.eligibility);
+
+        self.since = self.since.min(other.since);
+        self.until = self.until.max(other.until);
+        self.built_at = Self::current_timestamp();
+        self.events_processed += other.events_processed;
+        self.events_with_language += other.events_with_language;
+        self.events_too_short += other.events_too_short;
+        self.events_labeled += other.events_labeled;
+        self.self_labels += other.self_labels;
+        self.events_detected += other.events_detected;
+        self.label_conflicts += other.label_conflicts;
+        self.profiles_used += other.profiles_used;
+        self.gaps.extend(other.gaps);
+        self.gaps.sort_unstable();
+
+        Ok(gap)
+    }
+
+    /// Convert to the `userLanguages` map exchanged with JS
+    pub fn to_language_map(&self) -> HashMap<PubkeyHex, HashMap<LanguageCode, f32>> {
+        self.user_languages
+            .iter()
+            .map(|(pubkey, langs)| (pubkey.clone(), langs.languages.clone()))
+            .collect()
+    }
+
+    /// Per-language evidence of every user
+    pub fn to_evidence_map(&self) -> HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>> {
+        self.user_languages
+            .iter()
+            .map(|(pubkey, langs)| (pubkey.clone(), langs.evidence.clone()))
+            .collect()
+    }
+
+    /// Per-language evidence of the `pending` users
+    pub fn to_pending_map(&self) -> HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>> {
+        self.pending
+            .iter()
+            .map(|(pubkey, langs)| (pubkey.clone(), langs.evidence.clone()))
+            .collect()
+    }
+
+    /// Rebuild an index saved as its result, `userLanguages` map, evidence
+    /// and pending evidence, so it can be extended
+    pub fn restore(
+        result: &LanguageIndexResult,
+        map: HashMap<PubkeyHex, HashMap<LanguageCode, f32>>,
+        evidence: HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>>,
+        pending: HashMap<PubkeyHex, HashMap<LanguageCode, LanguageEvidence>>,
+        since: Timestamp,
+        until: Timestamp,
+    ) -> Self {
+        let mut index = Self::from_language_map(map, since, until).with_evidence(evidence);
+        index.pending = pending
+            .into_iter()
+            .map(|(pubkey, evidence)| {
+                let user_langs = UserLanguages {
+                    languages: HashMap::new(),
+                    evidence,
+                };
+                (pubkey, user_langs)
+            })
+            .collect();
+        index.events_processed = result.events_processed;
+        index.events_with_language = result.events_with_language;
+        index.events_too_short = result.events_too_short;
+        index.events_labeled = result.events_labeled;
+        index.self_labels = result.self_labels;
+        index.events_detected = result.events_detected;
+        index.label_conflicts = result.label_conflicts;
+        index.profiles_used = result.profiles_used;
+        index.detector = result.detector.clone();
+        index.gaps = result.gaps.clone();
+        index
+    }
+
+    /// Summary stats for this index
+    pub fn result(&self) -> LanguageIndexResult {
+        let mut by_lang: HashMap<LanguageCode, u32> = HashMap::new();
+        for user_langs in self.user_languages.values() {
+            for lang in user_langs.languages.keys() {
+                *by_lang.entry(lang.clone()).or_insert(0) += 1;
+            }
+        }
+
+        LanguageIndexResult {
+            users: self.user_languages.len() as u32,
+            by_lang,
+            events_processed: self.events_processed,
+            events_with_language: self.events_with_language,
+            events_too_short: self.events_too_short,
+            events_labeled: self.events_labeled,
+            self_labels: self.self_labels,
+            events_detected: self.events_detected,
+            label_conflicts: self.label_conflicts,
+            profiles_used: self.profiles_used,
+            detector: self.detector.clone(),
+            coverage: Vec::new(),
+            gaps: self.gaps.clone(),
         }
     }
 
@@ -180,6 +1161,15 @@ impl LanguageIndex {
             .map(|(pubkey, _)| pubkey.clone())
             .collect()
     }
+
+    /// Users having any of the given languages
+    pub fn get_users_for_languages(&self, langs: &[LanguageCode]) -> HashSet<PubkeyHex> {
+        self.user_languages
+            .iter()
+            .filter(|(_, user_langs)| langs.iter().any(|lang| user_langs.has_language(lang)))
+            .map(|(pubkey, _)| pubkey.clone())
+            .collect()
+    }
 }
 
 /// Helper to convert timestamp to epoch day
@@ -191,3 +1181,27 @@ pub fn timestamp_to_epoch_day(timestamp: Timestamp) -> EpochDay {
 pub fn epoch_day_to_timestamp(epoch_day: EpochDay) -> Timestamp {
     epoch_day as Timestamp * 86400
 }
+
+/// Helper to get the Monday starting the ISO week of an epoch day
+/// (1970-01-01 was a Thursday). Days 0-2 belong to a week starting before
+/// the epoch and are clamped to day 0.
+pub fn epoch_day_to_week_start(epoch_day: EpochDay) -> EpochDay {
+    epoch_day.saturating_sub((epoch_day + 3) % 7)
+}
+
+/// Helper to get the first day of the calendar month of an epoch day
+pub fn epoch_day_to_month_start(epoch_day: EpochDay) -> EpochDay {
+    let date = epoch_day_to_date(epoch_day);
+    epoch_day - (date.day() - 1)
+}
+
+/// Helper to convert epoch day to a calendar date
+pub fn epoch_day_to_date(epoch_day: EpochDay) -> NaiveDate {
+    DateTime::UNIX_EPOCH.date_naive() + Days::new(epoch_day.into())
+}
+
+/// Helper to convert a calendar date to epoch day (dates before 1970 clamp
+/// to day 0)
+pub fn date_to_epoch_day(date: NaiveDate) -> EpochDay {
+    (date - DateTime::UNIX_EPOCH.date_naive()).num_days().max(0) as EpochDay
+}
@@ -1,3 +1,4 @@
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 /// Set up better panic messages in wasm
@@ -6,6 +7,7 @@ pub fn set_panic_hook() {
 }
 
 /// Log to browser console
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
@@ -18,6 +20,23 @@ extern "C" {
     pub fn warn(s: &str);
 }
 
+/// Native builds have no console; progress logs are dropped and
+/// warnings/errors go to stderr
+#[cfg(not(target_arch = "wasm32"))]
+pub fn log(_s: &str) {}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(dead_code)]
+pub fn error(s: &str) {
+    eprintln!("{}", s);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(dead_code)]
+pub fn warn(s: &str) {
+    eprintln!("{}", s);
+}
+
 #[allow(unused_macros)]
 macro_rules! console_log {
     ($($t:tt)*) => {
@@ -0,0 +1,296 @@
+// Event validation
+//
+// Relays return whatever they store: events with ids that do not match their
+// content, forged signatures, or `created_at` values in the future or before
+// Nostr existed. Such events would count as activity, so the pipeline can
+// drop them first and report how many were rejected and why.
+
+use crate::error::AnalyticsError;
+use crate::relay_client::NostrEvent;
+use crate::types::Timestamp;
+use nostr::hashes::sha256::Hash as Sha256Hash;
+use nostr::hashes::Hash;
+use nostr::secp256k1::schnorr::Signature;
+use nostr::secp256k1::Message;
+use nostr::{EventId, PublicKey, SECP256K1};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// 2020-11-01 UTC, shortly before the first Nostr events. Earlier
+/// `created_at` values are bogus.
+pub const NOSTR_EPOCH: Timestamp = 1_604_188_800;
+
+/// Seconds an event may be ahead of the validating clock by default
+pub const DEFAULT_MAX_CLOCK_SKEW: u64 = 900;
+
+/// Checks applied to each event
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ValidationPolicy {
+    /// Recompute the NIP-01 id from the event's fields
+    #[serde(default = "default_true")]
+    pub verify_ids: bool,
+
+    /// Verify the Schnorr signature of the id (slow: about a thousand times
+    /// the cost of the id check, so off by default)
+    #[serde(default)]
+    pub verify_signatures: bool,
+
+    /// Seconds `created_at` may be ahead of now; `None` disables the check
+    #[serde(default = "default_max_clock_skew")]
+    pub max_clock_skew: Option<u64>,
+
+    /// Earliest accepted `created_at`; 0 disables the check
+    #[serde(default = "default_min_created_at")]
+    pub min_created_at: Timestamp,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_clock_skew() -> Option<u64> {
+    Some(DEFAULT_MAX_CLOCK_SKEW)
+}
+
+fn default_min_created_at() -> Timestamp {
+    NOSTR_EPOCH
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        Self {
+            verify_ids: true,
+            verify_signatures: false,
+            max_clock_skew: default_max_clock_skew(),
+            min_created_at: NOSTR_EPOCH,
+        }
+    }
+}
+
+/// Why an event was rejected. Checks run in this order and the first
+/// failure is reported.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// `created_at` before [`ValidationPolicy::min_created_at`]
+    PreNostrTimestamp,
+    /// `created_at` more than [`ValidationPolicy::max_clock_skew`] ahead
+    FutureTimestamp,
+    /// Id, pubkey or signature is not hex of the right length
+    Malformed,
+    /// Id does not match the event's fields
+    InvalidId,
+    /// Signature does not verify against the pubkey
+    InvalidSignature,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::PreNostrTimestamp => "pre_nostr_timestamp",
+            RejectReason::FutureTimestamp => "future_timestamp",
+            RejectReason::Malformed => "malformed",
+            RejectReason::InvalidId => "invalid_id",
+            RejectReason::InvalidSignature => "invalid_signature",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RejectReason {
+    type Err = AnalyticsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pre_nostr_timestamp" => Ok(RejectReason::PreNostrTimestamp),
+            "future_timestamp" => Ok(RejectReason::FutureTimestamp),
+            "malformed" => Ok(RejectReason::Malformed),
+            "invalid_id" => Ok(RejectReason::InvalidId),
+            "invalid_signature" => Ok(RejectReason::InvalidSignature),
+            other => Err(AnalyticsError::InvalidParameter(format!(
+                "Unknown reject reason: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Events checked and rejected, by reason
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub checked: u32,
+    pub accepted: u32,
+    pub rejected: BTreeMap<RejectReason, u32>,
+}
+
+impl ValidationReport {
+    pub fn rejected_total(&self) -> u32 {
+        self.rejected.values().sum()
+    }
+
+    /// Add the counts of `other`, e.g. another batch
+    pub fn merge(&mut self, other: &ValidationReport) {
+        self.checked += other.checked;
+        self.accepted += other.accepted;
+        for (reason, count) in &other.rejected {
+            *self.rejected.entry(*reason).or_default() += count;
+        }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    /// `3 of 120 events rejected (invalid_id: 2, future_timestamp: 1)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} events rejected",
+            self.rejected_total(),
+            self.checked
+        )?;
+        if !self.rejected.is_empty() {
+            let reasons: Vec<String> = self
+                .rejected
+                .iter()
+                .map(|(reason, count)| format!("{}: {}", reason, count))
+                .collect();
+            write!(f, " ({})", reasons.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies a [`ValidationPolicy`] against a fixed clock and counts the
+/// outcome across any number of batches
+#[derive(Clone, Debug)]
+pub struct EventValidator {
+    policy: ValidationPolicy,
+    now: Timestamp,
+    report: ValidationReport,
+}
+
+impl EventValidator {
+    /// Validator judging future timestamps against `now`
+    pub fn new(policy: ValidationPolicy, now: Timestamp) -> Self {
+        Self {
+            policy,
+            now,
+            report: ValidationReport::default(),
+        }
+    }
+
+    pub fn policy(&self) -> &ValidationPolicy {
+        &self.policy
+    }
+
+    /// First check `event` fails, without counting it
+    pub fn check(&self, event: &NostrEvent) -> Result<(), RejectReason> {
+        let policy = &self.policy;
+        if event.created_at < policy.min_created_at {
+            return Err(RejectReason::PreNostrTimestamp);
+        }
+        if let Some(skew) = policy.max_clock_skew {
+            if event.created_at > self.now.saturating_add(skew) {
+                return Err(RejectReason::FutureTimestamp);
+            }
+        }
+        if policy.verify_ids {
+            verify_id(event)?;
+        }
+        if policy.verify_signatures {
+            verify_signature(event)?;
+        }
+        Ok(())
+    }
+
+    /// Check and count `event`; returns whether it was accepted
+    pub fn accept(&mut self, event: &NostrEvent) -> bool {
+        self.report.checked += 1;
+        match self.check(event) {
+            Ok(()) => {
+                self.report.accepted += 1;
+                true
+            }
+            Err(reason) => {
+                *self.report.rejected.entry(reason).or_default() += 1;
+                false
+            }
+        }
+    }
+
+    /// Drop the rejected events of `events`
+    pub fn retain_valid(&mut self, events: &mut Vec<NostrEvent>) {
+        events.retain(|event| self.accept(event));
+    }
+
+    /// Counts so far
+    pub fn report(&self) -> &ValidationReport {
+        &self.report
+    }
+
+    pub fn into_report(self) -> ValidationReport {
+        self.report
+    }
+}
+
+/// Drop the events of `events` that fail `policy`, judging future
+/// timestamps against `now`
+pub fn validate_events(
+    events: &mut Vec<NostrEvent>,
+    policy: &ValidationPolicy,
+    now: Timestamp,
+) -> ValidationReport {
+    let mut validator = EventValidator::new(policy.clone(), now);
+    validator.retain_valid(events);
+    validator.into_report()
+}
+
+/// Whether the id of `event` matches its fields (NIP-01)
+pub fn verify_id(event: &NostrEvent) -> Result<(), RejectReason> {
+    let id = parse_id(event)?;
+    let pubkey = parse_pubkey(event)?;
+    // The tags are hashed as they came: `nostr::Tag` refuses some arrays
+    // NIP-01 allows, such as an empty `[]`
+    let serialized = serde_json::json!([
+        0,
+        pubkey.to_hex(),
+        event.created_at,
+        event.kind,
+        event.tags,
+        event.content
+    ])
+    .to_string();
+    let hash = Sha256Hash::hash(serialized.as_bytes());
+    if EventId::from_byte_array(hash.to_byte_array()) == id {
+        Ok(())
+    } else {
+        Err(RejectReason::InvalidId)
+    }
+}
+
+/// Whether the signature of `event` verifies its id against its pubkey
+pub fn verify_signature(event: &NostrEvent) -> Result<(), RejectReason> {
+    let id = parse_id(event)?;
+    let pubkey = parse_pubkey(event)?
+        .xonly()
+        .map_err(|_| RejectReason::Malformed)?;
+    let sig = Signature::from_str(&event.sig).map_err(|_| RejectReason::Malformed)?;
+    let message = Message::from_digest(id.to_bytes());
+    SECP256K1
+        .verify_schnorr(&sig, &message, &pubkey)
+        .map_err(|_| RejectReason::InvalidSignature)
+}
+
+fn parse_id(event: &NostrEvent) -> Result<EventId, RejectReason> {
+    EventId::from_hex(&event.id).map_err(|_| RejectReason::Malformed)
+}
+
+fn parse_pubkey(event: &NostrEvent) -> Result<PublicKey, RejectReason> {
+    PublicKey::from_hex(&event.pubkey).map_err(|_| RejectReason::Malformed)
+}
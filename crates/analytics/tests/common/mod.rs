@@ -0,0 +1,176 @@
+// In-process mock relay for relay client tests
+#![allow(dead_code)]
+
+use nostr_analytics::{Filter, NostrEvent};
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// How the mock relay answers a REQ
+#[derive(Clone, Debug)]
+pub enum ReqBehavior {
+    /// Send matching events followed by EOSE
+    Serve,
+    /// Reply with CLOSED and the given reason
+    Close(String),
+    /// Never answer (used for timeout tests)
+    Ignore,
+}
+
+#[derive(Clone)]
+pub struct MockRelayConfig {
+    pub events: Vec<NostrEvent>,
+
+    /// Maximum events returned per filter, like relays that cap REQ results
+    pub max_limit: Option<usize>,
+
+    /// NOTICE sent right after the connection opens
+    pub greeting_notice: Option<String>,
+
+    pub behavior: ReqBehavior,
+}
+
+impl Default for MockRelayConfig {
+    fn default() -> Self {
+        Self {
+            events: Vec::new(),
+            max_limit: None,
+            greeting_notice: None,
+            behavior: ReqBehavior::Serve,
+        }
+    }
+}
+
+/// Every REQ received as (subscription id, filters)
+pub type RequestLog = Arc<Mutex<Vec<(String, Vec<Filter>)>>>;
+
+pub struct MockRelay {
+    pub url: String,
+
+    pub requests: RequestLog,
+
+    /// Subscription ids received in CLOSE
+    pub closed: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockRelay {
+    pub fn start(config: MockRelayConfig) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(Mutex::new(Vec::new()));
+
+        let (req_log, close_log) = (requests.clone(), closed.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let _ = stream.set_nodelay(true);
+                let config = config.clone();
+                let (req_log, close_log) = (req_log.clone(), close_log.clone());
+                thread::spawn(move || {
+                    let Ok(mut socket) = tungstenite::accept(stream) else {
+                        return;
+                    };
+                    if let Some(notice) = &config.greeting_notice {
+                        let _ = socket.send(json!(["NOTICE", notice]).to_string().into());
+                    }
+                    while let Ok(message) = socket.read() {
+                        let Ok(text) = message.to_text() else { break };
+                        let Ok(Value::Array(parts)) = serde_json::from_str::<Value>(text) else {
+                            continue;
+                        };
+                        let sub_id = parts.get(1).and_then(Value::as_str).unwrap_or_default();
+                        match parts.first().and_then(Value::as_str) {
+                            Some("REQ") => {
+                                let filters: Vec<Filter> = parts[2..]
+                                    .iter()
+                                    .map(|f| serde_json::from_value(f.clone()).unwrap())
+                                    .collect();
+                                req_log
+                                    .lock()
+                                    .unwrap()
+                                    .push((sub_id.to_string(), filters.clone()));
+                                let replies = match &config.behavior {
+                                    ReqBehavior::Serve => {
+                                        let mut replies: Vec<Value> =
+                                            matching_events(&config, &filters)
+                                                .into_iter()
+                                                .map(|e| json!(["EVENT", sub_id, e]))
+                                                .collect();
+                                        replies.push(json!(["EOSE", sub_id]));
+                                        replies
+                                    }
+                                    ReqBehavior::Close(reason) => {
+                                        vec![json!(["CLOSED", sub_id, reason])]
+                                    }
+                                    ReqBehavior::Ignore => Vec::new(),
+                                };
+                                for reply in replies {
+                                    if socket.send(reply.to_string().into()).is_err() {
+                                        return;
+                                    }
+                                }
+                            }
+                            Some("CLOSE") => {
+                                close_log.lock().unwrap().push(sub_id.to_string());
+                            }
+                            Some("EVENT") => {
+                                let id = parts
+                                    .get(1)
+                                    .and_then(|e| e.get("id"))
+                                    .and_then(Value::as_str)
+                                    .unwrap_or_default();
+                                let _ = socket.send(json!(["OK", id, true, ""]).to_string().into());
+                            }
+                            _ => {}
+                        }
+                    }
+                });
+            }
+        });
+
+        Self {
+            url,
+            requests,
+            closed,
+        }
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+/// Events matching any filter, newest first, capped like a real relay
+fn matching_events(config: &MockRelayConfig, filters: &[Filter]) -> Vec<NostrEvent> {
+    let mut out: Vec<NostrEvent> = Vec::new();
+    for filter in filters {
+        let mut matched: Vec<&NostrEvent> =
+            config.events.iter().filter(|e| filter.matches(e)).collect();
+        matched.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+        let mut cap = filter.limit.map(|l| l as usize).unwrap_or(usize::MAX);
+        if let Some(max) = config.max_limit {
+            cap = cap.min(max);
+        }
+        for event in matched.into_iter().take(cap) {
+            if !out.iter().any(|e| e.id == event.id) {
+                out.push(event.clone());
+            }
+        }
+    }
+    out
+}
+
+/// Build a test event
+pub fn event(id: &str, pubkey: &str, created_at: u64, kind: u16, content: &str) -> NostrEvent {
+    NostrEvent {
+        id: id.to_string(),
+        pubkey: pubkey.to_string(),
+        created_at,
+        kind,
+        tags: Vec::new(),
+        content: content.to_string(),
+        sig: String::new(),
+    }
+}
@@ -0,0 +1,172 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{event, MockRelay, MockRelayConfig, ReqBehavior};
+use nostr_analytics::*;
+use std::time::Duration;
+
+const DAY: u64 = 1704067200; // 2024-01-01
+
+/// `count` events spread evenly over one day, one pubkey per event
+fn busy_day(count: usize) -> Vec<NostrEvent> {
+    (0..count)
+        .map(|i| {
+            let created_at = DAY + (i as u64 * 86400 / count as u64);
+            event(&format!("e{}", i), &format!("pk{}", i), created_at, 1, "gm")
+        })
+        .collect()
+}
+
+fn connect(relay: &MockRelay) -> RelayClient {
+    RelayClient::connect_with_options(
+        &relay.url,
+        RelayClientOptions {
+            connect_timeout: Duration::from_secs(5),
+            subscription_timeout: Duration::from_millis(500),
+        },
+    )
+    .unwrap()
+}
+
+fn planner(strategy: PaginationStrategy) -> FetchPlanner {
+    FetchPlanner {
+        limit: 10,
+        strategy,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_plan_day_windows() {
+    let windows = FetchPlanner::new().plan(DAY, DAY + 2 * 86400 + 100);
+    assert_eq!(
+        windows,
+        vec![
+            (DAY, DAY + 86399),
+            (DAY + 86400, DAY + 2 * 86400 - 1),
+            (DAY + 2 * 86400, DAY + 2 * 86400 + 100),
+        ]
+    );
+}
+
+#[test]
+fn test_split_recovers_truncated_window() {
+    let relay = MockRelay::start(MockRelayConfig {
+        events: busy_day(95),
+        max_limit: Some(10),
+        ..Default::default()
+    });
+    let mut client = connect(&relay);
+
+    let output = planner(PaginationStrategy::Split)
+        .fetch(&mut client, DAY, DAY + 86399)
+        .unwrap();
+
+    assert_eq!(output.events.len(), 95);
+    assert_eq!(output.coverage.windows.len(), 1);
+    assert!(output.coverage.windows[0].complete);
+    assert!(output.coverage.requests() > 1);
+    assert_eq!(output.coverage.coverage_ratio(), 1.0);
+    assert_eq!(output.coverage.source, relay.url);
+}
+
+#[test]
+fn test_walk_back_recovers_truncated_window() {
+    let relay = MockRelay::start(MockRelayConfig {
+        events: busy_day(95),
+        max_limit: Some(10),
+        ..Default::default()
+    });
+    let mut client = connect(&relay);
+
+    let output = planner(PaginationStrategy::WalkBack)
+        .fetch(&mut client, DAY, DAY + 86399)
+        .unwrap();
+
+    assert_eq!(output.events.len(), 95);
+    assert!(output.coverage.windows[0].complete);
+
+    // Each follow-up REQ moves `until` back to the oldest event seen
+    let requests = relay.requests.lock().unwrap();
+    let untils: Vec<_> = requests.iter().map(|(_, f)| f[0].until.unwrap()).collect();
+    assert!(untils.windows(2).all(|w| w[1] < w[0]));
+}
+
+#[test]
+fn test_request_budget_marks_window_incomplete() {
+    let relay = MockRelay::start(MockRelayConfig {
+        events: busy_day(95),
+        max_limit: Some(10),
+        ..Default::default()
+    });
+    let mut client = connect(&relay);
+
+    let output = FetchPlanner {
+        max_requests_per_window: 2,
+        ..planner(PaginationStrategy::WalkBack)
+    }
+    .fetch(&mut client, DAY, DAY + 86399)
+    .unwrap();
+
+    assert_eq!(output.events.len(), 19);
+    assert!(!output.coverage.windows[0].complete);
+    assert_eq!(output.coverage.coverage_ratio(), 0.0);
+}
+
+#[test]
+fn test_closed_subscription_is_incomplete() {
+    let relay = MockRelay::start(MockRelayConfig {
+        behavior: ReqBehavior::Close("error: shutting down".to_string()),
+        ..Default::default()
+    });
+    let mut client = connect(&relay);
+
+    let output = planner(PaginationStrategy::Split)
+        .fetch(&mut client, DAY, DAY + 2 * 86400 - 1)
+        .unwrap();
+
+    assert_eq!(output.coverage.windows.len(), 2);
+    assert_eq!(output.coverage.incomplete_windows(), 2);
+}
+
+#[test]
+fn test_language_index_result_coverage_ratio() {
+    let mut result = LanguageIndexResult {
+        users: 0,
+        by_lang: Default::default(),
+        events_processed: 0,
+        events_with_language: 0,
+        events_too_short: 0,
+        events_labeled: 0,
+        self_labels: 0,
+        events_detected: 0,
+        label_conflicts: 0,
+        profiles_used: 0,
+        detector: default_detector_id(),
+        coverage: Vec::new(),
+        gaps: Vec::new(),
+    };
+    assert_eq!(result.coverage_ratio(), 1.0);
+
+    result.coverage.push(FetchCoverage {
+        source: "wss://a".to_string(),
+        windows: vec![
+            WindowStats {
+                since: 0,
+                until: 99,
+                events: 5,
+                requests: 1,
+                complete: true,
+            },
+            WindowStats {
+                since: 100,
+                until: 399,
+                events: 10,
+                requests: 3,
+                complete: false,
+            },
+        ],
+    });
+    assert_eq!(result.coverage_ratio(), 0.25);
+}
@@ -90,3 +90,2043 @@ fn test_language_index_get_users() {
     assert_eq!(ja_users.len(), 1);
     assert!(ja_users.contains("pubkey2"));
 }
+
+#[test]
+fn test_analytics_event_tags() {
+    let event = AnalyticsEvent {
+        metric: metric_name(30),
+        language: "ja".to_string(),
+        relays: vec!["wss://relay.damus.io".to_string()],
+        start: 1704067200,
+        end: 1711929600,
+        granularity: Granularity::Day,
+        window_days: 30,
+        zone: Zone::Utc,
+        detector: default_detector_id(),
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+        counts: vec![MetricDataPoint {
+            epoch_day: 19723,
+            count: 450,
+            error: None,
+        }],
+        eligible_user_count: 15000,
+        ratios: Vec::new(),
+    };
+
+    assert_eq!(event.d_tag(), "mau-ja-day-30-1704067200-1711929600-v1");
+
+    let tags = event.tags();
+    assert_eq!(tags[0], vec!["d", "mau-ja-day-30-1704067200-1711929600-v1"]);
+    assert!(tags.contains(&vec!["wdays".to_string(), "30".to_string()]));
+    assert!(tags.contains(&vec!["r".to_string(), "wss://relay.damus.io".to_string()]));
+
+    let content: serde_json::Value =
+        serde_json::from_str(&event.content(1704110400).unwrap()).unwrap();
+    assert_eq!(content["counts"], serde_json::json!([[19723, 450]]));
+    assert_eq!(content["timeframe"]["windowDays"], 30);
+    assert_eq!(content["eligibleUserCount"], 15000);
+
+    let signed = event
+        .sign("0000000000000000000000000000000000000000000000000000000000000001")
+        .unwrap();
+    assert_eq!(signed.kind, ANALYTICS_EVENT_KIND);
+    assert_eq!(signed.tags, tags);
+}
+
+const DAY0: u64 = 1704067200; // 2024-01-01 (epoch day 19723)
+
+fn note(pubkey: &str, created_at: u64, content: &str) -> NostrEvent {
+    NostrEvent {
+        id: format!("{}-{}", pubkey, created_at),
+        pubkey: pubkey.to_string(),
+        created_at,
+        kind: 1,
+        tags: Vec::new(),
+        content: content.to_string(),
+        sig: String::new(),
+    }
+}
+
+fn sample_index() -> LanguageIndex {
+    let events = vec![
+        note(
+            "alice",
+            DAY0 + 10,
+            "こんにちは、これは日本語のテストメッセージです。",
+        ),
+        note(
+            "bob",
+            DAY0 + 20,
+            "Hello, this is a test message in English.",
+        ),
+        note(
+            "carol",
+            DAY0 + 30,
+            "今日はとても良い天気ですね。散歩に行きましょう。",
+        ),
+    ];
+    build_language_index(&events, &IndexConfig::default())
+}
+
+#[test]
+fn test_build_language_index() {
+    let index = sample_index();
+
+    assert_eq!(index.events_processed, 3);
+    assert_eq!(index.events_with_language, 3);
+    assert_eq!(index.since, DAY0 + 10);
+    assert_eq!(index.until, DAY0 + 30);
+    assert!(index.user_languages["alice"].has_language("ja"));
+    assert!(index.user_languages["bob"].has_language("en"));
+
+    let result = index.result();
+    assert_eq!(result.users, 3);
+    assert_eq!(result.by_lang["ja"], 2);
+    assert_eq!(result.by_lang["en"], 1);
+}
+
+#[test]
+fn test_build_language_index_config() {
+    let events = vec![
+        note(
+            "alice",
+            DAY0,
+            "こんにちは、これは日本語のテストメッセージです。",
+        ),
+        note("bob", DAY0 + 1, "Hello, this is a test message in English."),
+        note("carol", DAY0 + 2, ""),
+    ];
+
+    let index = build_language_index(
+        &events,
+        &IndexConfig {
+            max_events: Some(1),
+            ..Default::default()
+        },
+    );
+    assert_eq!(index.events_processed, 1);
+    assert_eq!(index.user_languages.len(), 1);
+
+    // Nothing passes an impossible threshold
+    let index = build_language_index(
+        &events,
+        &IndexConfig {
+            conf_thresh: 1.1,
+            ..Default::default()
+        },
+    );
+    assert_eq!(index.events_processed, 3);
+    assert_eq!(index.events_with_language, 0);
+    assert!(index.user_languages.is_empty());
+}
+
+#[test]
+fn test_compute_active_users_sliding_window() {
+    let index = sample_index();
+    let activity = vec![
+        note("alice", DAY0 + 100, "x"),
+        note("alice", DAY0 + 200, "x"),
+        note("bob", DAY0 + 86400, "x"),
+        note("carol", DAY0 + 2 * 86400, "x"),
+        note("stranger", DAY0 + 2 * 86400, "x"),
+    ];
+    let mut options = MetricsOptions {
+        since: DAY0,
+        until: DAY0 + 3 * 86400,
+        languages: vec!["ja".to_string(), "en".to_string()],
+        granularity: Granularity::Day,
+        window_days: 1,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+
+    let dau: Vec<u32> = compute_active_users(&activity, &index, &options)
+        .iter()
+        .map(|p| p.count)
+        .collect();
+    assert_eq!(dau, vec![1, 1, 1, 0]);
+
+    options.window_days = 2;
+    let points = compute_active_users(&activity, &index, &options);
+    assert_eq!(points[0].epoch_day, 19723);
+    let counts: Vec<u32> = points.iter().map(|p| p.count).collect();
+    assert_eq!(counts, vec![1, 2, 2, 1]);
+}
+
+#[test]
+fn test_compute_active_users_by_language() {
+    let index = sample_index();
+    let activity = vec![
+        note("alice", DAY0, "x"),
+        note("bob", DAY0, "x"),
+        note("carol", DAY0 + 86400, "x"),
+    ];
+    let options = MetricsOptions {
+        since: DAY0,
+        until: DAY0 + 86400,
+        languages: vec!["ja".to_string(), "en".to_string(), "fr".to_string()],
+        granularity: Granularity::Day,
+        window_days: 7,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+
+    let by_lang = compute_active_users_by_language(&activity, &index, &options);
+    let counts = |lang: &str| -> Vec<u32> { by_lang[lang].iter().map(|p| p.count).collect() };
+
+    assert_eq!(counts("ja"), vec![1, 2]);
+    assert_eq!(counts("en"), vec![1, 1]);
+    assert_eq!(counts("fr"), vec![0, 0]);
+}
+
+#[test]
+fn test_compute_metrics_group_by_kind_and_client() {
+    let index = sample_index();
+    let mut reaction = note("bob", DAY0 + 50, "+");
+    reaction.kind = 7;
+    let mut tagged = note("alice", DAY0 + 60, "x");
+    tagged.tags = vec![vec!["client".to_string(), "lumilumi".to_string()]];
+    let activity = vec![
+        note("alice", DAY0, "x"),
+        reaction,
+        tagged,
+        note("stranger", DAY0, "x"),
+    ];
+    let options = MetricsOptions {
+        since: DAY0,
+        until: DAY0,
+        languages: vec!["ja".to_string(), "en".to_string()],
+        granularity: Granularity::Day,
+        window_days: 1,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+
+    let by_kind = compute_metrics(&activity, &index, &options, GroupBy::Kind);
+    assert_eq!(by_kind.len(), 2);
+    assert_eq!(by_kind["1"][0].count, 1);
+    assert_eq!(by_kind["7"][0].count, 1);
+
+    let by_client = compute_metrics(&activity, &index, &options, GroupBy::Client);
+    assert_eq!(by_client["lumilumi"][0].count, 1);
+    assert_eq!(by_client[UNKNOWN_CLIENT][0].count, 2);
+
+    let all = compute_metrics(&activity, &index, &options, GroupBy::All);
+    assert_eq!(all[ALL_GROUP][0].count, 2);
+    assert_eq!("kind".parse::<GroupBy>().unwrap(), GroupBy::Kind);
+    assert!("weekday".parse::<GroupBy>().is_err());
+}
+
+#[test]
+fn test_epoch_day_to_week_start() {
+    // 2024-01-01 was a Monday
+    assert_eq!(epoch_day_to_week_start(19723), 19723);
+    assert_eq!(epoch_day_to_week_start(19729), 19723);
+    assert_eq!(epoch_day_to_week_start(19730), 19730);
+    // 1970-01-01 (Thursday) belongs to the week starting 1969-12-29, which
+    // is clamped to the epoch
+    for day in 0..=3 {
+        assert_eq!(epoch_day_to_week_start(day), 0);
+    }
+    assert_eq!(epoch_day_to_week_start(4), 4);
+}
+
+#[test]
+fn test_compute_retention_daily_cohorts() {
+    let index = sample_index();
+    let day = |n: u64| DAY0 + n * 86400 + 100;
+    let activity = vec![
+        // alice: first seen day 0, back on day 1 and day 7
+        note("alice", day(0), "x"),
+        note("alice", day(1), "x"),
+        note("alice", day(7), "x"),
+        // carol: first seen day 0, back on day 7 only
+        note("carol", day(0), "x"),
+        note("carol", day(7), "x"),
+        // bob (en): first seen day 1, never back
+        note("bob", day(1), "x"),
+    ];
+    let options = RetentionOptions {
+        since: DAY0,
+        until: day(7),
+        languages: vec!["ja".to_string(), "en".to_string()],
+        cohort_period: CohortPeriod::Day,
+        offsets: Vec::new(),
+        zone: Zone::Utc,
+    };
+
+    let results = compute_retention(&activity, &index, &options);
+
+    let ja = &results["ja"];
+    assert_eq!(ja.offsets, vec![1, 7, 30]);
+    assert_eq!(ja.cohorts.len(), 1);
+    assert_eq!(ja.cohorts[0].cohort_start, 19723);
+    assert_eq!(ja.cohorts[0].size, 2);
+    // D30 is beyond `until`
+    assert_eq!(ja.cohorts[0].retained, vec![Some(1), Some(2), None]);
+    assert_eq!(ja.cohorts[0].rates()[0], Some(0.5));
+
+    let en = &results["en"];
+    assert_eq!(en.cohorts[0].cohort_start, 19724);
+    assert_eq!(en.cohorts[0].retained, vec![Some(0), None, None]);
+}
+
+#[test]
+fn test_compute_retention_weekly_cohorts() {
+    let index = sample_index();
+    let day = |n: u64| DAY0 + n * 86400;
+    let activity = vec![
+        note("alice", day(0), "x"),
+        note("alice", day(1), "x"),
+        note("carol", day(3), "x"),
+        note("carol", day(4), "x"),
+    ];
+    let options = RetentionOptions {
+        since: DAY0,
+        until: day(13),
+        languages: vec!["ja".to_string()],
+        cohort_period: CohortPeriod::Week,
+        offsets: vec![1, 7],
+        zone: Zone::Utc,
+    };
+
+    let ja = &compute_retention(&activity, &index, &options)["ja"];
+    assert_eq!(ja.cohorts.len(), 1);
+    assert_eq!(ja.cohorts[0].size, 2);
+    assert_eq!(ja.cohorts[0].retained, vec![Some(2), Some(0)]);
+}
+
+#[test]
+fn test_compute_growth_accounting() {
+    let index = sample_index();
+    let day = |n: u64| DAY0 + n * 86400;
+    let activity = vec![
+        // alice: days 0, 1, then back on day 4
+        note("alice", day(0), "x"),
+        note("alice", day(1), "x"),
+        note("alice", day(4), "x"),
+        // carol: day 1 only
+        note("carol", day(1), "x"),
+    ];
+    let options = MetricsOptions {
+        since: day(0),
+        until: day(4),
+        languages: vec!["ja".to_string()],
+        granularity: Granularity::Day,
+        window_days: 1,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+
+    let ja = &compute_growth_accounting(&activity, &index, &options, 1)["ja"];
+    let field = |f: fn(&GrowthDataPoint) -> u32| ja.iter().map(f).collect::<Vec<_>>();
+    assert_eq!(field(|p| p.active), vec![1, 2, 0, 0, 1]);
+    assert_eq!(field(|p| p.new), vec![1, 1, 0, 0, 0]);
+    assert_eq!(field(|p| p.retained), vec![0, 1, 0, 0, 0]);
+    assert_eq!(field(|p| p.resurrected), vec![0, 0, 0, 0, 1]);
+    assert_eq!(field(|p| p.churned), vec![0, 0, 2, 0, 0]);
+    assert_eq!(ja[2].quick_ratio, Some(0.0));
+    assert_eq!(ja[0].quick_ratio, None);
+
+    // With a 3-day inactivity threshold alice's return on day 4 is retained
+    let ja = &compute_growth_accounting(&activity, &index, &options, 3)["ja"];
+    assert_eq!(ja[4].retained, 1);
+    assert_eq!(ja[4].resurrected, 0);
+    assert_eq!(ja[4].churned, 1);
+}
+
+#[test]
+fn test_compute_stickiness() {
+    let index = sample_index();
+    let day = |n: u64| DAY0 + n * 86400;
+    let activity = vec![
+        note("alice", day(0), "x"),
+        note("alice", day(1), "x"),
+        note("carol", day(0), "x"),
+        note("bob", day(1), "x"),
+    ];
+    let options = MetricsOptions {
+        since: day(0),
+        until: day(2),
+        languages: vec!["ja".to_string()],
+        granularity: Granularity::Day,
+        window_days: 1,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+
+    let result = compute_stickiness(
+        &activity,
+        &index,
+        &options,
+        GroupBy::Language,
+        &[30, 1, 7, 1],
+    );
+
+    let windows: Vec<u16> = result
+        .windows
+        .iter()
+        .map(|w| w.options.window_days)
+        .collect();
+    assert_eq!(windows, vec![1, 7, 30]);
+    assert_eq!(result.ratios.len(), 3);
+
+    let dau_mau = &result.ratios[1];
+    assert_eq!(dau_mau.numerator.window_days, 1);
+    assert_eq!(dau_mau.denominator.window_days, 30);
+    let ratios: Vec<Option<f32>> = dau_mau.series["ja"].iter().map(|p| p.ratio).collect();
+    assert_eq!(ratios, vec![Some(1.0), Some(0.5), Some(0.0)]);
+
+    let event = AnalyticsEvent::from_ratio(dau_mau, "ja", Vec::new(), 2, "whatlang@0.16").unwrap();
+    assert_eq!(
+        event.d_tag(),
+        format!("dau_mau-ja-day-30-{}-{}-v1", day(0), day(2))
+    );
+    let content: serde_json::Value = serde_json::from_str(&event.content(day(3)).unwrap()).unwrap();
+    assert_eq!(
+        content["ratios"],
+        serde_json::json!([[19723, 1.0], [19724, 0.5], [19725, 0.0]])
+    );
+    assert!(AnalyticsEvent::from_ratio(dau_mau, "en", Vec::new(), 0, "whatlang@0.16").is_none());
+}
+
+#[test]
+fn test_calendar_helpers() {
+    let date = |y, m, d| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    assert_eq!(epoch_day_to_date(0), date(1970, 1, 1));
+    assert_eq!(epoch_day_to_date(19723), date(2024, 1, 1));
+    assert_eq!(epoch_day_to_date(19782), date(2024, 2, 29));
+    assert_eq!(date_to_epoch_day(date(2024, 3, 1)), 19783);
+    assert_eq!(date_to_epoch_day(date(1969, 12, 31)), 0);
+    assert_eq!(epoch_day_to_month_start(19782), 19754);
+
+    assert_eq!(Granularity::Month.next_bucket(19754), 19783);
+    assert_eq!(Granularity::Month.next_bucket(20058), 20089); // 2024-12 -> 2025-01
+    assert_eq!(Granularity::Week.bucket_start(19725), 19723);
+    assert_eq!("month".parse::<Granularity>().unwrap(), Granularity::Month);
+    assert!("hour".parse::<Granularity>().is_err());
+}
+
+#[test]
+fn test_compute_metrics_calendar_buckets() {
+    let index = sample_index();
+    let day = |n: u64| DAY0 + n * 86400;
+    let activity = vec![
+        // week of 2024-01-01
+        note("alice", day(0), "x"),
+        note("alice", day(3), "x"),
+        note("carol", day(6), "x"),
+        // week of 2024-01-08
+        note("alice", day(8), "x"),
+        // 2024-02-01
+        note("carol", day(31), "x"),
+    ];
+    let mut options = MetricsOptions {
+        since: day(0),
+        until: day(31),
+        languages: vec!["ja".to_string()],
+        granularity: Granularity::Week,
+        window_days: 1,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+
+    let weekly = &compute_active_users_by_language(&activity, &index, &options)["ja"];
+    let counts: Vec<(u32, u32)> = weekly.iter().map(|p| (p.epoch_day, p.count)).collect();
+    assert_eq!(
+        counts,
+        vec![(19723, 2), (19730, 1), (19737, 0), (19744, 0), (19751, 1)]
+    );
+
+    options.granularity = Granularity::Month;
+    let monthly = &compute_active_users_by_language(&activity, &index, &options)["ja"];
+    let counts: Vec<(u32, u32)> = monthly.iter().map(|p| (p.epoch_day, p.count)).collect();
+    assert_eq!(counts, vec![(19723, 2), (19754, 1)]);
+
+    let event = AnalyticsEvent {
+        metric: granular_metric_name(Granularity::Month, 1),
+        language: "ja".to_string(),
+        relays: Vec::new(),
+        start: day(0),
+        end: day(31),
+        granularity: Granularity::Month,
+        window_days: 1,
+        zone: Zone::Utc,
+        detector: default_detector_id(),
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+        counts: monthly.clone(),
+        eligible_user_count: 2,
+        ratios: Vec::new(),
+    };
+    assert_eq!(
+        event.d_tag(),
+        format!("mau-ja-month-1-{}-{}-v1", day(0), day(31))
+    );
+    assert!(event
+        .tags()
+        .contains(&vec!["gran".to_string(), "month".to_string()]));
+}
+
+#[test]
+fn test_zone_parsing_and_day_boundaries() {
+    let jst: Zone = "Asia/Tokyo".parse().unwrap();
+    let fixed: Zone = "+09:00".parse().unwrap();
+    assert_eq!(fixed, Zone::Fixed(9 * 3600));
+    assert_eq!(fixed.to_string(), "+09:00");
+    assert_eq!(jst.to_string(), "Asia/Tokyo");
+    assert_eq!("-05:30".parse::<Zone>().unwrap().to_string(), "-05:30");
+    assert_eq!("utc".parse::<Zone>().unwrap(), Zone::Utc);
+    assert_eq!("+00:00".parse::<Zone>().unwrap(), Zone::Utc);
+    assert!("Mars/Olympus".parse::<Zone>().is_err());
+    assert!("+25:00".parse::<Zone>().is_err());
+
+    // 2024-01-01 15:00 UTC is 2024-01-02 00:00 JST
+    let ts = DAY0 + 15 * 3600;
+    assert_eq!(Zone::Utc.epoch_day(ts), 19723);
+    assert_eq!(jst.epoch_day(ts), 19724);
+    assert_eq!(fixed.epoch_day(ts - 1), 19723);
+
+    // Daylight saving: New York is UTC-4 in July
+    let ny: Zone = "America/New_York".parse().unwrap();
+    assert_eq!(ny.offset_secs(1_720_000_000), -4 * 3600);
+
+    let json = serde_json::to_string(&jst).unwrap();
+    assert_eq!(json, "\"Asia/Tokyo\"");
+    assert_eq!(serde_json::from_str::<Zone>(&json).unwrap(), jst);
+}
+
+#[test]
+fn test_compute_metrics_in_time_zone() {
+    let index = sample_index();
+    // Both notes fall on 2024-01-01 UTC but on different JST days
+    let activity = vec![
+        note("alice", DAY0 + 3600, "x"),
+        note("carol", DAY0 + 16 * 3600, "x"),
+    ];
+    let mut options = MetricsOptions {
+        since: DAY0,
+        until: DAY0 + 86400 + 3600,
+        languages: vec!["ja".to_string()],
+        granularity: Granularity::Day,
+        window_days: 1,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+    let utc = &compute_active_users_by_language(&activity, &index, &options)["ja"];
+    let counts: Vec<u32> = utc.iter().map(|p| p.count).collect();
+    assert_eq!(counts, vec![2, 0]);
+
+    options.zone = "Asia/Tokyo".parse().unwrap();
+    let jst = &compute_active_users_by_language(&activity, &index, &options)["ja"];
+    let counts: Vec<(u32, u32)> = jst.iter().map(|p| (p.epoch_day, p.count)).collect();
+    assert_eq!(counts, vec![(19723, 1), (19724, 1)]);
+
+    assert_eq!(
+        algo_tag(
+            "whatlang@0.16",
+            options.zone,
+            EligibilityScope::Lifetime,
+            CountingMode::Exact
+        ),
+        "lang=whatlang@0.16;act=all-kinds;elig=lifetime;tz=Asia/Tokyo"
+    );
+    assert_eq!(
+        algo_tag(
+            &default_detector_id(),
+            Zone::Utc,
+            EligibilityScope::Lifetime,
+            CountingMode::Exact
+        ),
+        ALGO_TAG
+    );
+}
+
+#[test]
+fn test_compute_activity_heatmap() {
+    let index = sample_index();
+    // DAY0 is Monday 2024-01-01 00:00 UTC
+    let activity = vec![
+        note("alice", DAY0 + 9 * 3600, "x"),
+        note("alice", DAY0 + 9 * 3600 + 60, "x"),
+        note("carol", DAY0 + 9 * 3600 + 120, "x"),
+        // following Monday, same hour: alice is not counted twice
+        note("alice", DAY0 + 7 * 86400 + 9 * 3600, "x"),
+        // Sunday 23:00 UTC
+        note("carol", DAY0 + 6 * 86400 + 23 * 3600, "x"),
+        note("bob", DAY0 + 9 * 3600, "x"),
+    ];
+    let mut options = HeatmapOptions {
+        since: DAY0,
+        until: DAY0 + 14 * 86400,
+        languages: vec!["ja".to_string(), "en".to_string()],
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+    };
+
+    let heatmaps = compute_activity_heatmap(&activity, &index, &options);
+    assert_eq!(heatmaps.len(), 2);
+    let ja = &heatmaps[0];
+    assert_eq!(ja.language, "ja");
+    assert_eq!(ja.users.len(), 7);
+    assert_eq!(ja.users[0].len(), 24);
+    assert_eq!(ja.users_at(0, 9), 2);
+    assert_eq!(ja.events_at(0, 9), 4);
+    assert_eq!(ja.users_at(6, 23), 1);
+    assert_eq!(heatmaps[1].events_at(0, 9), 1);
+
+    // In JST, Sunday 23:00 UTC becomes Monday 08:00
+    options.zone = "Asia/Tokyo".parse().unwrap();
+    let ja = &compute_activity_heatmap(&activity, &index, &options)[0];
+    assert_eq!(ja.zone, "Asia/Tokyo");
+    assert_eq!(ja.users_at(0, 18), 2);
+    assert_eq!(ja.users_at(0, 8), 1);
+    assert_eq!(ja.users_at(6, 23), 0);
+
+    // Everyone posted on DAY0 only, so a one-day lookback drops later weeks
+    options.zone = Zone::Utc;
+    options.eligibility = EligibilityScope::Period { lookback_days: 1 };
+    let heatmaps = compute_activity_heatmap(&activity, &index, &options);
+    let ja = &heatmaps[0];
+    assert_eq!(ja.users_at(0, 9), 2);
+    assert_eq!(ja.events_at(0, 9), 3);
+    assert_eq!(ja.users_at(6, 23), 0);
+    assert_eq!(heatmaps[1].events_at(0, 9), 1);
+}
+
+#[test]
+fn test_build_language_index_skips_link_only_notes() {
+    let events = vec![
+        note("alice", DAY0, "https://example.com/photo.jpg #photography"),
+        note(
+            "alice",
+            DAY0 + 1,
+            "nostr:npub1sg6plzptd64u62a878hep2kev88swjh3tw00gjsfl8f237lmu63q0uf63m 今日はとても良い天気ですね。散歩に行きましょう。",
+        ),
+    ];
+
+    let index = build_language_index(&events, &IndexConfig::default());
+    assert_eq!(index.events_processed, 2);
+    assert_eq!(index.events_too_short, 1);
+    assert_eq!(index.events_with_language, 1);
+    assert!(index.user_languages["alice"].has_language("ja"));
+    assert_eq!(index.result().events_too_short, 1);
+}
+
+#[test]
+fn test_build_language_index_with_custom_detector() {
+    let events = vec![
+        note("alice", DAY0, "whatever the text says"),
+        note("bob", DAY0 + 1, "https://example.com"),
+    ];
+    let detector = FixedLanguageDetector::new("ja", 0.8);
+
+    let index = build_language_index_with_detector(&events, &IndexConfig::default(), &detector);
+    assert_eq!(index.detector, "fixed@ja");
+    assert!(index.user_languages["alice"].has_language("ja"));
+    // Preprocessing still applies before the detector runs
+    assert!(!index.user_languages.contains_key("bob"));
+
+    let result = index.result();
+    assert_eq!(result.detector, "fixed@ja");
+    assert_eq!(
+        algo_tag(
+            &result.detector,
+            Zone::Utc,
+            EligibilityScope::Lifetime,
+            CountingMode::Exact
+        ),
+        "lang=fixed@ja;act=all-kinds;elig=lifetime"
+    );
+    assert_eq!(
+        build_language_index(&events, &IndexConfig::default()).detector,
+        "whatlang@0.16+cjk-script@1"
+    );
+}
+
+#[test]
+fn test_detect_content_language_exposes_script_decision() {
+    let result = detect_content_language(
+        &default_detector(),
+        "東京都の新規感染者数を発表した https://example.com",
+        DEFAULT_MIN_CONTENT_CHARS,
+    )
+    .unwrap();
+    let detection = result.detection.as_ref().unwrap();
+    assert_eq!(detection.language, "ja");
+    let script = detection.script.as_ref().unwrap();
+    assert!(script.stats.kana() > 0);
+    assert!(matches!(
+        script.action,
+        ScriptAction::Kept | ScriptAction::Overridden
+    ));
+
+    let json = serde_json::to_value(&result).unwrap();
+    assert!(
+        json["detection"]["script"]["stats"]["han"]
+            .as_u64()
+            .unwrap()
+            > 10
+    );
+}
+
+#[test]
+fn test_user_languages_evidence() {
+    let mut user_langs = UserLanguages::new();
+    user_langs.add_post("ja".to_string(), 0.9, DAY0 + 10, 5);
+    user_langs.add_post("ja".to_string(), 0.7, DAY0, 5);
+    user_langs.add_post("en".to_string(), 1.0, DAY0 + 5, 5);
+
+    let ja = &user_langs.evidence["ja"];
+    assert_eq!(ja.posts, 2);
+    assert!((ja.confidence_sum - 1.6).abs() < 1e-6);
+    assert_eq!(ja.first_seen, Some(DAY0));
+    assert_eq!(ja.last_seen, Some(DAY0 + 10));
+    assert!((user_langs.languages["ja"] - 0.8).abs() < 1e-6);
+    assert!((user_langs.share("en") - 1.0 / 3.0).abs() < 1e-6);
+    assert_eq!(user_langs.total_posts(), 3);
+
+    // NaN confidences must not panic when pruning
+    user_langs.add_language("xx".to_string(), f32::NAN, 1);
+    assert_eq!(user_langs.languages.len(), 1);
+    assert!(user_langs.has_language("ja"));
+}
+
+#[test]
+fn test_eligibility_rule_drops_stray_languages() {
+    let rule = EligibilityRule::default();
+    let mut user_langs = UserLanguages::new();
+    for i in 0..9 {
+        user_langs.add_post("en".to_string(), 0.9, DAY0 + i, 5);
+    }
+    // One confident stray post: 10% share, fewer than 3 posts
+    user_langs.add_post("de".to_string(), 1.0, DAY0 + 20, 5);
+    assert!(user_langs.is_eligible("en", &rule));
+    assert!(!user_langs.is_eligible("de", &rule));
+
+    // Three posts are enough regardless of share
+    for i in 0..2 {
+        user_langs.add_post("de".to_string(), 0.8, DAY0 + 30 + i, 5);
+    }
+    assert!(user_langs.is_eligible("de", &rule));
+
+    user_langs.add_post("fr".to_string(), 0.9, DAY0 + 40, 5);
+    user_langs.apply_eligibility(&rule);
+    assert!(user_langs.has_language("en"));
+    assert!(user_langs.has_language("de"));
+    assert!(!user_langs.has_language("fr"));
+    // Evidence is kept for later re-evaluation
+    assert_eq!(user_langs.evidence["fr"].posts, 1);
+
+    // Index options override either half of the rule
+    let options: LanguageIndexOptions =
+        serde_json::from_value(serde_json::json!({"since": 0, "until": 0, "min_posts": 1}))
+            .unwrap();
+    let config = IndexConfig::from(&options);
+    assert_eq!(
+        config.eligibility,
+        EligibilityRule {
+            min_posts: 1,
+            min_share: 0.2
+        }
+    );
+}
+
+#[test]
+fn test_language_index_serializes_for_caching() {
+    let events = vec![
+        note(
+            "alice",
+            DAY0,
+            "今日はとても良い天気ですね。散歩に行きましょう。",
+        ),
+        note(
+            "alice",
+            DAY0 + 60,
+            "明日も晴れるといいですね。楽しみにしています。",
+        ),
+    ];
+    let index = build_language_index(&events, &IndexConfig::default());
+
+    let json = serde_json::to_string(&index).unwrap();
+    let cached: LanguageIndex = serde_json::from_str(&json).unwrap();
+    assert_eq!(cached.user_languages["alice"].evidence["ja"].posts, 2);
+    assert_eq!(
+        cached.user_languages["alice"].evidence["ja"].last_seen,
+        Some(DAY0 + 60)
+    );
+    assert_eq!(cached.detector, index.detector);
+}
+
+#[test]
+fn test_eligibility_scope_parse() {
+    assert_eq!(
+        "lifetime".parse::<EligibilityScope>().unwrap(),
+        EligibilityScope::Lifetime
+    );
+    assert_eq!(
+        "period".parse::<EligibilityScope>().unwrap(),
+        EligibilityScope::Period {
+            lookback_days: DEFAULT_LOOKBACK_DAYS
+        }
+    );
+    let scope: EligibilityScope = "period-30d".parse().unwrap();
+    assert_eq!(scope, EligibilityScope::Period { lookback_days: 30 });
+    assert_eq!(scope.to_string(), "period-30d");
+    assert!("period-0d".parse::<EligibilityScope>().is_err());
+    assert!("forever".parse::<EligibilityScope>().is_err());
+}
+
+#[test]
+fn test_period_eligibility() {
+    const DAY: u64 = 86400;
+    // carol wrote Japanese in early January, then switched to English
+    let posts = vec![
+        note(
+            "carol",
+            DAY0,
+            "今日はとても良い天気ですね。散歩に行きましょう。",
+        ),
+        note(
+            "carol",
+            DAY0 + DAY,
+            "明日も晴れるといいですね。楽しみにしています。",
+        ),
+        note(
+            "carol",
+            DAY0 + 2 * DAY,
+            "週末は友達と一緒に映画を見に行く予定です。",
+        ),
+        note(
+            "carol",
+            DAY0 + 20 * DAY,
+            "I moved abroad and now I mostly write in English.",
+        ),
+        note(
+            "carol",
+            DAY0 + 21 * DAY,
+            "The weather here is cold but the people are friendly.",
+        ),
+        note(
+            "carol",
+            DAY0 + 22 * DAY,
+            "Looking forward to exploring the city this weekend.",
+        ),
+    ];
+    let index = build_language_index(&posts, &IndexConfig::default());
+    assert!(index.user_languages["carol"].has_language("ja"));
+
+    // Reactions on day 1 and day 25
+    let activity = vec![
+        NostrEvent {
+            kind: 7,
+            ..note("carol", DAY0 + DAY + 60, "+")
+        },
+        NostrEvent {
+            kind: 7,
+            ..note("carol", DAY0 + 25 * DAY, "+")
+        },
+    ];
+    let mut options = MetricsOptions {
+        since: DAY0,
+        until: DAY0 + 25 * DAY,
+        languages: vec!["ja".to_string(), "en".to_string()],
+        granularity: Granularity::Day,
+        window_days: 1,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+    let count_on = |results: &std::collections::HashMap<String, Vec<MetricDataPoint>>,
+                    lang: &str,
+                    day: u32| {
+        results[lang]
+            .iter()
+            .find(|p| p.epoch_day == 19723 + day)
+            .unwrap()
+            .count
+    };
+
+    let lifetime = compute_active_users_by_language(&activity, &index, &options);
+    assert_eq!(count_on(&lifetime, "ja", 25), 1);
+    assert_eq!(count_on(&lifetime, "en", 1), 1);
+
+    options.eligibility = EligibilityScope::Period { lookback_days: 14 };
+    let period = compute_active_users_by_language(&activity, &index, &options);
+    assert_eq!(count_on(&period, "ja", 1), 1);
+    assert_eq!(count_on(&period, "ja", 25), 0);
+    // No English posts yet on day 1
+    assert_eq!(count_on(&period, "en", 1), 0);
+    assert_eq!(count_on(&period, "en", 25), 1);
+
+    assert_eq!(
+        algo_tag(
+            &index.detector,
+            Zone::Utc,
+            options.eligibility,
+            options.counting
+        ),
+        "lang=whatlang@0.16+cjk-script@1;act=all-kinds;elig=period-14d"
+    );
+}
+
+#[test]
+fn test_period_eligibility_in_time_zone() {
+    const DAY: u64 = 86400;
+    const JST: i32 = 9 * 3600;
+    // 08:00 JST on 2024-01-11 is 23:00 UTC on 2024-01-10
+    let posted_at = DAY0 + 10 * DAY - 3600;
+    let japanese = "こんにちは、これは日本語のテストメッセージです。";
+    let index = build_language_index(
+        &[note("alice", posted_at, japanese)],
+        &IndexConfig {
+            eligibility: EligibilityRule {
+                min_posts: 1,
+                min_share: 0.0,
+            },
+            ..Default::default()
+        },
+    );
+
+    let options = MetricsOptions {
+        since: DAY0 + 9 * DAY - JST as u64,
+        until: DAY0 + 12 * DAY - JST as u64 - 1,
+        languages: vec!["ja".to_string()],
+        granularity: Granularity::Day,
+        window_days: 1,
+        zone: Zone::Fixed(JST),
+        eligibility: EligibilityScope::Period { lookback_days: 1 },
+        counting: CountingMode::Exact,
+    };
+    let events = [
+        note("alice", posted_at, japanese),
+        // 10:00 JST the same JST day, the next UTC day
+        note("alice", posted_at + 2 * 3600, "+"),
+    ];
+    let series = compute_active_users(&events, &index, &options);
+    let jst_day = timestamp_to_epoch_day(posted_at + JST as u64);
+    let counts: Vec<(EpochDay, u32)> = series.iter().map(|p| (p.epoch_day, p.count)).collect();
+    assert_eq!(
+        counts,
+        vec![(jst_day - 1, 0), (jst_day, 1), (jst_day + 1, 0)]
+    );
+}
+
+#[test]
+fn test_evaluate_detector_scores() {
+    let corpus = r#"{"content": "これは日本語の文章です", "expected_lang": "ja"}
+{"content": "今日はいい天気ですね", "expected_lang": "ja"}
+
+{"content": "This is an English sentence", "expected_lang": "en"}
+{"content": "ok", "expected_lang": "en"}
+"#;
+    let samples = parse_labeled_corpus(corpus).unwrap();
+    assert_eq!(samples.len(), 4);
+    assert!(parse_labeled_corpus("{\"content\": \"x\"}").is_err());
+
+    // Everything long enough is detected as ja with confidence 0.6
+    let detector = FixedLanguageDetector::new("ja", 0.6);
+    let evaluation = evaluate_detector(&detector, &samples, &[0.5, 0.7], 5).unwrap();
+    assert_eq!(evaluation.detector, "fixed@ja");
+    assert_eq!(evaluation.thresholds.len(), 2);
+
+    let low = &evaluation.thresholds[0];
+    assert_eq!(low.detected, 3);
+    assert_eq!(low.correct, 2);
+    assert_eq!(low.accuracy, 0.5);
+    let ja = &low.languages["ja"];
+    assert_eq!((ja.support, ja.predicted, ja.true_positives), (2, 3, 2));
+    assert!((ja.precision.unwrap() - 2.0 / 3.0).abs() < 1e-6);
+    assert_eq!(ja.recall, Some(1.0));
+    assert!((ja.f1.unwrap() - 0.8).abs() < 1e-6);
+    let en = &low.languages["en"];
+    assert_eq!(en.precision, None);
+    assert_eq!(en.recall, Some(0.0));
+    // en is never predicted: it scores 0 and still counts towards macro F1
+    assert_eq!(en.f1, Some(0.0));
+    assert!((low.macro_f1.unwrap() - 0.4).abs() < 1e-6);
+    assert_eq!(low.confusion["en"]["ja"], 1);
+    // "ok" is too short to detect
+    assert_eq!(low.confusion["en"][UNDETERMINED], 1);
+
+    let high = &evaluation.thresholds[1];
+    assert_eq!(high.detected, 0);
+    assert_eq!(high.confusion["ja"][UNDETERMINED], 2);
+    // Detecting nothing scores 0 rather than dropping out of the mean
+    assert_eq!(high.macro_f1, Some(0.0));
+
+    assert_eq!(evaluation.best_threshold().unwrap().conf_thresh, 0.5);
+}
+
+#[test]
+fn test_fixture_corpus() {
+    let samples = parse_labeled_corpus(FIXTURE_CORPUS).unwrap();
+    assert!(samples.len() >= 50);
+
+    let evaluation = evaluate_detector(
+        &default_detector(),
+        &samples,
+        &[0.0],
+        DEFAULT_MIN_CONTENT_CHARS,
+    )
+    .unwrap();
+    let scores = &evaluation.thresholds[0];
+    assert_eq!(scores.samples as usize, samples.len());
+    assert!(scores.languages["ja"].recall.unwrap() >= 0.75);
+    assert!(scores.languages["ko"].f1.unwrap() >= 0.9);
+}
+
+fn tagged(mut event: NostrEvent, tags: &[&[&str]]) -> NostrEvent {
+    event.tags = tags
+        .iter()
+        .map(|tag| tag.iter().map(|s| s.to_string()).collect())
+        .collect();
+    event
+}
+
+#[test]
+fn test_event_language_label() {
+    let labeled = tagged(
+        note("alice", DAY0, "ok"),
+        &[&["L", "ISO-639-1"], &["l", "JA", "ISO-639-1"]],
+    );
+    assert_eq!(event_language_label(&labeled).as_deref(), Some("ja"));
+
+    let lang_tag = tagged(note("alice", DAY0, "ok"), &[&["lang", "en-US"]]);
+    assert_eq!(event_language_label(&lang_tag).as_deref(), Some("en"));
+
+    // Other namespaces and conflicting labels are ignored
+    let other = tagged(note("alice", DAY0, "ok"), &[&["l", "ja", "ugc"]]);
+    assert_eq!(event_language_label(&other), None);
+    let conflicting = tagged(
+        note("alice", DAY0, "ok"),
+        &[&["l", "ja", "ISO-639-1"], &["l", "en", "ISO-639-1"]],
+    );
+    assert_eq!(event_language_label(&conflicting), None);
+
+    assert_eq!("blend".parse::<LabelMode>().unwrap(), LabelMode::Blend);
+    assert!("always".parse::<LabelMode>().is_err());
+}
+
+#[test]
+fn test_language_labels_take_priority() {
+    let english = "Hello, this is a test message in English.";
+    let events = vec![
+        // Mislabeled on purpose so label and detection disagree
+        tagged(note("alice", DAY0, english), &[&["l", "ja", "ISO-639-1"]]),
+        note("alice", DAY0 + 10, english),
+        // Too short to detect, labeled by its author in a kind 1985 event
+        note("bob", DAY0 + 20, "w"),
+        tagged(
+            NostrEvent {
+                kind: LABEL_EVENT_KIND,
+                ..note("bob", DAY0 + 30, "")
+            },
+            &[
+                &["L", "ISO-639-1"],
+                &["l", "ko", "ISO-639-1"],
+                &["e", "bob-1704067220"],
+            ],
+        ),
+        // Labels by someone else are not trusted
+        tagged(
+            NostrEvent {
+                kind: LABEL_EVENT_KIND,
+                ..note("mallory", DAY0 + 40, "")
+            },
+            &[&["l", "de", "ISO-639-1"], &["e", "alice-1704067210"]],
+        ),
+        // Self-declared language
+        tagged(
+            NostrEvent {
+                kind: LABEL_EVENT_KIND,
+                ..note("carol", DAY0 + 50, "")
+            },
+            &[&["l", "pt", "ISO-639-1"], &["p", "carol"]],
+        ),
+        // Declaring the same language again does not add weight
+        tagged(
+            NostrEvent {
+                kind: LABEL_EVENT_KIND,
+                ..note("carol", DAY0 + 60, "")
+            },
+            &[&["l", "pt", "ISO-639-1"], &["p", "carol"]],
+        ),
+    ];
+    assert_eq!(
+        LanguageLabels::from_events(&events).users["carol"],
+        vec![("pt".to_string(), DAY0 + 60)]
+    );
+    let config = |label_mode| IndexConfig {
+        label_mode,
+        eligibility: EligibilityRule {
+            min_posts: 1,
+            min_share: 0.0,
+        },
+        ..Default::default()
+    };
+
+    let prefer = build_language_index(&events, &config(LabelMode::Prefer));
+    let alice = &prefer.user_languages["alice"];
+    assert_eq!(alice.evidence["ja"].posts, 1);
+    assert_eq!(alice.evidence["en"].posts, 1);
+    assert!(!alice.has_language("de"));
+    assert!(prefer.user_languages["bob"].has_language("ko"));
+    // A self-label is a signal, not a post in the language
+    let carol = &prefer.user_languages["carol"].evidence["pt"];
+    assert_eq!((carol.posts, carol.signal), (0, SELF_LABEL_WEIGHT));
+    assert!(prefer.user_languages["carol"].has_language("pt"));
+    let result = prefer.result();
+    assert_eq!(result.events_labeled, 2);
+    assert_eq!(result.self_labels, 1);
+    assert_eq!(result.events_detected, 1);
+    assert_eq!(result.label_conflicts, 0);
+
+    let blend = build_language_index(&events, &config(LabelMode::Blend));
+    let alice = &blend.user_languages["alice"];
+    // The conflicting event counts once, as a post of its label; the
+    // overruled detection only adds a signal
+    assert_eq!(alice.evidence["ja"].posts, 1);
+    assert_eq!(alice.evidence["en"].posts, 1);
+    assert_eq!(alice.evidence["en"].signal, OVERRULED_DETECTION_WEIGHT);
+    assert_eq!(alice.total_posts(), 2);
+    assert_eq!(blend.label_conflicts, 1);
+    assert_eq!(blend.events_labeled, 2);
+    assert_eq!(blend.self_labels, 1);
+
+    let ignore = build_language_index(&events, &config(LabelMode::Ignore));
+    assert!(!ignore.user_languages["alice"].evidence.contains_key("ja"));
+    assert!(!ignore.user_languages.contains_key("bob"));
+    assert!(!ignore.user_languages.contains_key("carol"));
+    assert_eq!(ignore.events_labeled, 0);
+    assert_eq!(ignore.self_labels, 0);
+    assert_eq!(ignore.events_detected, 2);
+}
+
+fn profile(pubkey: &str, created_at: u64, metadata: serde_json::Value) -> NostrEvent {
+    NostrEvent {
+        kind: METADATA_EVENT_KIND,
+        ..note(pubkey, created_at, &metadata.to_string())
+    }
+}
+
+#[test]
+fn test_profile_metadata_hints() {
+    let metadata = ProfileMetadata::parse(
+        r#"{"name":"taro","displayName":"たろう","about":"東京在住のエンジニアです。","nip05":"taro@example.jp","picture":"x"}"#,
+    )
+    .unwrap();
+    assert_eq!(metadata.display_name.as_deref(), Some("たろう"));
+    assert_eq!(metadata.nip05_language().as_deref(), Some("ja"));
+    assert_eq!(metadata.text(), "たろう\n東京在住のエンジニアです。");
+
+    let hints = profile_hints(&default_detector(), &metadata, 0.5, 5).unwrap();
+    assert_eq!(hints.len(), 2);
+    assert!(hints.iter().all(|hint| hint.language == "ja"));
+    assert_eq!(hints[1].confidence, NIP05_HINT_CONFIDENCE);
+
+    let generic = ProfileMetadata {
+        nip05: Some("_@example.com".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(generic.nip05_language(), None);
+    assert!(ProfileMetadata::parse("not json").is_err());
+}
+
+#[test]
+fn test_profile_prior_in_language_index() {
+    let events = vec![
+        // Rare poster: one short-ish note, profile in Japanese
+        note("alice", DAY0, "今日はとても良い天気ですね。"),
+        profile(
+            "alice",
+            DAY0 - 100,
+            serde_json::json!({"about": "Old profile written in English, long ago."}),
+        ),
+        profile(
+            "alice",
+            DAY0 - 10,
+            serde_json::json!({"about": "大阪で猫と暮らしています。写真が好きです。"}),
+        ),
+        // No posts at all, only a German profile on a .de domain
+        profile(
+            "bob",
+            DAY0,
+            serde_json::json!({"about": "Ich wohne in Berlin und fahre gerne Fahrrad.", "nip05": "bob@beispiel.de"}),
+        ),
+        // Broken profile content is skipped
+        NostrEvent {
+            kind: METADATA_EVENT_KIND,
+            ..note("carol", DAY0, "{not json")
+        },
+    ];
+
+    let index = build_language_index(&events, &IndexConfig::default());
+    assert_eq!(index.profiles_used, 2);
+    assert_eq!(index.events_detected, 1);
+
+    let alice = &index.user_languages["alice"];
+    let ja = &alice.evidence["ja"];
+    assert_eq!(ja.posts, 1);
+    assert_eq!(ja.prior, DEFAULT_PROFILE_WEIGHT);
+    assert!(!alice.evidence.contains_key("en"));
+    assert!(ja.mean_confidence() <= 1.0);
+
+    // Two hints at weight 2 meet the default 3-post rule on their own
+    let bob = &index.user_languages["bob"];
+    assert!(bob.has_language("de"));
+    assert_eq!(bob.evidence["de"].posts, 0);
+    assert!((bob.evidence["de"].prior - 2.0 * DEFAULT_PROFILE_WEIGHT).abs() < 1e-6);
+    assert!(!index.user_languages.contains_key("carol"));
+
+    let without = build_language_index(
+        &events,
+        &IndexConfig {
+            profile_weight: 0.0,
+            ..Default::default()
+        },
+    );
+    assert_eq!(without.profiles_used, 0);
+    assert!(!without.user_languages.contains_key("bob"));
+    assert_eq!(without.user_languages["alice"].evidence["ja"].prior, 0.0);
+}
+
+#[test]
+fn test_extend_language_index() {
+    const DAY: u64 = 86400;
+    let ja = [
+        "今日はとても良い天気ですね。散歩に行きましょう。",
+        "明日も晴れるといいですね。楽しみにしています。",
+        "週末は友達と一緒に映画を見に行く予定です。",
+    ];
+    let events = vec![
+        note("alice", DAY0 + 10, ja[0]),
+        note("alice", DAY0 + 20, ja[1]),
+        note(
+            "bob",
+            DAY0 + 30,
+            "Hello, this is a test message in English.",
+        ),
+        // Day 2: alice's third post
+        note("alice", DAY0 + DAY + 10, ja[2]),
+    ];
+    let config = IndexConfig {
+        eligibility: EligibilityRule {
+            min_posts: 3,
+            min_share: 1.1,
+        },
+        ..Default::default()
+    };
+
+    let mut full = build_language_index(&events, &config);
+    full.since = DAY0;
+    full.until = DAY0 + 2 * DAY - 1;
+
+    let mut index = LanguageIndex::new(0, 0);
+    extend_language_index(
+        &mut index,
+        &events,
+        DAY0,
+        DAY0 + DAY - 1,
+        &config,
+        GapPolicy::Refuse,
+    )
+    .unwrap();
+    assert!(index.user_languages.is_empty());
+    assert_eq!(index.pending["alice"].evidence["ja"].posts, 2);
+
+    // Overlapping events are skipped, so re-running from DAY0 is harmless
+    extend_language_index(
+        &mut index,
+        &events,
+        DAY0,
+        DAY0 + 2 * DAY - 1,
+        &config,
+        GapPolicy::Refuse,
+    )
+    .unwrap();
+    assert_eq!((index.since, index.until), (full.since, full.until));
+    assert_eq!(index.events_processed, full.events_processed);
+    assert_eq!(index.to_language_map(), full.to_language_map());
+    assert_eq!(index.user_languages["alice"].evidence["ja"].posts, 3);
+    assert!(index.pending.contains_key("bob"));
+    assert!(index.gaps.is_empty());
+
+    // A day is missing before the next batch
+    let later = vec![note(
+        "bob",
+        DAY0 + 3 * DAY,
+        "Hello again, this is another test message in English.",
+    )];
+    let mut refused = index.clone();
+    let err = extend_language_index(
+        &mut refused,
+        &later,
+        DAY0 + 3 * DAY,
+        DAY0 + 4 * DAY - 1,
+        &config,
+        GapPolicy::Refuse,
+    );
+    assert!(err.is_err());
+    assert_eq!(refused.until, index.until);
+
+    let gap = extend_language_index(
+        &mut index,
+        &later,
+        DAY0 + 3 * DAY,
+        DAY0 + 4 * DAY - 1,
+        &config,
+        GapPolicy::Warn,
+    )
+    .unwrap();
+    assert_eq!(gap, Some((DAY0 + 2 * DAY, DAY0 + 3 * DAY - 1)));
+    assert_eq!(index.gaps, vec![(DAY0 + 2 * DAY, DAY0 + 3 * DAY - 1)]);
+    assert_eq!(index.result().gaps, index.gaps);
+    assert_eq!(index.pending["bob"].evidence["en"].posts, 2);
+}
+
+#[test]
+fn test_merge_language_index_checks_ranges() {
+    let config = IndexConfig::default();
+    let mut first = LanguageIndex::new(100, 199);
+    let overlapping = LanguageIndex::new(150, 299);
+    assert!(first.merge(overlapping, &config, GapPolicy::Warn).is_err());
+
+    let mut other_detector = LanguageIndex::new(200, 299);
+    other_detector.detector = "fixed@ja".to_string();
+    assert!(first
+        .merge(other_detector, &config, GapPolicy::Refuse)
+        .is_err());
+
+    // Earlier ranges can be merged in too
+    let before = LanguageIndex::new(0, 99);
+    first.merge(before, &config, GapPolicy::Refuse).unwrap();
+    assert_eq!((first.since, first.until), (0, 199));
+
+    // Ranges without events or users still count: the gap is recorded
+    let later = LanguageIndex::new(300, 399);
+    assert!(first
+        .clone()
+        .merge(later.clone(), &config, GapPolicy::Refuse)
+        .is_err());
+    let gap = first.merge(later, &config, GapPolicy::Warn).unwrap();
+    assert_eq!(gap, Some((200, 299)));
+    assert_eq!((first.since, first.until), (0, 399));
+    assert_eq!(first.gaps, vec![(200, 299)]);
+
+    // A range ending at the end of time neither overflows nor extends
+    let mut last = LanguageIndex::new(1000, u64::MAX);
+    assert_eq!(
+        last.clone()
+            .merge(LanguageIndex::new(400, 999), &config, GapPolicy::Refuse)
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        extend_language_index(&mut last, &[], 0, u64::MAX, &config, GapPolicy::Refuse).unwrap(),
+        None
+    );
+    assert_eq!((last.since, last.until), (1000, u64::MAX));
+
+    let mut fresh = LanguageIndex::new(0, 0);
+    assert!(fresh.is_empty());
+    fresh.merge(first, &config, GapPolicy::Refuse).unwrap();
+    assert_eq!((fresh.since, fresh.until), (0, 399));
+}
+
+#[test]
+fn test_user_languages_merge_replaces_profile_prior() {
+    let mut old = UserLanguages::new();
+    old.add_post("ja".to_string(), 0.8, DAY0, 5);
+    old.add_prior("ja".to_string(), 0.9, 2.0, 5);
+
+    let mut new = UserLanguages::new();
+    new.add_post("ja".to_string(), 0.6, DAY0 + 86400, 5);
+    new.add_prior("en".to_string(), 1.0, 2.0, 5);
+
+    old.merge(new, 5);
+    let ja = &old.evidence["ja"];
+    assert_eq!(ja.posts, 2);
+    assert_eq!(ja.prior, 0.0);
+    assert_eq!(ja.last_seen, Some(DAY0 + 86400));
+    assert_eq!(ja.daily_posts.len(), 2);
+    assert_eq!(old.evidence["en"].prior, 2.0);
+    assert!((old.languages["ja"] - 0.7).abs() < 1e-6);
+}
+
+#[test]
+fn test_language_index_binary_round_trip() {
+    const DAY: u64 = 86400;
+    let alice = "a".repeat(64);
+    let bob = "0b".repeat(32);
+    let carol = "c0".repeat(32);
+    let mut index = LanguageIndex::new(DAY0, DAY0 + 2 * DAY - 1);
+    index.events_processed = 7;
+    index.events_with_language = 5;
+    index.profiles_used = 1;
+    index.gaps.push((DAY0 - DAY, DAY0 - 1));
+    let mut alice_langs = UserLanguages::new();
+    alice_langs.add_post("ja".to_string(), 0.8, DAY0, 5);
+    alice_langs.add_post("ja".to_string(), 0.7, DAY0 + DAY, 5);
+    alice_langs.add_post("en".to_string(), 0.6, DAY0 + DAY, 5);
+    alice_langs.add_prior("ja".to_string(), 0.9, 2.0, 5);
+    index.user_languages.insert(alice.clone(), alice_langs);
+    let mut bob_langs = UserLanguages::new();
+    bob_langs.add_language("en".to_string(), 0.95, 5);
+    index.user_languages.insert(bob.clone(), bob_langs);
+    let mut carol_langs = UserLanguages::new();
+    carol_langs.add_post("de".to_string(), 0.5, DAY0, 5);
+    carol_langs.languages.clear();
+    index.pending.insert(carol.clone(), carol_langs);
+
+    let bytes = index.to_bytes(true).unwrap();
+    assert!(is_encoded_index(&bytes));
+    let json = serde_json::to_vec(&index).unwrap();
+    assert!(bytes.len() * 3 < json.len());
+
+    let decoded = LanguageIndex::from_bytes(&bytes).unwrap();
+    assert_eq!(
+        (decoded.built_at, decoded.since, decoded.until),
+        (index.built_at, index.since, index.until)
+    );
+    assert_eq!(decoded.events_processed, 7);
+    assert_eq!(decoded.events_with_language, 5);
+    assert_eq!(decoded.profiles_used, 1);
+    assert_eq!(decoded.detector, index.detector);
+    assert_eq!(decoded.gaps, index.gaps);
+    for (pubkey, user_langs) in &index.user_languages {
+        let languages = &decoded.user_languages[pubkey].languages;
+        assert_eq!(languages.len(), user_langs.languages.len());
+        for (lang, confidence) in &user_langs.languages {
+            assert!((languages[lang] - confidence).abs() < 1e-4);
+        }
+        assert_eq!(decoded.user_languages[pubkey].evidence, user_langs.evidence);
+    }
+    assert_eq!(
+        decoded.pending[&carol].evidence,
+        index.pending[&carol].evidence
+    );
+
+    // Without evidence only the ranked languages survive
+    let lean = index.to_bytes(false).unwrap();
+    assert!(lean.len() < bytes.len());
+    let decoded = LanguageIndex::from_bytes(&lean).unwrap();
+    assert!(decoded.user_languages[&alice].evidence.is_empty());
+    assert!(decoded.user_languages[&alice].has_language("en"));
+    assert!(decoded.pending.is_empty());
+
+    // Encoding is deterministic
+    assert_eq!(index.clone().to_bytes(true).unwrap(), bytes);
+}
+
+#[test]
+fn test_language_index_binary_rejects_bad_input() {
+    let mut index = LanguageIndex::new(0, 99);
+    let mut user_langs = UserLanguages::new();
+    user_langs.add_language("ja".to_string(), 0.9, 5);
+    index
+        .user_languages
+        .insert("alice".to_string(), user_langs.clone());
+    assert!(index.to_bytes(true).is_err());
+
+    index.user_languages.clear();
+    index.user_languages.insert("ab".repeat(32), user_langs);
+    let bytes = index.to_bytes(true).unwrap();
+    assert!(LanguageIndex::from_bytes(&bytes).is_ok());
+
+    // Any flipped bit fails the checksum
+    let mut corrupted = bytes.clone();
+    corrupted[20] ^= 0x01;
+    assert!(LanguageIndex::from_bytes(&corrupted).is_err());
+    assert!(LanguageIndex::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(LanguageIndex::from_bytes(b"{\"result\":{}}").is_err());
+
+    // A newer version is refused even with a valid checksum
+    let mut newer = bytes[..bytes.len() - 4].to_vec();
+    newer[4] = INDEX_ENCODING_VERSION + 1;
+    let checksum = crc32_for_test(&newer);
+    newer.extend_from_slice(&checksum.to_le_bytes());
+    let err = LanguageIndex::from_bytes(&newer).unwrap_err();
+    assert!(err.to_string().contains("version"));
+}
+
+/// Reference bitwise CRC-32 (IEEE)
+fn crc32_for_test(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[test]
+fn test_pubkey_interner() {
+    let mut interner = OwnedPubkeyInterner::new();
+    assert_eq!(interner.intern("alice"), 0);
+    assert_eq!(interner.intern("bob"), 1);
+    assert_eq!(interner.intern("alice"), 0);
+    assert_eq!(interner.len(), 2);
+    assert_eq!(interner.get("bob"), Some(1));
+    assert_eq!(interner.get("carol"), None);
+    assert_eq!(interner.resolve(1), Some("bob"));
+    assert_eq!(interner.resolve(2), None);
+}
+
+#[test]
+fn test_user_bitmap_set_operations() {
+    use std::collections::BTreeSet;
+
+    // Sparse ids, a dense run past the array limit and a second container
+    let a_ids: BTreeSet<u32> = (0..6000)
+        .chain((10_000..10_100).step_by(3))
+        .chain([70_000, 1 << 31])
+        .collect();
+    let b_ids: BTreeSet<u32> = (3000..9000).chain([10_003, 70_000, 70_001]).collect();
+    let a: UserBitmap = a_ids.iter().copied().collect();
+    let b: UserBitmap = b_ids.iter().copied().collect();
+
+    assert_eq!(a.len(), a_ids.len());
+    assert!(a.contains(5999) && a.contains(1 << 31) && !a.contains(6000));
+    assert_eq!(a.iter().collect::<BTreeSet<u32>>(), a_ids);
+    let mut c = a.clone();
+    assert!(!c.insert(42));
+    assert!(c.insert(6000));
+
+    let mut union = a.clone();
+    union.union_with(&b);
+    assert_eq!(
+        union.iter().collect::<BTreeSet<u32>>(),
+        a_ids.union(&b_ids).copied().collect()
+    );
+
+    assert_eq!(a.intersection_len(&b), a_ids.intersection(&b_ids).count());
+
+    let mut difference = a.clone();
+    difference.difference_with(&b);
+    let expected: BTreeSet<u32> = a_ids.difference(&b_ids).copied().collect();
+    assert_eq!(difference.iter().collect::<BTreeSet<u32>>(), expected);
+
+    // Equal sets compare equal whatever order they were built in
+    let rebuilt: UserBitmap = expected.iter().rev().copied().collect();
+    assert_eq!(difference, rebuilt);
+    difference.difference_with(&a);
+    assert!(difference.is_empty());
+}
+
+#[test]
+fn test_hyperloglog_estimates() {
+    let pubkey = |i: u64| format!("{:064x}", i.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+
+    let mut sketch = HyperLogLog::new(DEFAULT_HLL_PRECISION);
+    assert!(sketch.is_empty());
+    assert_eq!(sketch.estimate(), 0.0);
+
+    for i in 0..20_000 {
+        sketch.insert(&pubkey(i));
+        // Duplicates do not count
+        sketch.insert(&pubkey(i / 2));
+    }
+    let bound = 2.0 * sketch.relative_error() * 20_000.0;
+    assert!((sketch.estimate() - 20_000.0).abs() <= bound);
+
+    // Small sets use linear counting, whose error is well below the
+    // asymptotic bound
+    let mut small = HyperLogLog::new(DEFAULT_HLL_PRECISION);
+    for i in 0..1000 {
+        small.insert(&pubkey(i));
+    }
+    let (estimate, error) = small.estimate_with_error();
+    assert_eq!(estimate, small.estimate());
+    assert!(error < small.relative_error() * estimate);
+    assert!((estimate - 1000.0).abs() <= 2.0 * error);
+
+    // Merging equals sketching the union
+    let mut a = HyperLogLog::new(10);
+    let mut b = HyperLogLog::new(10);
+    let mut union = HyperLogLog::new(10);
+    for i in 0..3000 {
+        let key = pubkey(i);
+        if i % 3 == 0 {
+            a.insert(&key);
+        } else {
+            b.insert(&key);
+        }
+        union.insert(&key);
+    }
+    a.merge(&b);
+    assert_eq!(a, union);
+
+    assert_eq!(
+        "hll".parse::<CountingMode>().unwrap(),
+        CountingMode::Approximate {
+            precision: DEFAULT_HLL_PRECISION
+        }
+    );
+    assert_eq!(
+        "hll-p14".parse::<CountingMode>().unwrap().to_string(),
+        "hll-p14"
+    );
+    assert!("hll-p30".parse::<CountingMode>().is_err());
+    assert!("fuzzy".parse::<CountingMode>().is_err());
+}
+
+#[test]
+fn test_approximate_metrics_match_exact_within_bound() {
+    const DAY: u64 = 86400;
+    let pubkey = |i: u64| format!("{:064x}", i.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+
+    let mut map = std::collections::HashMap::new();
+    let mut events = Vec::new();
+    for i in 0..600u64 {
+        let lang = if i % 4 == 0 { "en" } else { "ja" };
+        map.insert(
+            pubkey(i),
+            std::collections::HashMap::from([(lang.to_string(), 0.9)]),
+        );
+        // Each user is active on a few of the 40 days
+        for day in [i % 40, (i * 7) % 40, (i * 13) % 40] {
+            events.push(note(&pubkey(i), DAY0 + day * DAY + 60, "+"));
+        }
+    }
+    let index = LanguageIndex::from_language_map(map, DAY0, DAY0 + 40 * DAY - 1);
+
+    let mut options = MetricsOptions {
+        since: DAY0,
+        until: DAY0 + 40 * DAY - 1,
+        languages: vec!["ja".to_string(), "en".to_string()],
+        granularity: Granularity::Day,
+        window_days: 7,
+        zone: Zone::Utc,
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+    };
+    let approximate = MetricsOptions {
+        counting: CountingMode::Approximate { precision: 10 },
+        ..options.clone()
+    };
+
+    for granularity in [Granularity::Day, Granularity::Month] {
+        options.granularity = granularity;
+        let approximate = MetricsOptions {
+            granularity,
+            ..approximate.clone()
+        };
+        let exact = compute_active_users_by_language(&events, &index, &options);
+        let estimated = compute_active_users_by_language(&events, &index, &approximate);
+        for lang in ["ja", "en"] {
+            assert_eq!(exact[lang].len(), estimated[lang].len());
+            for (e, a) in exact[lang].iter().zip(&estimated[lang]) {
+                assert_eq!(e.epoch_day, a.epoch_day);
+                assert_eq!(e.error, None);
+                let error = a.error.unwrap();
+                assert!(
+                    e.count.abs_diff(a.count) <= error,
+                    "{} day {}: exact {} estimate {} ± {}",
+                    lang,
+                    e.epoch_day,
+                    e.count,
+                    a.count,
+                    error
+                );
+            }
+        }
+    }
+
+    // The mode is recorded in the algo tag and the content
+    let by_language = compute_active_users_by_language(&events, &index, &approximate);
+    let event = AnalyticsEvent {
+        metric: "wau".to_string(),
+        language: "ja".to_string(),
+        relays: Vec::new(),
+        start: approximate.since,
+        end: approximate.until,
+        granularity: Granularity::Day,
+        window_days: 7,
+        zone: Zone::Utc,
+        detector: index.detector.clone(),
+        eligibility: EligibilityScope::Lifetime,
+        counting: approximate.counting,
+        counts: by_language["ja"].clone(),
+        eligible_user_count: 450,
+        ratios: Vec::new(),
+    };
+    let algo = event
+        .tags()
+        .into_iter()
+        .find(|tag| tag[0] == "algo")
+        .unwrap();
+    assert!(algo[1].ends_with(";elig=lifetime;count=hll-p10"));
+    let content: serde_json::Value = serde_json::from_str(&event.content(DAY0).unwrap()).unwrap();
+    assert_eq!(content["countErrors"].as_array().unwrap().len(), 40);
+}
+
+#[test]
+fn test_language_index_builder_matches_single_pass() {
+    let english = "Hello, this is a test message in English.";
+    let japanese = "こんにちは、これは日本語のテストメッセージです。";
+    let events = vec![
+        note("alice", DAY0, japanese),
+        tagged(
+            note("alice", DAY0 + 10, english),
+            &[&["l", "ja", "ISO-639-1"]],
+        ),
+        // Labeled by a kind 1985 event in a later batch
+        note("bob", DAY0 + 20, "w"),
+        note("bob", DAY0 + 25, english),
+        profile(
+            "carol",
+            DAY0 + 30,
+            serde_json::json!({"about": "東京在住のエンジニアです。よろしくお願いします。"}),
+        ),
+        note("carol", DAY0 + 40, japanese),
+        tagged(
+            NostrEvent {
+                kind: LABEL_EVENT_KIND,
+                ..note("bob", DAY0 + 50, "")
+            },
+            &[&["l", "ko", "ISO-639-1"], &["e", "bob-1704067220"]],
+        ),
+        note("dave", DAY0 + 60, english),
+    ];
+
+    for label_mode in [LabelMode::Prefer, LabelMode::Blend, LabelMode::Ignore] {
+        let config = IndexConfig {
+            label_mode,
+            eligibility: EligibilityRule {
+                min_posts: 1,
+                min_share: 0.0,
+            },
+            ..Default::default()
+        };
+        let single = build_language_index(&events, &config);
+
+        let mut builder = LanguageIndexBuilder::new(config.clone());
+        for batch in events.chunks(3) {
+            builder.push_batch(batch);
+        }
+        assert_eq!(
+            builder.progress(),
+            StreamProgress {
+                batches: 3,
+                events_processed: 8,
+                events_rejected: 0,
+                users: 4,
+            }
+        );
+        let streamed = builder.finish();
+
+        assert_eq!(streamed.to_language_map(), single.to_language_map());
+        assert_eq!(streamed.to_evidence_map(), single.to_evidence_map());
+        assert_eq!((streamed.since, streamed.until), (DAY0, DAY0 + 60));
+        // The label of a later batch still applies to bob's first note
+        assert_eq!(
+            streamed.user_languages["bob"].evidence.contains_key("ko"),
+            label_mode != LabelMode::Ignore
+        );
+        let (streamed, single) = (streamed.result(), single.result());
+        assert_eq!(streamed.events_processed, single.events_processed);
+        assert_eq!(streamed.events_with_language, single.events_with_language);
+        assert_eq!(streamed.events_labeled, single.events_labeled);
+        assert_eq!(streamed.self_labels, single.self_labels);
+        assert_eq!(streamed.events_detected, single.events_detected);
+        assert_eq!(streamed.events_too_short, single.events_too_short);
+        assert_eq!(streamed.label_conflicts, single.label_conflicts);
+        assert_eq!(streamed.profiles_used, single.profiles_used);
+    }
+
+    // The label in the last batch still applies to bob's first note
+    let mut builder = LanguageIndexBuilder::new(IndexConfig::default());
+    builder.push_batch(&events[..4]);
+    builder.push_batch(&events[4..]);
+    let index = builder.finish();
+    assert_eq!(index.user_languages["bob"].evidence["ko"].posts, 1);
+    assert_eq!(index.events_too_short, 0);
+
+    // `max_events` counts across batches
+    let mut builder = LanguageIndexBuilder::new(IndexConfig {
+        max_events: Some(5),
+        ..Default::default()
+    });
+    builder.push_batch(&events[..3]);
+    builder.push_batch(&events[3..]);
+    assert_eq!(builder.progress().events_processed, 5);
+    assert_eq!(builder.finish().until, DAY0 + 30);
+}
+
+#[test]
+fn test_language_index_builder_buffer_is_bounded() {
+    let english = "Hello, this is a test message in English.";
+    let mut builder = LanguageIndexBuilder::new(IndexConfig::default()).with_label_buffer(100);
+    for batch in 0..20u64 {
+        let events: Vec<NostrEvent> = (0..50u64)
+            .map(|i| note(&format!("user{}", i % 5), DAY0 + batch * 1000 + i, english))
+            .collect();
+        builder.push_batch(&events);
+        assert!(builder.buffered_posts() <= 100);
+    }
+    assert_eq!(builder.buffered_posts(), 100);
+
+    // A label of a post still in the buffer moves it; older posts keep their
+    // detection
+    builder.push_batch(&[tagged(
+        NostrEvent {
+            kind: LABEL_EVENT_KIND,
+            ..note("user0", DAY0 + 30_000, "")
+        },
+        &[
+            &["l", "ko", "ISO-639-1"],
+            &["e", &format!("user0-{}", DAY0)],
+            &["e", &format!("user0-{}", DAY0 + 19 * 1000 + 45)],
+        ],
+    )]);
+    let index = builder.finish();
+    assert_eq!(index.events_with_language, 1000);
+    assert_eq!(index.events_labeled, 1);
+    assert_eq!(index.user_languages["user0"].evidence["ko"].posts, 1);
+    assert_eq!(index.user_languages["user0"].evidence["en"].posts, 199);
+}
+
+#[test]
+fn test_metrics_builder_matches_single_pass() {
+    const DAY: u64 = 86400;
+    let mut events = Vec::new();
+    for day in 0..20u64 {
+        events.push(note("alice", DAY0 + day * DAY + 60, "+"));
+        if day % 3 == 0 {
+            events.push(note("bob", DAY0 + day * DAY + 120, "+"));
+        }
+        events.push(note("mallory", DAY0 + day * DAY + 180, "+"));
+    }
+    let mut index = LanguageIndex::new(DAY0, DAY0 + 20 * DAY - 1);
+    for (pubkey, lang, day) in [("alice", "ja", 0), ("bob", "en", 9), ("bob", "ja", 12)] {
+        index
+            .user_languages
+            .entry(pubkey.to_string())
+            .or_default()
+            .add_post(lang.to_string(), 0.9, DAY0 + day * DAY, 5);
+    }
+
+    for (eligibility, counting) in [
+        (EligibilityScope::Lifetime, CountingMode::Exact),
+        (
+            EligibilityScope::Period { lookback_days: 3 },
+            CountingMode::Exact,
+        ),
+        (
+            EligibilityScope::Lifetime,
+            CountingMode::Approximate { precision: 8 },
+        ),
+    ] {
+        let options = MetricsOptions {
+            since: DAY0,
+            until: DAY0 + 20 * DAY - 1,
+            languages: vec!["ja".to_string(), "en".to_string()],
+            granularity: Granularity::Day,
+            window_days: 7,
+            zone: Zone::Utc,
+            eligibility,
+            counting,
+        };
+        for group_by in [GroupBy::All, GroupBy::Language, GroupBy::Kind] {
+            let single = compute_metrics(&events, &index, &options, group_by);
+
+            let mut builder = MetricsBuilder::new(&index, options.clone(), group_by);
+            for batch in events.chunks(7) {
+                builder.push_batch(batch);
+            }
+            let progress = builder.progress();
+            assert_eq!(progress.batches, 7);
+            assert_eq!(progress.events_processed, events.len() as u32);
+            assert_eq!(progress.users, 2);
+            assert_eq!(
+                serde_json::to_value(builder.finish()).unwrap(),
+                serde_json::to_value(single).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_validate_events_with_empty_tags() {
+    use nostr::hashes::{sha256, Hash};
+
+    // NIP-01 allows any array of strings as a tag, even an empty one
+    let keys =
+        nostr::Keys::parse("0000000000000000000000000000000000000000000000000000000000000001")
+            .unwrap();
+    let mut event = NostrEvent {
+        id: String::new(),
+        pubkey: keys.public_key().to_hex(),
+        created_at: DAY0,
+        kind: 1,
+        tags: vec![vec![], vec!["t".to_string(), "nostr".to_string()]],
+        content: "こんにちは".to_string(),
+        sig: String::new(),
+    };
+    let serialized = serde_json::json!([
+        0,
+        event.pubkey,
+        event.created_at,
+        event.kind,
+        event.tags,
+        event.content
+    ]);
+    let hash = sha256::Hash::hash(serialized.to_string().as_bytes()).to_byte_array();
+    event.id = nostr::EventId::from_byte_array(hash).to_hex();
+    event.sig = keys
+        .sign_schnorr(&nostr::secp256k1::Message::from_digest(hash))
+        .to_string();
+
+    let strict = ValidationPolicy {
+        verify_signatures: true,
+        ..Default::default()
+    };
+    let mut accepted = vec![event.clone()];
+    let report = validate_events(&mut accepted, &strict, DAY0);
+    assert_eq!((report.accepted, report.rejected_total()), (1, 0));
+
+    event.tags.remove(0);
+    assert_eq!(verify_id(&event), Err(RejectReason::InvalidId));
+}
+
+#[test]
+fn test_validate_events_by_reason() {
+    let signed = AnalyticsEvent {
+        metric: "dau".to_string(),
+        language: "ja".to_string(),
+        relays: Vec::new(),
+        start: DAY0,
+        end: DAY0 + 86399,
+        granularity: Granularity::Day,
+        window_days: 1,
+        zone: Zone::Utc,
+        detector: default_detector_id(),
+        eligibility: EligibilityScope::Lifetime,
+        counting: CountingMode::Exact,
+        counts: Vec::new(),
+        eligible_user_count: 0,
+        ratios: Vec::new(),
+    }
+    .sign("0000000000000000000000000000000000000000000000000000000000000001")
+    .unwrap();
+    let now = signed.created_at;
+
+    let mut forged_content = signed.clone();
+    forged_content.content.push(' ');
+    let mut forged_sig = signed.clone();
+    let last = if forged_sig.sig.ends_with('0') {
+        "1"
+    } else {
+        "0"
+    };
+    forged_sig.sig.replace_range(127.., last);
+    let malformed = NostrEvent {
+        id: "not-an-id".to_string(),
+        ..signed.clone()
+    };
+    let future = NostrEvent {
+        created_at: now + 3600,
+        ..signed.clone()
+    };
+    let ancient = NostrEvent {
+        created_at: NOSTR_EPOCH - 1,
+        ..signed.clone()
+    };
+    let events = vec![
+        signed.clone(),
+        forged_content,
+        forged_sig,
+        malformed,
+        future,
+        ancient,
+    ];
+
+    // Ids and timestamps by default; the forged signature goes unnoticed
+    let mut accepted = events.clone();
+    let report = validate_events(&mut accepted, &ValidationPolicy::default(), now);
+    assert_eq!(accepted.len(), 2);
+    assert_eq!((report.checked, report.accepted), (6, 2));
+    assert_eq!(
+        serde_json::to_value(&report.rejected).unwrap(),
+        serde_json::json!({
+            "pre_nostr_timestamp": 1,
+            "future_timestamp": 1,
+            "malformed": 1,
+            "invalid_id": 1,
+        })
+    );
+    assert_eq!(
+        report.to_string(),
+        "4 of 6 events rejected (pre_nostr_timestamp: 1, future_timestamp: 1, malformed: 1, invalid_id: 1)"
+    );
+
+    let strict = ValidationPolicy {
+        verify_signatures: true,
+        ..Default::default()
+    };
+    let mut accepted = events.clone();
+    let report = validate_events(&mut accepted, &strict, now);
+    assert_eq!(accepted[0].id, signed.id);
+    assert_eq!(report.accepted, 1);
+    assert_eq!(report.rejected[&RejectReason::InvalidSignature], 1);
+
+    // Every check can be turned off
+    let lenient = ValidationPolicy {
+        verify_ids: false,
+        verify_signatures: false,
+        max_clock_skew: None,
+        min_created_at: 0,
+    };
+    let mut accepted = events.clone();
+    assert_eq!(
+        validate_events(&mut accepted, &lenient, now).rejected_total(),
+        0
+    );
+
+    // Builders drop rejected events before processing them
+    let mut builder = LanguageIndexBuilder::new(IndexConfig::default())
+        .with_validation(EventValidator::new(strict.clone(), now));
+    builder.push_batch(&events[..3]);
+    builder.push_batch(&events[3..]);
+    let progress = builder.progress();
+    assert_eq!(
+        (progress.events_processed, progress.events_rejected),
+        (1, 5)
+    );
+    assert_eq!(builder.validation_report().unwrap().rejected_total(), 5);
+
+    let index = LanguageIndex::new(now, now);
+    let mut metrics = MetricsBuilder::new(
+        &index,
+        MetricsOptions {
+            since: now,
+            until: now,
+            languages: vec!["ja".to_string()],
+            granularity: Granularity::Day,
+            window_days: 1,
+            zone: Zone::Utc,
+            eligibility: EligibilityScope::Lifetime,
+            counting: CountingMode::Exact,
+        },
+        GroupBy::All,
+    )
+    .with_validation(EventValidator::new(ValidationPolicy::default(), now));
+    metrics.push_batch(&events);
+    assert_eq!(metrics.progress().events_processed, 2);
+    assert_eq!(metrics.progress().events_rejected, 4);
+}
@@ -0,0 +1,152 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use common::{event, MockRelay, MockRelayConfig, ReqBehavior};
+use nostr_analytics::*;
+use std::time::{Duration, Instant};
+
+fn sample_events() -> Vec<NostrEvent> {
+    vec![
+        event("a1", "alice", 1704067200, 1, "hello"),
+        event("a2", "alice", 1704070800, 7, "+"),
+        event("b1", "bob", 1704074400, 1, "こんにちは"),
+        event("b2", "bob", 1704153600, 1, "tomorrow"),
+    ]
+}
+
+fn quick_options() -> RelayClientOptions {
+    RelayClientOptions {
+        connect_timeout: Duration::from_secs(5),
+        subscription_timeout: Duration::from_millis(500),
+    }
+}
+
+#[test]
+fn test_fetch_until_eose() {
+    let relay = MockRelay::start(MockRelayConfig {
+        events: sample_events(),
+        ..Default::default()
+    });
+    let mut client = RelayClient::connect_with_options(&relay.url, quick_options()).unwrap();
+
+    let filter = Filter::new()
+        .kinds(vec![1])
+        .since(1704067200)
+        .until(1704153599);
+    let result = client.fetch(&[filter]).unwrap();
+
+    assert_eq!(result.end, SubscriptionEnd::Eose);
+    let mut ids: Vec<_> = result.events.iter().map(|e| e.id.as_str()).collect();
+    ids.sort();
+    assert_eq!(ids, vec!["a1", "b1"]);
+
+    // Subscription is closed after EOSE
+    client.close().unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(relay.closed.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_fetch_many_concurrent_subscriptions() {
+    let relay = MockRelay::start(MockRelayConfig {
+        events: sample_events(),
+        ..Default::default()
+    });
+    let mut client = RelayClient::connect_with_options(&relay.url, quick_options()).unwrap();
+
+    let results = client
+        .fetch_many(&[
+            vec![Filter::new().authors(vec!["alice".to_string()])],
+            vec![Filter::new().authors(vec!["bob".to_string()])],
+            vec![Filter::new().kinds(vec![7])],
+        ])
+        .unwrap();
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].events.len(), 2);
+    assert_eq!(results[1].events.len(), 2);
+    assert_eq!(results[2].events.len(), 1);
+    assert!(results.iter().all(|r| r.end == SubscriptionEnd::Eose));
+    assert_eq!(relay.request_count(), 3);
+}
+
+#[test]
+fn test_closed_and_notice() {
+    let relay = MockRelay::start(MockRelayConfig {
+        greeting_notice: Some("welcome".to_string()),
+        behavior: ReqBehavior::Close("blocked: rate-limited".to_string()),
+        ..Default::default()
+    });
+    let mut client = RelayClient::connect_with_options(&relay.url, quick_options()).unwrap();
+
+    let result = client.fetch(&[Filter::new()]).unwrap();
+
+    assert_eq!(result.end, SubscriptionEnd::Closed);
+    assert_eq!(result.message.as_deref(), Some("blocked: rate-limited"));
+    assert_eq!(client.notices(), ["welcome".to_string()]);
+}
+
+#[test]
+fn test_subscription_timeout() {
+    let relay = MockRelay::start(MockRelayConfig {
+        behavior: ReqBehavior::Ignore,
+        ..Default::default()
+    });
+    let mut client = RelayClient::connect_with_options(&relay.url, quick_options()).unwrap();
+
+    let started = Instant::now();
+    let result = client.fetch(&[Filter::new()]).unwrap();
+
+    assert_eq!(result.end, SubscriptionEnd::Timeout);
+    assert!(result.events.is_empty());
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn test_connect_error() {
+    let result = RelayClient::connect_with_options("ws://127.0.0.1:1", quick_options());
+    assert!(matches!(result, Err(AnalyticsError::RelayConnection(_))));
+}
+
+#[test]
+fn test_publish_waits_for_ok() {
+    let relay = MockRelay::start(MockRelayConfig::default());
+    let mut client = RelayClient::connect_with_options(&relay.url, quick_options()).unwrap();
+
+    let (accepted, _) = client
+        .publish(&event("e1", "alice", 1704067200, 1, "hi"))
+        .unwrap();
+    assert!(accepted);
+}
+
+#[test]
+fn test_relay_message_parsing() {
+    let msg = RelayMessage::from_json(r#"["EOSE","sub1"]"#).unwrap();
+    assert!(matches!(msg, RelayMessage::Eose { subscription_id } if subscription_id == "sub1"));
+
+    let msg = RelayMessage::from_json(r#"["OK","abc",false,"invalid: bad sig"]"#).unwrap();
+    assert!(matches!(
+        msg,
+        RelayMessage::Ok {
+            accepted: false,
+            ..
+        }
+    ));
+
+    assert!(RelayMessage::from_json(r#"{"not":"an array"}"#).is_err());
+}
+
+#[test]
+fn test_filter_serialization() {
+    let filter = Filter::new()
+        .kinds(vec![1, 42])
+        .since(100)
+        .limit(10)
+        .tag('l', vec!["ja".to_string()]);
+    let json = serde_json::to_value(&filter).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({"kinds": [1, 42], "since": 100, "limit": 10, "#l": ["ja"]})
+    );
+}
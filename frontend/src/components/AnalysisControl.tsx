@@ -1,7 +1,7 @@
 import React, { useState } from 'react';
 import { useAnalyticsStore } from '../state/store';
 import { initWasm, buildLanguageIndex, computeMetricsByLanguage } from '../wasm/analytics';
-import { MetricType } from '../types';
+import { MetricsOptions, MetricType } from '../types';
 import {
   generateCacheKey,
   generateLanguageIndexKey,
@@ -33,7 +33,9 @@ export const AnalysisControl: React.FC = () => {
     clearRelayProgress,
     setResults,
     clearResults,
-    setEvents
+    setEvents,
+    setDetector,
+    setMetricsOptions
   } = useAnalyticsStore();
   
   const [error, setError] = useState<string | null>(null);
@@ -71,12 +73,14 @@ export const AnalysisControl: React.FC = () => {
       );
       
       let userLanguages: Record<string, Record<string, number>>;
+      let detector: string | undefined; // of the index, for the published algo tag
       let allEvents: any[] = [];
       
       if (bestCache) {
         // Perfect match - use cached data
         console.log(`Using cached data: ${bestCache.since} - ${bestCache.until}`);
         const cachedData = bestCache.data;
+        detector = cachedData.result?.detector;
         
         if (cachedData.userLanguages) {
           userLanguages = cachedData.userLanguages;
@@ -110,6 +114,7 @@ export const AnalysisControl: React.FC = () => {
           
           // Load existing data
           const cachedData = overlappingCache.data;
+          detector = cachedData.result?.detector;
           if (cachedData.userLanguages) {
             userLanguages = cachedData.userLanguages;
           } else if (cachedData.result && cachedData.result.userLanguages) {
@@ -147,8 +152,10 @@ export const AnalysisControl: React.FC = () => {
               maxLangsPerUser: 5
             }, (relay, progress, status, fetched) => {
               setRelayProgress(relay, { status: status as any, progress, fetched });
-            });
+            }, {}); // default ValidationPolicy: check ids and timestamps
             
+            detector = indexData.result?.detector ?? detector;
+
             // Merge user languages
             let newUserLanguages = indexData.userLanguages;
             if (newUserLanguages instanceof Map) {
@@ -189,8 +196,9 @@ export const AnalysisControl: React.FC = () => {
             maxLangsPerUser: 5
           }, (relay, progress, status, fetched) => {
             setRelayProgress(relay, { status: status as any, progress, fetched });
-          });
+          }, {}); // default ValidationPolicy: check ids and timestamps
           
+          detector = indexData.result?.detector;
           if (indexData.userLanguages instanceof Map) {
             userLanguages = Object.fromEntries(indexData.userLanguages);
           } else {
@@ -215,7 +223,7 @@ export const AnalysisControl: React.FC = () => {
         // Save merged/new data to cache
         const indexKey = generateLanguageIndexKey(config.relays, since, until);
         const dataToCache = {
-          result: { userLanguages },
+          result: { userLanguages, detector },
           userLanguages: userLanguages
         };
         await saveLanguageIndex(indexKey, dataToCache, config.relays, since, until);
@@ -244,6 +252,18 @@ export const AnalysisControl: React.FC = () => {
         setEvents(allEvents);
       }
 
+      setDetector(detector ?? null);
+
+      // compute_metrics_by_language counts languages over the whole lifetime
+      const metricsOptions: Omit<MetricsOptions, 'window_days'> = {
+        since,
+        until,
+        languages: [], // Empty = compute for all languages
+        granularity: 'day',
+        eligibility: 'lifetime'
+      };
+      setMetricsOptions(metricsOptions);
+
       // Step 2: Compute metrics for each selected metric
       for (let i = 0; i < config.metrics.length; i++) {
         const metric = config.metrics[i];
@@ -269,13 +289,7 @@ export const AnalysisControl: React.FC = () => {
           const metricsDataByLang = await computeMetricsByLanguage(
             allEvents, // Use cached events
             userLanguages,
-            {
-              since,
-              until,
-              languages: [], // Empty = compute for all languages
-              granularity: 'day',
-              window_days: windowDays
-            }
+            { ...metricsOptions, window_days: windowDays }
           );
           
           // Save ALL language metrics to cache (convert to plain object)
@@ -4,7 +4,7 @@ import { isNostrAvailable, getPublicKey, createAnalyticsEvent, publishToRelays }
 import { MetricType } from '../types';
 
 export const NostrPublisher: React.FC = () => {
-  const { config, results, pubkey, setPubkey } = useAnalyticsStore();
+  const { config, results, detector, metricsOptions, pubkey, setPubkey } = useAnalyticsStore();
   const [isPublishing, setIsPublishing] = useState(false);
   const [publishError, setPublishError] = useState<string | null>(null);
   const [publishSuccess, setPublishSuccess] = useState(false);
@@ -21,16 +21,13 @@ export const NostrPublisher: React.FC = () => {
   }, [nostrAvailable, pubkey, setPubkey]);
 
   const handlePublish = async () => {
-    if (!hasResults || !nostrAvailable) return;
+    if (!hasResults || !nostrAvailable || !metricsOptions) return;
 
     setIsPublishing(true);
     setPublishError(null);
     setPublishSuccess(false);
 
     try {
-      const since = Math.floor(config.dateRange.start.getTime() / 1000);
-      const until = Math.floor(config.dateRange.end.getTime() / 1000);
-
       // Publish each metric for each language
       for (const metric of config.metrics) {
         const data = results[metric];
@@ -39,13 +36,14 @@ export const NostrPublisher: React.FC = () => {
         for (const language of config.languages) {
           const windowDays = metric === 'dau' ? 1 : metric === 'wau' ? 7 : metric === 'mau' ? 30 : 365;
           
-          const event = createAnalyticsEvent(
+          const event = await createAnalyticsEvent(
             metric as MetricType,
             language,
             config.relays,
-            { start: since, end: until, windowDays },
+            { ...metricsOptions, window_days: windowDays },
             data,
-            0 // eligibleUserCount - would be calculated from language index
+            0, // eligibleUserCount - would be calculated from language index
+            detector ?? undefined
           );
 
           await publishToRelays(event, config.relays);
@@ -1,4 +1,4 @@
-import { NostrEvent, MetricDataPoint, MetricType } from '../types';
+import { NostrEvent, MetricDataPoint, MetricType, MetricsOptions } from '../types';
 import { initWasm } from '../wasm/analytics';
 
 // Storage key for private key
@@ -111,30 +111,32 @@ export const signEvent = async (event: NostrEvent): Promise<NostrEvent> => {
   }
 };
 
-// Create analytics result event (kind: 30080)
-export const createAnalyticsEvent = (
+// Create analytics result event (kind: 30080). The algo tag comes from WASM
+// so it matches the events the CLI publishes.
+export const createAnalyticsEvent = async (
   metric: MetricType,
   language: string,
   relays: string[],
-  timeframe: {
-    start: number;
-    end: number;
-    windowDays: number;
-  },
+  options: MetricsOptions, // the options the counts were computed with
   counts: MetricDataPoint[],
-  eligibleUserCount: number
-): NostrEvent => {
+  eligibleUserCount: number,
+  detector?: string // of the language index; the default detector when omitted
+): Promise<NostrEvent> => {
+  const wasm = await ensureWasmLoaded();
   const now = Math.floor(Date.now() / 1000);
   
+  // Calendar buckets have no sliding window
+  const windowDays = options.granularity === 'day' ? options.window_days : 1;
+
   // Create d tag for parameterized replaceable event
-  const dTag = `${metric}-${language}-day-${timeframe.windowDays}-${timeframe.start}-${timeframe.end}-v1`;
+  const dTag = `${metric}-${language}-${options.granularity}-${windowDays}-${options.since}-${options.until}-v1`;
   
   const tags: string[][] = [
     ['d', dTag],
     ['l', language],
-    ['algo', 'lang=whatlang@0.16;act=all-kinds;elig=lifetime'],
-    ['gran', 'day'],
-    ['wdays', timeframe.windowDays.toString()],
+    ['algo', wasm.analytics_algo_tag(detector, options.zone, options.eligibility, options.counting)],
+    ['gran', options.granularity],
+    ['wdays', windowDays.toString()],
     ['app', 'nostr-analytics/0.1.0']
   ];
   
@@ -143,18 +145,24 @@ export const createAnalyticsEvent = (
     tags.push(['r', relay]);
   }
   
+  // Error bounds of HyperLogLog estimates, as the CLI publishes them
+  const countErrors = counts
+    .filter(c => c.error !== undefined && c.error !== null)
+    .map(c => [c.epoch_day, c.error]);
+
   const content = JSON.stringify({
     version: 1,
     metric,
     language,
     relays,
     timeframe: {
-      start: timeframe.start,
-      end: timeframe.end,
-      granularity: 'day',
-      windowDays: timeframe.windowDays
+      start: options.since,
+      end: options.until,
+      granularity: options.granularity,
+      windowDays
     },
     counts: counts.map(c => [c.epoch_day, c.count]),
+    ...(countErrors.length > 0 ? { countErrors } : {}),
     eligibleUserCount,
     notes: `Generated by Nostr Analytics at ${new Date().toISOString()}`
   });
@@ -1,6 +1,6 @@
 import { create } from 'zustand';
 import { persist } from 'zustand/middleware';
-import { AnalyticsConfig, MetricType } from '../types';
+import { AnalyticsConfig, MetricsOptions, MetricType } from '../types';
 
 interface AnalyticsState {
   // Configuration
@@ -37,6 +37,14 @@ interface AnalyticsState {
   events: any[];
   setEvents: (events: any[]) => void;
   
+  // Language detector id of the index behind the results
+  detector: string | null;
+  setDetector: (detector: string | null) => void;
+
+  // Options the results were computed with, but for each metric's window
+  metricsOptions: Omit<MetricsOptions, 'window_days'> | null;
+  setMetricsOptions: (options: Omit<MetricsOptions, 'window_days'> | null) => void;
+
   // Nostr
   pubkey: string | null;
   setPubkey: (pubkey: string | null) => void;
@@ -101,6 +109,11 @@ export const useAnalyticsStore = create<AnalyticsState>()(
       events: [],
       setEvents: (events) => set({ events }),
       
+      detector: null,
+      setDetector: (detector) => set({ detector }),
+      metricsOptions: null,
+      setMetricsOptions: (metricsOptions) => set({ metricsOptions }),
+
       // Nostr
       pubkey: null,
       setPubkey: (pubkey) => set({ pubkey })
@@ -5,6 +5,14 @@ export interface LanguageIndexResult {
   by_lang: Record<string, number>;
   events_processed: number;
   events_with_language: number;
+  events_too_short?: number;
+  events_labeled?: number;
+  self_labels?: number; // kind 1985 self-labels applied, once per user and language
+  events_detected?: number;
+  label_conflicts?: number;
+  profiles_used?: number;
+  gaps?: [number, number][];
+  detector?: string; // language detector id (name@version), recorded in the algo tag
 }
 
 export interface LanguageIndexOptions {
@@ -13,19 +21,54 @@ export interface LanguageIndexOptions {
   maxEvents?: number;
   confThresh?: number;
   maxLangsPerUser?: number;
+  minPosts?: number; // posts in a language that make it eligible (default 3)
+  minShare?: number; // or share of the user's detected posts (default 0.2)
 }
 
 export interface MetricsOptions {
   since: number;
   until: number;
   languages: string[];
-  granularity: 'day';
-  window_days: 1 | 7 | 30 | 365;
+  granularity: 'day' | 'week' | 'month'; // sliding windows, or calendar (ISO) weeks / months
+  window_days: 1 | 7 | 30 | 365; // sliding window size, only used with 'day'
+  zone?: string; // day boundaries: 'UTC' (default), an offset such as '+09:00' or an IANA name
+  eligibility?: 'lifetime' | `period-${number}d`; // when a user's language counts (default lifetime)
+  counting?: 'exact' | 'hll' | `hll-p${number}`;
 }
 
 export interface MetricDataPoint {
   epoch_day: number;
   count: number;
+  error?: number; // HyperLogLog estimates only: about 95% within count ± error
+}
+
+// Progress of the WASM IndexBuilder / MetricsStream fed in batches
+export interface StreamProgress {
+  batches: number;
+  events_processed: number;
+  events_rejected: number; // dropped by validation
+  users: number; // content authors (index) or active eligible users (metrics)
+}
+
+// Checks applied to fetched events; omitted fields take the defaults
+export interface ValidationPolicy {
+  verify_ids?: boolean; // default true
+  verify_signatures?: boolean; // default false (slow)
+  max_clock_skew?: number | null; // seconds ahead of now, default 900; null disables
+  min_created_at?: number; // default 1604188800 (2020-11-01); 0 disables
+}
+
+export type RejectReason =
+  | 'pre_nostr_timestamp'
+  | 'future_timestamp'
+  | 'malformed'
+  | 'invalid_id'
+  | 'invalid_signature';
+
+export interface ValidationReport {
+  checked: number;
+  accepted: number;
+  rejected: Partial<Record<RejectReason, number>>;
 }
 
 export type MetricType = 'dau' | 'wau' | 'mau' | 'yau';
@@ -4,7 +4,10 @@ import type {
   LanguageIndexOptions,
   LanguageIndexResult,
   MetricsOptions,
-  MetricDataPoint
+  MetricDataPoint,
+  StreamProgress,
+  ValidationPolicy,
+  ValidationReport
 } from '../types';
 
 let wasmModule: any = null;
@@ -209,14 +212,16 @@ async function fetchEventsFromRelay(
   }
 }
 
-// Fetch events in chunks
+// Fetch events in chunks. With onChunk, each chunk's new events are handed
+// over instead of being collected, and an empty array is returned.
 async function fetchEventsChunked(
   relays: string[],
   since: number,
   until: number,
   kinds?: number[],
   chunkSizeDays: number = 1,
-  onRelayProgress?: (relay: string, progress: number, status: string, fetched: number) => void
+  onRelayProgress?: (relay: string, progress: number, status: string, fetched: number) => void,
+  onChunk?: (events: any[]) => void
 ): Promise<any[]> {
   const allEvents: any[] = [];
   const seenIds = new Set<string>();
@@ -260,6 +265,7 @@ async function fetchEventsChunked(
     );
     const results = await Promise.allSettled(promises);
     
+    const chunkEvents: any[] = onChunk ? [] : allEvents;
     let chunkNewEvents = 0;
     for (let i = 0; i < results.length; i++) {
       const result = results[i];
@@ -269,7 +275,7 @@ async function fetchEventsChunked(
         for (const event of result.value) {
           if (!seenIds.has(event.id)) {
             seenIds.add(event.id);
-            allEvents.push(event);
+            chunkEvents.push(event);
             relayNewEvents++;
             chunkNewEvents++;
           }
@@ -284,6 +290,7 @@ async function fetchEventsChunked(
       }
     }
     
+    onChunk?.(chunkEvents);
     processedPeriod += currentChunkSize;
     const overallProgress = Math.round((processedPeriod / totalPeriod) * 100);
     
@@ -293,7 +300,7 @@ async function fetchEventsChunked(
       onRelayProgress?.(relay, overallProgress, 'fetching', fetched);
     });
     
-    console.log(`Fetched chunk ${currentSince}-${currentUntil}: ${allEvents.length} events total (+${chunkNewEvents} new) [${overallProgress}%]`);
+    console.log(`Fetched chunk ${currentSince}-${currentUntil}: ${seenIds.size} events total (+${chunkNewEvents} new) [${overallProgress}%]`);
   }
   
   // Mark all relays as completed
@@ -308,7 +315,8 @@ async function fetchEventsChunked(
 export const buildLanguageIndex = async (
   relays: string[],
   options: LanguageIndexOptions,
-  onRelayProgress?: (relay: string, progress: number, status: string, fetched: number) => void
+  onRelayProgress?: (relay: string, progress: number, status: string, fetched: number) => void,
+  validation?: ValidationPolicy
 ): Promise<{ result: LanguageIndexResult; userLanguages: Record<string, Record<string, number>>; events: any[] }> => {
   if (!wasmModule) {
     await initWasm();
@@ -321,7 +329,7 @@ export const buildLanguageIndex = async (
     relays,
     options.since,
     options.until,
-    [1, 42], // kind:1 (notes) and kind:42 (channel messages) for language detection
+    [0, 1, 42, 1985], // kind:0 (profiles), kind:1 (notes), kind:42 (channel messages) and kind:1985 (NIP-32 labels)
     1, // 1 day chunks
     onRelayProgress
   );
@@ -329,9 +337,17 @@ export const buildLanguageIndex = async (
   console.log(`Fetched ${events.length} events for language detection`);
   
   // Limit events if maxEvents is specified
-  const eventsToProcess = options.maxEvents 
+  let eventsToProcess = options.maxEvents 
     ? events.slice(0, options.maxEvents)
     : events;
+
+  // Drop invalid events here rather than in WASM, so the returned events
+  // (reused for metrics) are the validated ones
+  if (validation) {
+    const validated = await validateEvents(eventsToProcess, validation);
+    console.log('Validation:', validated.report);
+    eventsToProcess = validated.events;
+  }
   
   // Process events with WASM
   const confThresh = options.confThresh ?? 0.5;
@@ -340,7 +356,9 @@ export const buildLanguageIndex = async (
   const output = wasmModule.process_events_for_language_index(
     eventsToProcess,
     confThresh,
-    maxLangsPerUser
+    maxLangsPerUser,
+    options.minPosts,
+    options.minShare
   );
   
   // Return events with detected language
@@ -350,10 +368,158 @@ export const buildLanguageIndex = async (
   };
 };
 
+// Like buildLanguageIndex, but each fetched chunk is pushed into a WASM
+// IndexBuilder and dropped, so the events are never held all at once
+export const buildLanguageIndexStreaming = async (
+  relays: string[],
+  options: LanguageIndexOptions,
+  onRelayProgress?: (relay: string, progress: number, status: string, fetched: number) => void,
+  onBuildProgress?: (progress: StreamProgress) => void,
+  validation?: ValidationPolicy
+): Promise<{ result: LanguageIndexResult; userLanguages: Record<string, Record<string, number>> }> => {
+  if (!wasmModule) {
+    await initWasm();
+  }
+
+  console.log('Building language index in batches...');
+
+  const builder = new wasmModule.IndexBuilder(
+    options.confThresh ?? 0.5,
+    options.maxLangsPerUser ?? 5,
+    options.minPosts,
+    options.minShare,
+    validation
+  );
+  let remaining = options.maxEvents ?? Infinity;
+  try {
+    await fetchEventsChunked(
+      relays,
+      options.since,
+      options.until,
+      [0, 1, 42, 1985], // kind:0 (profiles), kind:1 (notes), kind:42 (channel messages) and kind:1985 (NIP-32 labels)
+      1, // 1 day chunks
+      onRelayProgress,
+      (events) => {
+        if (remaining <= 0) {
+          return;
+        }
+        const batch = events.length > remaining ? events.slice(0, remaining) : events;
+        remaining -= batch.length;
+        builder.push_batch(batch);
+        onBuildProgress?.(builder.progress());
+      }
+    );
+  } catch (error) {
+    builder.free();
+    throw error;
+  }
+
+  const report = builder.validation_report();
+  if (report) {
+    console.log('Validation:', report);
+  }
+
+  // finish() consumes the builder
+  return builder.finish();
+};
+
+// Like computeMetrics, but each fetched chunk is pushed into a WASM
+// MetricsStream and dropped. Takes the full index (with evidence) so period
+// eligibility works; returns the series of every group.
+export const computeMetricsStreaming = async (
+  relays: string[],
+  index: { result: LanguageIndexResult; userLanguages: Record<string, Record<string, number>> },
+  options: MetricsOptions,
+  groupBy: 'all' | 'language' | 'kind' | 'client' = 'language',
+  onBuildProgress?: (progress: StreamProgress) => void,
+  validation?: ValidationPolicy
+): Promise<Record<string, MetricDataPoint[]>> => {
+  if (!wasmModule) {
+    await initWasm();
+  }
+
+  console.log('Computing metrics in batches...');
+
+  const stream = new wasmModule.MetricsStream(index, options, groupBy, validation);
+  try {
+    await fetchEventsChunked(
+      relays,
+      options.since,
+      options.until,
+      undefined, // All kinds
+      1, // 1 day chunks for activity
+      undefined,
+      (events) => {
+        stream.push_batch(events);
+        onBuildProgress?.(stream.progress());
+      }
+    );
+  } catch (error) {
+    stream.free();
+    throw error;
+  }
+
+  const report = stream.validation_report();
+  if (report) {
+    console.log('Validation:', report);
+  }
+
+  const results = stream.finish();
+  if (results instanceof Map) {
+    return Object.fromEntries(results);
+  }
+  return results;
+};
+
+// Drop events with a wrong id or signature, or with created_at in the future
+// or before Nostr, and report the rejections by reason
+export const validateEvents = async (
+  events: any[],
+  policy: ValidationPolicy = {}
+): Promise<{ events: any[]; report: ValidationReport }> => {
+  if (!wasmModule) {
+    await initWasm();
+  }
+
+  const output = wasmModule.validate_event_batch(events, policy);
+  const rejected = output.report.rejected;
+  return {
+    events: output.events,
+    report: {
+      ...output.report,
+      rejected: rejected instanceof Map ? Object.fromEntries(rejected) : rejected
+    }
+  };
+};
+
+// Compact binary form of a language index (as returned by buildLanguageIndex)
+// for storage; without evidence it only supports lifetime eligibility
+export const encodeLanguageIndex = async (
+  index: { result: LanguageIndexResult; userLanguages: Record<string, Record<string, number>> },
+  includeEvidence: boolean = true
+): Promise<Uint8Array> => {
+  if (!wasmModule) {
+    await initWasm();
+  }
+
+  return wasmModule.encode_language_index(index, includeEvidence);
+};
+
+export const decodeLanguageIndex = async (
+  bytes: Uint8Array
+): Promise<{ result: LanguageIndexResult; userLanguages: Record<string, Record<string, number>> }> => {
+  if (!wasmModule) {
+    await initWasm();
+  }
+
+  return wasmModule.decode_language_index(bytes);
+};
+
 export const computeMetrics = async (
   relays: string[],
   userLanguages: Record<string, Record<string, number>>,
-  options: MetricsOptions
+  options: MetricsOptions,
+  validation?: ValidationPolicy
 ): Promise<MetricDataPoint[]> => {
   if (!wasmModule) {
     await initWasm();
@@ -385,7 +551,9 @@ export const computeMetrics = async (
     options.languages,
     BigInt(options.since),
     BigInt(options.until),
-    options.window_days
+    options.window_days,
+    options.zone ?? 'UTC',
+    validation
   );
   
   return results;
@@ -394,7 +562,8 @@ export const computeMetrics = async (
 export const computeMetricsByLanguage = async (
   events: any[], // Accept events as parameter instead of fetching
   userLanguages: Record<string, Record<string, number>>,
-  options: MetricsOptions
+  options: MetricsOptions,
+  validation?: ValidationPolicy
 ): Promise<Record<string, MetricDataPoint[]>> => {
   if (!wasmModule) {
     await initWasm();
@@ -425,7 +594,9 @@ export const computeMetricsByLanguage = async (
     targetLanguages,
     BigInt(options.since),
     BigInt(options.until),
-    options.window_days
+    options.window_days,
+    options.zone ?? 'UTC',
+    validation
   );
   
   // Convert Map to plain object if necessary